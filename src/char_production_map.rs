@@ -116,6 +116,8 @@ fn _char_access_map() -> HashMap<char, SinkKey> {
   res.insert('?',  SinkKey { sh: true, k: SLASH });
   res.insert('|',  SinkKey { sh: true, k: BACKSLASH });
   
+  res.insert(' ',  SinkKey { sh: false, k: SPACE });
+  
   res
 }
 
@@ -0,0 +1,275 @@
+// vim: shiftwidth=2
+
+use crate::keys::{Layout, KeyCode};
+use crate::remapping_loop::LayoutReloader;
+use lazy_static::lazy_static;
+use nix::Error;
+use nix::errno::Errno::EAGAIN;
+use nix::fcntl::OFlag;
+use nix::unistd::{close, pipe2, read, write};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::io::RawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::spawn;
+use std::time::Duration;
+
+// Requests for the remapping loops, from signals or the control socket.
+// Each loop has an `Inbox`: a queue, plus a pipe to wake up its `poll`.
+
+#[derive(Debug)]
+pub enum Command {
+  // Let go of everything and stop
+  Terminate,
+  // Load the layout file again
+  Reload,
+  // A new layout, and the file to reload it from from now on
+  SetLayout(Layout, Option<LayoutReloader>),
+  SetPaused(bool),
+  // Switch to one of the layout's named layouts
+  SelectLayout(String),
+  Status(Sender<DeviceStatus>)
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceStatus {
+  pub name: String,
+  pub paused: bool,
+  // The named layout in use, if the layout has several
+  pub layout: Option<String>,
+  pub held_keys: Vec<KeyCode>
+}
+
+// Which layout to switch to
+pub enum LayoutChoice {
+  Default(String),
+  File(String)
+}
+
+pub type LayoutLoader = Arc<dyn Fn(&LayoutChoice) -> Result<(Layout, Option<LayoutReloader>), String> + Send + Sync>;
+
+struct InboxHandle {
+  write_fd: RawFd,
+  commands: Arc<Mutex<VecDeque<Command>>>
+}
+
+lazy_static! {
+  static ref INBOXES: Mutex<Vec<InboxHandle>> = Mutex::new(Vec::new());
+  static ref ACTIVE_LAYOUT: Mutex<String> = Mutex::new(String::new());
+  static ref SOCKET_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+// Sends a command to every remapping loop. Returns how many there are.
+pub fn broadcast(make: impl Fn() -> Command) -> usize {
+  let inboxes = INBOXES.lock().unwrap();
+  for inbox in inboxes.iter() {
+    inbox.commands.lock().unwrap().push_back(make());
+    let _ = write(inbox.write_fd, &[0]);
+  }
+  inboxes.len()
+}
+
+pub fn open_inboxes() -> usize {
+  INBOXES.lock().unwrap().len()
+}
+
+pub struct Inbox {
+  pub fd: RawFd,
+  write_fd: RawFd,
+  commands: Arc<Mutex<VecDeque<Command>>>
+}
+
+impl Inbox {
+  pub fn open() -> Result<Inbox, Error> {
+    let (fd, write_fd) = pipe2(OFlag::O_NONBLOCK | OFlag::O_CLOEXEC)?;
+    let commands = Arc::new(Mutex::new(VecDeque::new()));
+    INBOXES.lock().unwrap().push(InboxHandle { write_fd, commands: Arc::clone(&commands) });
+    Ok(Inbox { fd, write_fd, commands })
+  }
+
+  // Fails with EAGAIN when there is nothing to do
+  pub fn next(self: &mut Inbox) -> Result<Command, Error> {
+    let mut buf = [0u8; 64];
+    loop {
+      match read(self.fd, &mut buf) {
+        Ok(0) | Err(Error::Sys(EAGAIN)) => break,
+        Ok(_) => (),
+        Err(e) => return Err(e)
+      }
+    }
+
+    match self.commands.lock().unwrap().pop_front() {
+      Some(command) => Ok(command),
+      None => Err(Error::Sys(EAGAIN))
+    }
+  }
+}
+
+impl Drop for Inbox {
+  fn drop(&mut self) {
+    INBOXES.lock().unwrap().retain(|inbox| inbox.write_fd != self.write_fd);
+    let _ = close(self.fd);
+    let _ = close(self.write_fd);
+  }
+}
+
+// For `status`, e.g. "file /etc/totalmapper.json"
+pub fn set_active_layout(description: &str) {
+  *ACTIVE_LAYOUT.lock().unwrap() = description.to_owned();
+}
+
+impl LayoutChoice {
+  fn description(&self) -> String {
+    match self {
+      LayoutChoice::Default(name) => format!("default {}", name),
+      LayoutChoice::File(path) => format!("file {}", path)
+    }
+  }
+}
+
+// Serves `totalmapper ctl` on a thread of its own. Each connection sends
+// one command line and gets back text to show the user.
+pub fn serve(path: &Path, load: LayoutLoader, verbose: bool) -> Result<(), String> {
+  // Left over from a previous run
+  let _ = std::fs::remove_file(path);
+  let listener = UnixListener::bind(path).map_err(|e| format!("Failed to listen on {:?}: {}", path, e))?;
+  *SOCKET_PATH.lock().unwrap() = Some(path.to_path_buf());
+
+  spawn(move || {
+    for stream in listener.incoming() {
+      match stream {
+        Err(e) => {
+          if verbose { eprintln!("Control socket: {}", e); }
+        },
+        Ok(stream) => {
+          if let Err(e) = handle_client(stream, &load) {
+            if verbose { eprintln!("Control socket: {}", e); }
+          }
+        }
+      }
+    }
+  });
+
+  Ok(())
+}
+
+pub fn remove_socket() {
+  if let Some(path) = &*SOCKET_PATH.lock().unwrap() {
+    let _ = std::fs::remove_file(path);
+  }
+}
+
+fn handle_client(stream: UnixStream, load: &LayoutLoader) -> Result<(), std::io::Error> {
+  let mut line = String::new();
+  BufReader::new(&stream).read_line(&mut line)?;
+  let reply = run_command(line.trim(), load);
+  (&stream).write_all(reply.as_bytes())
+}
+
+fn run_command(line: &str, load: &LayoutLoader) -> String {
+  let words: Vec<&str> = line.splitn(3, ' ').collect();
+  match words.as_slice() {
+    ["status"] => status_text(),
+    ["pause"] => {
+      broadcast(|| Command::SetPaused(true));
+      "Paused.\n".to_owned()
+    },
+    ["resume"] => {
+      broadcast(|| Command::SetPaused(false));
+      "Resumed.\n".to_owned()
+    },
+    ["reload"] => {
+      broadcast(|| Command::Reload);
+      "Reloading the layout file.\n".to_owned()
+    },
+    ["layout", kind, name] => {
+      let choice = match *kind {
+        "default" => LayoutChoice::Default(name.to_string()),
+        "file" => LayoutChoice::File(name.to_string()),
+        _ => return format!("Error: unknown kind of layout: {}\n", kind)
+      };
+      match load(&choice) {
+        Err(msg) => format!("Error: {}\n", msg),
+        Ok((layout, reloader)) => {
+          set_active_layout(&choice.description());
+          broadcast(|| Command::SetLayout(layout.clone(), reloader.clone()));
+          format!("Switched to {}.\n", choice.description())
+        }
+      }
+    },
+    _ => format!("Error: unknown command: {}\n", line)
+  }
+}
+
+fn status_text() -> String {
+  let (sender, receiver) = channel();
+  let count = broadcast(|| Command::Status(sender.clone()));
+
+  let mut devices: Vec<DeviceStatus> = Vec::new();
+  for _ in 0 .. count {
+    match receiver.recv_timeout(Duration::from_millis(1000)) {
+      Ok(status) => devices.push(status),
+      Err(_) => break
+    }
+  }
+  devices.sort_by(|a, b| a.name.cmp(&b.name));
+
+  let mut text = format!("Layout: {}\n", ACTIVE_LAYOUT.lock().unwrap());
+  for device in devices {
+    let held: Vec<String> = device.held_keys.iter().map(|k| format!("{:?}", k)).collect();
+    let layout = match &device.layout {
+      None => String::new(),
+      Some(name) => format!(", layout {}", name)
+    };
+    text.push_str(&format!("{}: {}{}, holding [{}]\n",
+      device.name,
+      if device.paused { "paused" } else { "remapping" },
+      layout,
+      held.join(", ")
+    ));
+  }
+  text
+}
+
+// The client side, for `totalmapper ctl`
+pub fn send_command(path: &Path, line: &str) -> Result<String, String> {
+  let mut stream = UnixStream::connect(path).map_err(|e| format!("Failed to connect to {:?}: {}", path, e))?;
+  stream.write_all(format!("{}\n", line).as_bytes()).map_err(|e| format!("Failed to send to {:?}: {}", path, e))?;
+  let mut reply = String::new();
+  stream.read_to_string(&mut reply).map_err(|e| format!("Failed to read from {:?}: {}", path, e))?;
+  Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::keys::Mapping;
+  use KeyCode::*;
+
+  #[test]
+  fn test_control_commands() {
+    let load: LayoutLoader = Arc::new(|choice| match choice {
+      LayoutChoice::Default(name) if name == "swap" => Ok((Layout {
+        mappings: vec![Mapping { from: vec![A], to: vec![B], ..Default::default() }],
+        ..Default::default()
+      }, None)),
+      _ => Err("no such layout".to_owned())
+    });
+
+    let mut inbox = Inbox::open().unwrap();
+    assert!(matches!(inbox.next(), Err(Error::Sys(EAGAIN))));
+
+    assert_eq!("Paused.\n", run_command("pause", &load));
+    assert!(matches!(inbox.next(), Ok(Command::SetPaused(true))));
+
+    assert_eq!("Switched to default swap.\n", run_command("layout default swap", &load));
+    assert!(matches!(inbox.next(), Ok(Command::SetLayout(_, None))));
+    assert_eq!("default swap", *ACTIVE_LAYOUT.lock().unwrap());
+    assert_eq!("Error: no such layout\n", run_command("layout file /nowhere.json", &load));
+    assert_eq!("Error: unknown command: dance\n", run_command("dance", &load));
+    assert!(matches!(inbox.next(), Err(Error::Sys(EAGAIN))));
+  }
+}
@@ -0,0 +1,124 @@
+// vim: shiftwidth=2
+
+use crate::keys::{Debounce, Event, KeyCode};
+use Event::{Pressed, Released};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+use wildmatch::WildMatch;
+
+// Sits in front of the mapper and drops presses that come too soon after
+// a release of the same key, along with the release that goes with them.
+pub struct Debouncer {
+  delay_ms: i32,
+  key_delays_ms: BTreeMap<KeyCode, i32>,
+  last_released: HashMap<KeyCode, Instant>,
+  dropped: HashSet<KeyCode>
+}
+
+impl Debouncer {
+  pub fn disabled() -> Debouncer {
+    Debouncer::for_rule(0, BTreeMap::new())
+  }
+
+  // The first rule whose pattern matches the device name applies.
+  pub fn for_device(rules: &[Debounce], device_name: &str) -> Debouncer {
+    let rule = rules.iter().find(|r| match &r.device {
+      None => true,
+      Some(pattern) => WildMatch::new(pattern).matches(device_name)
+    });
+
+    match rule {
+      None => Debouncer::disabled(),
+      Some(rule) => Debouncer::for_rule(rule.delay_ms, rule.keys.clone())
+    }
+  }
+
+  fn for_rule(delay_ms: i32, key_delays_ms: BTreeMap<KeyCode, i32>) -> Debouncer {
+    Debouncer {
+      delay_ms,
+      key_delays_ms,
+      last_released: HashMap::new(),
+      dropped: HashSet::new()
+    }
+  }
+
+  pub fn is_disabled(&self) -> bool {
+    self.delay_ms == 0 && self.key_delays_ms.values().all(|d| *d == 0)
+  }
+
+  // Returns whether the event should be passed on
+  pub fn accept(&mut self, ev: &Event, now: Instant) -> bool {
+    match ev {
+      Pressed(k) => {
+        let delay_ms = *self.key_delays_ms.get(k).unwrap_or(&self.delay_ms);
+        let chatter = match self.last_released.get(k) {
+          None => false,
+          Some(released) => now < *released + Duration::from_millis(delay_ms as u64)
+        };
+        if chatter {
+          self.dropped.insert(*k);
+        }
+        !chatter
+      },
+      Released(k) => {
+        if self.dropped.remove(k) {
+          false
+        }
+        else {
+          self.last_released.insert(*k, now);
+          true
+        }
+      },
+      _ => true
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use KeyCode::*;
+
+  fn ms(start: Instant, n: u64) -> Instant {
+    start + Duration::from_millis(n)
+  }
+
+  #[test]
+  fn test_debounce_drops_chatter() {
+    let rules = vec![Debounce { device: None, delay_ms: 30, keys: BTreeMap::new() }];
+    let mut d = Debouncer::for_device(&rules, "Some Keyboard");
+    let t = Instant::now();
+
+    assert!(d.accept(&Pressed(A), ms(t, 0)));
+    assert!(d.accept(&Released(A), ms(t, 50)));
+    assert!(!d.accept(&Pressed(A), ms(t, 60)));
+    assert!(!d.accept(&Released(A), ms(t, 65)));
+    // Other keys are unaffected
+    assert!(d.accept(&Pressed(B), ms(t, 66)));
+    assert!(d.accept(&Released(B), ms(t, 70)));
+    // Measured from the last accepted release
+    assert!(d.accept(&Pressed(A), ms(t, 80)));
+    assert!(d.accept(&Released(A), ms(t, 120)));
+  }
+
+  #[test]
+  fn test_debounce_key_overrides_and_devices() {
+    let rules = vec![
+      Debounce { device: Some("Old*".to_owned()), delay_ms: 30, keys: vec![(E, 60), (SPACE, 0)].into_iter().collect() },
+      Debounce { device: None, delay_ms: 10, keys: BTreeMap::new() },
+    ];
+    let mut d = Debouncer::for_device(&rules, "Old Cherry Keyboard");
+    let t = Instant::now();
+
+    assert!(d.accept(&Released(E), ms(t, 0)));
+    assert!(!d.accept(&Pressed(E), ms(t, 40)));
+    assert!(d.accept(&Released(SPACE), ms(t, 0)));
+    assert!(d.accept(&Pressed(SPACE), ms(t, 1)));
+
+    let mut d = Debouncer::for_device(&rules, "New Keyboard");
+    assert!(d.accept(&Released(E), ms(t, 0)));
+    assert!(d.accept(&Pressed(E), ms(t, 20)));
+
+    assert!(Debouncer::for_device(&rules[..1], "New Keyboard").is_disabled());
+  }
+}
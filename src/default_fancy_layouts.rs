@@ -59,16 +59,16 @@ pub static EASY_SYMBOLS_TAB_FOR_MOVEMENT: &'static str = r#"{
     { "from": ["@symbol", {"row": "A"}], "to": {"letters": "   = &)(/_$"} },
     { "from": ["@symbol", {"row": "Z"}], "to": {"letters": "     !+#"} },
     { "from": ["@symbol", "Q"], "to": "ESC" },
-    { "from": ["@movement", "J"], "to": "LEFT" }, 
-    { "from": ["@movement", "I"], "to": "UP" }, 
-    { "from": ["@movement", "K"], "to": "DOWN" }, 
-    { "from": ["@movement", "L"], "to": "RIGHT" }, 
-    { "from": ["@movement", "H"], "to": "HOME" }, 
-    { "from": ["@movement", "SEMICOLON"], "to": "END" }, 
-    { "from": ["@movement", "U"], "to": "PAGEUP" }, 
-    { "from": ["@movement", "M"], "to": "PAGEDOWN" }, 
-    { "from": ["@movement", "N"], "to": ["LEFTCTRL", "LEFT"] }, 
-    { "from": ["@movement", "COMMA"], "to": ["LEFTCTRL", "RIGHT"] }
+    { "from": ["@movement", "J"], "to": "LEFT", "priority": 1 },
+    { "from": ["@movement", "I"], "to": "UP", "priority": 1 },
+    { "from": ["@movement", "K"], "to": "DOWN", "priority": 1 },
+    { "from": ["@movement", "L"], "to": "RIGHT", "priority": 1 },
+    { "from": ["@movement", "H"], "to": "HOME", "priority": 1 },
+    { "from": ["@movement", "SEMICOLON"], "to": "END", "priority": 1 },
+    { "from": ["@movement", "U"], "to": "PAGEUP", "priority": 1 },
+    { "from": ["@movement", "M"], "to": "PAGEDOWN", "priority": 1 },
+    { "from": ["@movement", "N"], "to": ["LEFTCTRL", "LEFT"], "priority": 1 },
+    { "from": ["@movement", "COMMA"], "to": ["LEFTCTRL", "RIGHT"], "priority": 1 }
   ]
 }"#;
 
@@ -87,29 +87,29 @@ pub static SUPER_DVORAK: &'static str = r#"{
     { "from": ["@shift", {"row": "Q"}], "to": {"letters": ":<>PYFGCRL?^"}, "absorbing": "@shift", "repeat": "Disabled" },
     { "from": ["@shift", {"row": "A"}], "to": {"letters": "AOEUIDHTNS@"}, "absorbing": "@shift", "repeat": "Disabled" },
     { "from": ["@shift", {"row": "Z"}], "to": {"letters": "\"QJKXBMWVZ"}, "absorbing": "@shift", "repeat": "Disabled" },
-    { "from": ["@symbol", {"row": "Q"}], "to": {"letters": " {}% \\*][|~"}, "repeat": "Disabled" },
-    { "from": ["@symbol", {"row": "A"}], "to": {"letters": "   = &)(/_$"}, "repeat": "Disabled" },
-    { "from": ["@symbol", {"row": "Z"}], "to": {"letters": "     !+#"}, "repeat": "Disabled" },
-    { "from": ["@shift", "@symbol", {"row": "Q"}], "to": ["RIGHTALT", {"letters": ";,.pyfgcrl~@"}], "absorbing": "@shift", "repeat": "Disabled" },
-    { "from": ["@shift", "@symbol", {"row": "A"}], "to": ["RIGHTALT", {"letters": "aoeuidhtns-"}], "absorbing": "@shift", "repeat": "Disabled" },
-    { "from": ["@shift", "@symbol", {"row": "Z"}], "to": ["RIGHTALT", {"letters": "'qjkxbmwvz"}], "absorbing": "@shift", "repeat": "Disabled" },
+    { "from": ["@symbol", {"row": "Q"}], "to": {"letters": " {}% \\*][|~"}, "repeat": "Disabled", "priority": 1 },
+    { "from": ["@symbol", {"row": "A"}], "to": {"letters": "   = &)(/_$"}, "repeat": "Disabled", "priority": 1 },
+    { "from": ["@symbol", {"row": "Z"}], "to": {"letters": "     !+#"}, "repeat": "Disabled", "priority": 1 },
+    { "from": ["@shift", "@symbol", {"row": "Q"}], "to": ["RIGHTALT", {"letters": ";,.pyfgcrl~@"}], "absorbing": "@shift", "repeat": "Disabled", "priority": 3 },
+    { "from": ["@shift", "@symbol", {"row": "A"}], "to": ["RIGHTALT", {"letters": "aoeuidhtns-"}], "absorbing": "@shift", "repeat": "Disabled", "priority": 3 },
+    { "from": ["@shift", "@symbol", {"row": "Z"}], "to": ["RIGHTALT", {"letters": "'qjkxbmwvz"}], "absorbing": "@shift", "repeat": "Disabled", "priority": 3 },
     { "from": ["@shift", "@symbol", "SPACE"], "to": ["RIGHTALT", "N"], "repeat": "Disabled" },
-    { "from": ["@symbol", "Q"], "to": "ESC", "absorbing": "@symbol", "repeat": "Disabled" },
-    { "from": ["@movement", "J"], "to": "LEFT" },
-    { "from": ["@movement", "I"], "to": "UP" },
-    { "from": ["@movement", "K"], "to": "DOWN" },
-    { "from": ["@movement", "L"], "to": "RIGHT" },
-    { "from": ["@movement", "H"], "to": "HOME" },
-    { "from": ["@movement", "SEMICOLON"], "to": "END" },
-    { "from": ["@movement", "U"], "to": "PAGEUP" },
-    { "from": ["@movement", "M"], "to": "PAGEDOWN" },
-    { "from": ["@movement", "N"], "to": ["LEFTCTRL", "LEFT"] },
-    { "from": ["@movement", "COMMA"], "to": ["LEFTCTRL", "RIGHT"] },
+    { "from": ["@symbol", "Q"], "to": "ESC", "absorbing": "@symbol", "repeat": "Disabled", "priority": 1 },
+    { "from": ["@movement", "J"], "to": "LEFT", "priority": 2 },
+    { "from": ["@movement", "I"], "to": "UP", "priority": 2 },
+    { "from": ["@movement", "K"], "to": "DOWN", "priority": 2 },
+    { "from": ["@movement", "L"], "to": "RIGHT", "priority": 2 },
+    { "from": ["@movement", "H"], "to": "HOME", "priority": 2 },
+    { "from": ["@movement", "SEMICOLON"], "to": "END", "priority": 2 },
+    { "from": ["@movement", "U"], "to": "PAGEUP", "priority": 2 },
+    { "from": ["@movement", "M"], "to": "PAGEDOWN", "priority": 2 },
+    { "from": ["@movement", "N"], "to": ["LEFTCTRL", "LEFT"], "priority": 2 },
+    { "from": ["@movement", "COMMA"], "to": ["LEFTCTRL", "RIGHT"], "priority": 2 },
     { "from": "GRAVE", "to": "LEFTMETA" },
-    { "from": ["GRAVE", {"row": "1"}], "to": ["LEFTMETA", {"letters": "17531902468`"}], "repeat": "Disabled" },
-    { "from": ["GRAVE", {"row": "Q"}], "to": ["LEFTMETA", {"letters": ";,.pyfgcrl~@"}], "repeat": "Disabled" },
-    { "from": ["GRAVE", {"row": "A"}], "to": ["LEFTMETA", {"letters": "aoeuidhtns-"}], "repeat": "Disabled" },
-    { "from": ["GRAVE", {"row": "Z"}], "to": ["LEFTMETA", {"letters": "'qjkxbmwvz"}], "repeat": "Disabled" },
+    { "from": ["GRAVE", {"row": "1"}], "to": ["LEFTMETA", {"letters": "17531902468`"}], "repeat": "Disabled", "priority": 3 },
+    { "from": ["GRAVE", {"row": "Q"}], "to": ["LEFTMETA", {"letters": ";,.pyfgcrl~@"}], "repeat": "Disabled", "priority": 3 },
+    { "from": ["GRAVE", {"row": "A"}], "to": ["LEFTMETA", {"letters": "aoeuidhtns-"}], "repeat": "Disabled", "priority": 3 },
+    { "from": ["GRAVE", {"row": "Z"}], "to": ["LEFTMETA", {"letters": "'qjkxbmwvz"}], "repeat": "Disabled", "priority": 3 },
     { "from": "J", "repeat": {"Special": {"keys": "F21", "delay_ms": 180, "interval_ms": 30}} },
     { "from": "I", "repeat": {"Special": {"keys": "F20", "delay_ms": 180, "interval_ms": 30}} },
     { "from": "K", "repeat": {"Special": {"keys": ["LEFTCTRL", "F20"], "delay_ms": 180, "interval_ms": 30}} },
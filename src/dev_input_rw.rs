@@ -10,13 +10,13 @@ use nix::unistd::{read, write};
 use nix::Error;
 use libc::input_event;
 use std::mem::size_of;
-use uinput_sys::{ui_set_evbit, EV_SYN, EV_KEY, EV_MSC, ui_dev_create, ui_set_keybit};
+use uinput_sys::{ui_set_evbit, EV_SYN, EV_KEY, EV_MSC, EV_REL, EV_LED, ui_dev_create, ui_set_keybit, ui_set_relbit, ui_set_ledbit, REL_X, REL_Y, REL_WHEEL, REL_HWHEEL, LED_NUML, LED_CAPSL, LED_SCROLLL};
 use crate::struct_ser::StructSerializer;
 use std::os::unix::io::RawFd;
-use crate::keys::Event;
+use crate::keys::{Event, Axis, Led};
 use num_traits::FromPrimitive;
 use std::path::Path;
-use ioctls::{eviocgkey, eviocgrab};
+use ioctls::{eviocgkey, eviocgname, eviocgrab};
 
 pub struct DevInputReader {
   pub fd: RawFd
@@ -78,6 +78,28 @@ impl DevInputReader {
     
     Ok(DevInputReader { fd })
   }
+  
+  // Gives the keyboard back to other readers, such as the console
+  pub fn ungrab(self: &DevInputReader) -> Result<(), Error> {
+    unsafe {
+      if eviocgrab(self.fd, &*Box::new(0)) == -1 {
+        return Err(Error::last());
+      }
+    }
+    Ok(())
+  }
+  
+  // The same name that `list_keyboards` shows
+  pub fn name(self: &DevInputReader) -> Result<String, Error> {
+    let mut buf = vec![0u8; 256];
+    unsafe {
+      if eviocgname(self.fd, buf.as_mut_ptr(), buf.len()) == -1 {
+        return Err(Error::last());
+      }
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+  }
 }
 
 fn do_exclusion_loop(fd: RawFd) -> Result<(), Error> {
@@ -139,17 +161,33 @@ fn wait_for_any_activity(fd: RawFd) -> Result<(), Error> {
 }
 
 pub struct DevInputWriter {
-  fd: RawFd
+  pub fd: RawFd
 }
 
 impl DevInputWriter {
-  pub fn open() -> Result<DevInputWriter, Error> {
-    let fdo = open("/dev/uinput", OFlag::O_WRONLY | OFlag::O_NONBLOCK, Mode::empty())?;
+  // `pointer` adds the relative axes that mouse keys need
+  pub fn open(pointer: bool) -> Result<DevInputWriter, Error> {
+    // Readable too, so we hear when the lock LEDs are set
+    let fdo = open("/dev/uinput", OFlag::O_RDWR | OFlag::O_NONBLOCK, Mode::empty())?;
 
     unsafe {
       ui_set_evbit(fdo, EV_SYN);
       ui_set_evbit(fdo, EV_KEY);
       ui_set_evbit(fdo, EV_MSC);
+      ui_set_evbit(fdo, EV_LED);
+    }
+    
+    for led in &[LED_NUML, LED_CAPSL, LED_SCROLLL] {
+      unsafe { ui_set_ledbit(fdo, *led); }
+    }
+    
+    // Needed for mouse keys. Buttons are already included in the key
+    // bits below.
+    if pointer {
+      unsafe { ui_set_evbit(fdo, EV_REL); }
+      for rel in &[REL_X, REL_Y, REL_WHEEL, REL_HWHEEL] {
+        unsafe { ui_set_relbit(fdo, *rel); }
+      }
     }
     
     // FYI for people considering adding more keys: be careful not to
@@ -186,37 +224,67 @@ impl DevInputWriter {
     Ok(DevInputWriter { fd: fdo })
   }
   
+  // The next change to a lock LED of the virtual keyboard
+  pub fn next_led(self: &mut DevInputWriter) -> Result<(Led, bool), Error> {
+    loop {
+      let size = size_of::<input_event>();
+      let mut buf: Vec<u8> = vec![0; size];
+      read(self.fd, &mut buf)?;
+      
+      let type_ = u16::from_ne_bytes([buf[16], buf[17]]);
+      let code = u16::from_ne_bytes([buf[18], buf[19]]);
+      let value = i32::from_ne_bytes([buf[20], buf[21], buf[22], buf[23]]);
+      
+      if type_ as i32 == EV_LED {
+        let led = match code as i32 {
+          LED_NUML => Led::Num,
+          LED_CAPSL => Led::Caps,
+          LED_SCROLLL => Led::Scroll,
+          _ => continue
+        };
+        return Ok((led, value != 0));
+      }
+    }
+  }
+  
   pub fn send(self: &mut DevInputWriter, evs: &Vec<Event>) -> Result<(), Error> {
     let mut input_event_data = StructSerializer {
       sink: Vec::new()
     };
     
-    let mut send_type_code_value = |type_, code, value| {
-      input_event_data.add_i64(0);
-      input_event_data.add_i64(0);
-      input_event_data.add_u16(type_);
-      input_event_data.add_u16(code);
-      input_event_data.add_i32(value);
-    };
+    fn add_type_code_value(data: &mut StructSerializer, type_: u16, code: u16, value: i32) {
+      data.add_i64(0);
+      data.add_i64(0);
+      data.add_u16(type_);
+      data.add_u16(code);
+      data.add_i32(value);
+    }
       
     for ev in evs {
-      let k = match ev {
-        Event::Pressed(k) => k,
-        Event::Released(k) => k,
-      };
-      
-      let value = match ev {
-        Event::Pressed(_) => 1,
-        Event::Released(_) => 0
-      };
-      
-      let code = (*k) as u16;
-      
-      send_type_code_value(1, code, value);
+      match ev {
+        Event::Pressed(k) => add_type_code_value(&mut input_event_data, 1, (*k) as u16, 1),
+        Event::Released(k) => add_type_code_value(&mut input_event_data, 1, (*k) as u16, 0),
+        Event::Moved { axis, delta } => {
+          let code = match axis {
+            Axis::X => REL_X,
+            Axis::Y => REL_Y,
+            Axis::Wheel => REL_WHEEL,
+            Axis::HWheel => REL_HWHEEL
+          };
+          add_type_code_value(&mut input_event_data, 2, code as u16, *delta);
+        },
+        Event::Sync { .. } => {
+          add_type_code_value(&mut input_event_data, 0, 0, 0);
+          write(self.fd, &input_event_data.sink)?;
+          input_event_data.sink.clear();
+        }
+      }
     }
-    send_type_code_value(0, 0, 0);
     
-    write(self.fd, &input_event_data.sink)?;
+    if !input_event_data.sink.is_empty() {
+      add_type_code_value(&mut input_event_data, 0, 0, 0);
+      write(self.fd, &input_event_data.sink)?;
+    }
     
     Ok(())
   }
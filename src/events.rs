@@ -4,6 +4,20 @@ use crate::key_codes::KeyCode;
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Event {
   Pressed(KeyCode),
-  Released(KeyCode)
+  Released(KeyCode),
+  // Only sent, never read: relative pointer motion or scrolling
+  Moved { axis: Axis, delta: i32 },
+  // Only sent, never read: ends the current SYN_REPORT frame, so each
+  // step of a macro is seen as a separate keystroke. The mapper turns
+  // `delay_ms` into a timer and holds back the rest until it fires.
+  Sync { delay_ms: i32 }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Axis {
+  X,
+  Y,
+  Wheel,
+  HWheel
 }
 
@@ -4,12 +4,30 @@ use std::fmt::Display;
 // vim: shiftwidth=2
  
 pub use crate::key_codes::KeyCode; 
+pub use crate::keys::LatchAction;
+pub use crate::keys::Action;
+pub use crate::keys::Led;
+pub use crate::keys::Mouse;
+pub use crate::keys::Debounce;
+pub use crate::unicode_input::UnicodeMethod;
 pub use crate::events::Event;
 pub use Event::Pressed;
 pub use Event::Released;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Layout {
+  // With `named_layouts`, these are shared by all of them
+  pub mappings: Vec<Mapping>,
+  pub debounce: Vec<Debounce>,
+  pub named_layouts: Vec<NamedLayout>,
+  pub switch_all_keyboards: bool,
+  // In place of the default emergency chord
+  pub emergency_chord: Option<Vec<KeyCode>>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedLayout {
+  pub name: String,
   pub mappings: Vec<Mapping>
 }
 
@@ -19,12 +37,24 @@ pub enum Mapping {
   Alias(AliasMapping),
   Row(RowMapping),
   RepeatOnlySingle(RepeatOnlySingleMapping),
+  Latch(LatchMapping),
+  Combo(ComboMapping),
+  Sequence(SequenceMapping),
+  Macro(MacroMapping),
+  Action(ActionMapping),
+  Mouse(MouseMapping),
+  Priority(PriorityMapping),
+  Conditional(ConditionalMapping),
+  ClearModifiers(ClearModifiersMapping),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AliasMapping {
   pub from: AliasFromKeys,
-  pub to: AliasToKeys
+  pub to: AliasToKeys,
+  pub tap: Option<Tap>,
+  pub one_shot: bool,
+  pub to_if_alone: Option<SingleToKeys>
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -32,7 +62,10 @@ pub struct SingleMapping {
   pub from: SingleFromKeys,
   pub to: SingleToKeys,
   pub repeat: SingleRepeat,
-  pub absorbing: Vec<Modifier>
+  pub absorbing: Vec<Modifier>,
+  pub tap: Option<Tap>,
+  pub one_shot: bool,
+  pub to_if_alone: Option<SingleToKeys>
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -41,6 +74,78 @@ pub struct RepeatOnlySingleMapping {
   pub repeat: SingleRepeat,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatchMapping {
+  pub from: SingleFromKeys,
+  pub action: LatchAction,
+  pub alias: String
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComboMapping {
+  pub keys: Vec<KeyCode>,
+  pub to: SingleToKeys,
+  pub window_ms: i32
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceMapping {
+  pub keys: Vec<KeyCode>,
+  pub to: SingleToKeys,
+  pub timeout_ms: i32
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroMapping {
+  pub from: SingleFromKeys,
+  pub steps: Vec<MacroStep>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionMapping {
+  pub from: SingleFromKeys,
+  pub action: Action
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MouseMapping {
+  pub from: SingleFromKeys,
+  pub mouse: Mouse
+}
+
+// Any other mapping that only applies while none of the `unless` keys
+// are held, if `exclusive`, while no keys outside its `from` are held,
+// and while all the `if_led` LEDs are on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionalMapping {
+  pub unless: Vec<Modifier>,
+  pub exclusive: bool,
+  pub if_led: Vec<Led>,
+  pub mapping: Box<Mapping>
+}
+
+// Any other mapping that releases held modifiers not in its output
+// while it is active
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClearModifiersMapping {
+  pub mapping: Box<Mapping>
+}
+
+// Any other mapping with an explicit `priority`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriorityMapping {
+  pub priority: i32,
+  pub mapping: Box<Mapping>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacroStep {
+  Tap(SingleToKeys),
+  Delay(i32),
+  Text(String),
+  Unicode { text: String, method: UnicodeMethod }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RowMapping {
   pub from: RowFromKeys,
@@ -120,6 +225,22 @@ pub struct RowToKeys {
   pub terminal: String
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tap {
+  pub keys: SingleToKeys,
+  pub tapping_term_ms: i32,
+  pub extra_taps: Vec<SingleToKeys>
+}
+
+pub const DEFAULT_TAPPING_TERM_MS: i32 = 200;
+
+pub const DEFAULT_COMBO_WINDOW_MS: i32 = 30;
+
+pub const DEFAULT_SEQUENCE_TIMEOUT_MS: i32 = 1000;
+
+// Largest `mouse_move` or `mouse_scroll` amount, per tick
+pub const MAX_MOUSE_AMOUNT: i32 = 10000;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum SingleTerminalToKey {
   Physical(KeyCode),
@@ -134,6 +255,9 @@ pub enum SingleRepeat {
     keys: SingleToKeys,
     delay_ms: i32,
     interval_ms: i32
+  },
+  AutoShift {
+    delay_ms: i32
   }
 }
 
@@ -145,6 +269,9 @@ pub enum RowRepeat {
     keys: RowToKeys,
     delay_ms: i32,
     interval_ms: i32
+  },
+  AutoShift {
+    delay_ms: i32
   }
 }
 
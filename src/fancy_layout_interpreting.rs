@@ -4,16 +4,59 @@ use crate::keys as s;
 use crate::fancy_keys as f;
 use crate::key_codes::KeyCode;
 use std::collections::HashMap;
+use crate::unicode_input::{unicode_macro_steps, DEFAULT_UNICODE_METHOD};
 
 pub fn convert(f: &f::Layout) -> Result<s::Layout, String> {
+  let mut named_layouts = Vec::new();
+  for named in &f.named_layouts {
+    let fms: Vec<f::Mapping> = f.mappings.iter().chain(&named.mappings).cloned().collect();
+    named_layouts.push(s::NamedLayout {
+      name: named.name.clone(),
+      mappings: convert_mappings(&fms).map_err(|e| format!("In layout {}: {}", named.name, e))?
+    });
+  }
+  
+  // The first named layout is the one to start with
+  let mappings = match named_layouts.first() {
+    None => convert_mappings(&f.mappings)?,
+    Some(first) => first.mappings.clone()
+  };
+  
+  check_layout_switches(&mappings, &named_layouts)?;
+  
+  Ok(s::Layout {
+    mappings,
+    debounce: f.debounce.clone(),
+    emergency_chord: f.emergency_chord.clone().unwrap_or_else(s::default_emergency_chord),
+    named_layouts,
+    switch_all_keyboards: f.switch_all_keyboards
+  })
+}
+
+fn check_layout_switches(mappings: &[s::Mapping], named_layouts: &[s::NamedLayout]) -> Result<(), String> {
+  let all_mappings = mappings.iter().chain(named_layouts.iter().flat_map(|named| named.mappings.iter()));
+  for m in all_mappings {
+    if let Some(s::Action::SwitchLayout(name)) = &m.action {
+      if !named_layouts.iter().any(|named| &named.name == name) {
+        return Err(format!("There is no layout named {} to switch to", name));
+      }
+    }
+  }
+  Ok(())
+}
+
+fn convert_mappings(fms: &[f::Mapping]) -> Result<Vec<s::Mapping>, String> {
   let mut res = Vec::new();
   let mut from_table: HashMap<FromSet, Vec<usize>> = HashMap::new();
+  // Which of `fms` each of `res` came from
+  let mut sources = Vec::new();
   
-  let alias_mappings = find_alias_mappings(f);
+  let alias_mappings = find_alias_mappings(fms);
   
-  for fm in &f.mappings {
+  for (source, fm) in fms.iter().enumerate() {
     let sms = convert_mapping(&alias_mappings, fm)?;
     for sm in sms {
+      sources.push(source);
       let from_set = FromSet::new(&sm.from);
       match from_table.get_mut(&from_set) {
         Some(v) => v.push(res.len()),
@@ -26,13 +69,14 @@ pub fn convert(f: &f::Layout) -> Result<s::Layout, String> {
     }
   }
   
-  for fm in &f.mappings {
+  check_ties(&res, &from_table)?;
+  check_overlaps(&res, &sources)?;
+  
+  for fm in fms {
     adjust_repeats(&mut res, &from_table, &alias_mappings, fm)?;
   }
   
-  Ok(s::Layout {
-    mappings: res
-  })
+  Ok(res)
 }
 
 fn adjust_repeats<'a>(res: &mut Vec<s::Mapping>, from_table: &HashMap<FromSet, Vec<usize>>, alias_mappings: &'a HashMap<String, Vec<&'a f::AliasMapping>>, fm: &f::Mapping) -> Result<(), String> {
@@ -50,6 +94,10 @@ fn adjust_repeats<'a>(res: &mut Vec<s::Mapping>, from_table: &HashMap<FromSet, V
             keys: modifier_combination.translate_single_to_keys(&keys)?,
             delay_ms: *delay_ms,
             interval_ms: *interval_ms
+          },
+          f::SingleRepeat::AutoShift { delay_ms } => s::Repeat::AutoShift {
+            keys: shifted_keys(&from),
+            delay_ms: *delay_ms
           }
         };
 
@@ -57,11 +105,14 @@ fn adjust_repeats<'a>(res: &mut Vec<s::Mapping>, from_table: &HashMap<FromSet, V
         if let Some(is) = from_table.get(&from_set) {
           for i in is {
             let sm = &mut res[*i];
-            sm.repeat = repeat.clone();
+            sm.repeat = match &repeat {
+              s::Repeat::AutoShift { keys: _, delay_ms } => s::Repeat::AutoShift { keys: shifted_keys(&sm.to), delay_ms: *delay_ms },
+              _ => repeat.clone()
+            };
           }
         }
         else {
-          res.push(s::Mapping { from: from.clone(), to: from, repeat, absorbing: vec![] });
+          res.push(s::Mapping { from: from.clone(), to: from, repeat, absorbing: vec![], ..Default::default() });
         }
       }
     },
@@ -70,6 +121,78 @@ fn adjust_repeats<'a>(res: &mut Vec<s::Mapping>, from_table: &HashMap<FromSet, V
   Ok(())
 }
 
+// Two mappings with the same keys, conditions and priority would leave
+// no way to tell which one is meant, so that is an error.
+fn check_ties(res: &[s::Mapping], from_table: &HashMap<FromSet, Vec<usize>>) -> Result<(), String> {
+  let is_key_mapping = |m: &s::Mapping| m.combo.is_none() && m.sequence.is_none();
+  
+  for is in from_table.values() {
+    for (n, i) in is.iter().enumerate() {
+      for j in &is[n+1 ..] {
+        let (a, b) = (&res[*i], &res[*j]);
+        if is_key_mapping(a) && is_key_mapping(b) && a.priority == b.priority && same_conditions(a, b) {
+          let from: Vec<String> = a.from.iter().map(|k| k.to_string()).collect();
+          return Err(format!("There are two mappings from {} with priority {}; give one of them a different \"priority\"", from.join(", "), a.priority));
+        }
+      }
+    }
+  }
+  
+  Ok(())
+}
+
+// Mappings from different keys can still both apply, when all of their
+// keys are held at once. If they rank the same, only their order in the
+// layout would pick one, so that is an error too, unless they do the same
+// or are alias expansions of the same mapping.
+fn check_overlaps(res: &[s::Mapping], sources: &[usize]) -> Result<(), String> {
+  let is_key_mapping = |m: &s::Mapping| m.combo.is_none() && m.sequence.is_none();
+  let is_conditional = |m: &s::Mapping| m.exclusive || !m.unless.is_empty() || !m.if_led.is_empty();
+  
+  for (n, a) in res.iter().enumerate() {
+    for (m, b) in res.iter().enumerate().skip(n+1) {
+      if sources[n] != sources[m]
+        && is_key_mapping(a) && is_key_mapping(b)
+        && a.from.last() == b.from.last()
+        && (a.priority, a.from.len(), is_conditional(a)) == (b.priority, b.from.len(), is_conditional(b))
+        && FromSet::new(&a.from) != FromSet::new(&b.from)
+        && can_both_apply(a, b)
+        && !same_effect(a, b)
+      {
+        let from_a: Vec<String> = a.from.iter().map(|k| k.to_string()).collect();
+        let from_b: Vec<String> = b.from.iter().map(|k| k.to_string()).collect();
+        return Err(format!("The mappings from {} and from {} both apply when all of their keys are held, with priority {}; give one of them a different \"priority\"", from_a.join(", "), from_b.join(", "), a.priority));
+      }
+    }
+  }
+  
+  Ok(())
+}
+
+fn can_both_apply(a: &s::Mapping, b: &s::Mapping) -> bool {
+  let held: Vec<KeyCode> = a.from.iter().chain(&b.from).copied().collect();
+  let allows = |m: &s::Mapping| {
+    !m.unless.iter().any(|k| held.contains(k)) && (!m.exclusive || held.iter().all(|k| m.from.contains(k)))
+  };
+  allows(a) && allows(b)
+}
+
+fn same_effect(a: &s::Mapping, b: &s::Mapping) -> bool {
+  s::Mapping { from: vec![], ..a.clone() } == s::Mapping { from: vec![], ..b.clone() }
+}
+
+fn same_conditions(a: &s::Mapping, b: &s::Mapping) -> bool {
+  let mut unless_a = a.unless.clone();
+  let mut unless_b = b.unless.clone();
+  unless_a.sort();
+  unless_b.sort();
+  let mut if_led_a = a.if_led.clone();
+  let mut if_led_b = b.if_led.clone();
+  if_led_a.sort();
+  if_led_b.sort();
+  unless_a == unless_b && a.exclusive == b.exclusive && if_led_a == if_led_b
+}
+
 #[derive(PartialEq, Eq, Hash, Clone)]
 struct FromSet {
   keys: Vec<KeyCode>
@@ -90,27 +213,126 @@ impl FromSet {
 
 fn convert_mapping<'a>(alias_mappings: &HashMap<String, Vec<&'a f::AliasMapping>>, m: &f::Mapping) -> Result<Vec<s::Mapping>, String> {
   match m {
-    f::Mapping::Alias(alias) => Ok(convert_alias(alias)),
+    f::Mapping::Alias(alias) => convert_alias(alias),
     f::Mapping::Single(single) => convert_single(alias_mappings, single),
     f::Mapping::Row(row) => convert_row(alias_mappings, row),
     f::Mapping::RepeatOnlySingle(_) => Ok(vec![]),
+    f::Mapping::Latch(latch) => convert_latch(alias_mappings, latch),
+    f::Mapping::Combo(combo) => convert_combo(combo),
+    f::Mapping::Sequence(sequence) => convert_sequence(sequence),
+    f::Mapping::Macro(macro_mapping) => convert_macro(alias_mappings, macro_mapping),
+    f::Mapping::Action(action) => convert_action(alias_mappings, action),
+    f::Mapping::Mouse(mouse) => convert_mouse(alias_mappings, mouse),
+    f::Mapping::Priority(priority) => convert_priority(alias_mappings, priority),
+    f::Mapping::Conditional(conditional) => convert_conditional(alias_mappings, conditional),
+    f::Mapping::ClearModifiers(clear) => convert_clear_modifiers(alias_mappings, clear),
   }
 }
 
-fn convert_alias(alias: &f::AliasMapping) -> Vec<s::Mapping> {
+fn convert_clear_modifiers(alias_mappings: &HashMap<String, Vec<&f::AliasMapping>>, clear: &f::ClearModifiersMapping) -> Result<Vec<s::Mapping>, String> {
+  let mut res = convert_mapping(alias_mappings, &clear.mapping)?;
+  for sm in &mut res {
+    sm.clear_modifiers = true;
+  }
+  Ok(res)
+}
+
+fn convert_conditional(alias_mappings: &HashMap<String, Vec<&f::AliasMapping>>, conditional: &f::ConditionalMapping) -> Result<Vec<s::Mapping>, String> {
+  let mut unless = Vec::new();
+  for m in &conditional.unless {
+    match m {
+      f::Modifier::Key(k) => unless.push(*k),
+      f::Modifier::Alias(name) => {
+        for alias in alias_mappings.get(name).map(|v| v.as_slice()).unwrap_or(&[]) {
+          match alias.from.keys.as_slice() {
+            [k] => unless.push(*k),
+            _ => Err(format!("`unless` can only use aliases made from a single key, but {} is made from {:?}", name, alias.from.keys))?
+          }
+        }
+      }
+    }
+  }
+  
+  let mut res = convert_mapping(alias_mappings, &conditional.mapping)?;
+  for sm in &mut res {
+    sm.unless = unless.clone();
+    sm.exclusive = conditional.exclusive;
+    sm.if_led = conditional.if_led.clone();
+  }
+  Ok(res)
+}
+
+fn convert_priority(alias_mappings: &HashMap<String, Vec<&f::AliasMapping>>, priority: &f::PriorityMapping) -> Result<Vec<s::Mapping>, String> {
+  let mut res = convert_mapping(alias_mappings, &priority.mapping)?;
+  for sm in &mut res {
+    sm.priority = priority.priority;
+  }
+  Ok(res)
+}
+
+fn convert_alias(alias: &f::AliasMapping) -> Result<Vec<s::Mapping>, String> {
+  let tap = match &alias.tap {
+    None => None,
+    Some(tap) => Some(s::Tap {
+      keys: translate_plain_to_keys(&tap.keys)?,
+      tapping_term_ms: tap.tapping_term_ms,
+      extra_taps: tap.extra_taps.iter().map(translate_plain_to_keys).collect::<Result<Vec<_>, String>>()?
+    })
+  };
+  
+  let to_if_alone = match &alias.to_if_alone {
+    None => vec![],
+    Some(keys) => translate_plain_to_keys(keys)?
+  };
+  
   // This test tries to be clever about whethere the user
   // expects modifiers to pass-through.
   if !is_just_one_modifier(&alias.from.keys) {
-    vec![s::Mapping {
+    Ok(vec![s::Mapping {
       from: alias.from.keys.clone(),
       to: alias.to.initial.clone(),
       repeat: s::Repeat::Normal,
-      absorbing: vec![]
-    }]
+      absorbing: vec![],
+      tap,
+      one_shot: alias.one_shot,
+      to_if_alone: to_if_alone.clone(),
+      ..Default::default()
+    }])
+  }
+  else if tap.is_some() || alias.one_shot || !to_if_alone.is_empty() {
+    // The modifier still passes through when held; the mapping
+    // only exists to carry the tap, one-shot or `to_if_alone`.
+    Ok(vec![s::Mapping {
+      from: alias.from.keys.clone(),
+      to: alias.from.keys.clone(),
+      repeat: s::Repeat::Normal,
+      absorbing: vec![],
+      tap,
+      one_shot: alias.one_shot,
+      to_if_alone: to_if_alone.clone(),
+      ..Default::default()
+    }])
   }
   else {
-    vec![]
+    Ok(vec![])
+  }
+}
+
+fn translate_plain_to_keys(to: &f::SingleToKeys) -> Result<Vec<KeyCode>, String> {
+  let mut res = Vec::new();
+  
+  for m in &to.initial {
+    match m {
+      f::Modifier::Key(k) => res.push(*k),
+      f::Modifier::Alias(alias) => return Err(format!("Alias {} cannot be used here since there is no `from` alias to match it", alias))
+    }
+  }
+  
+  if let f::SingleTerminalToKey::Physical(k) = to.terminal {
+    res.push(k);
   }
+  
+  Ok(res)
 }
 
 fn convert_single<'a>(alias_mappings: &'a HashMap<String, Vec<&'a f::AliasMapping>>, single: &f::SingleMapping) -> Result<Vec<s::Mapping>, String> {
@@ -129,21 +351,180 @@ fn convert_single<'a>(alias_mappings: &'a HashMap<String, Vec<&'a f::AliasMappin
         keys: modifier_combination.translate_single_to_keys(&keys)?,
         delay_ms: *delay_ms,
         interval_ms: *interval_ms
+      },
+      f::SingleRepeat::AutoShift { delay_ms } => s::Repeat::AutoShift {
+        keys: shifted_keys(&to),
+        delay_ms: *delay_ms
       }
     };
 
     let absorbing = modifier_combination.reify_modifiers(&single.absorbing)?;
+    
+    let tap = match &single.tap {
+      None => None,
+      Some(tap) => Some(s::Tap {
+        keys: modifier_combination.translate_single_to_keys(&tap.keys)?,
+        tapping_term_ms: tap.tapping_term_ms,
+        extra_taps: tap.extra_taps.iter().map(|keys| modifier_combination.translate_single_to_keys(keys)).collect::<Result<Vec<_>, String>>()?
+      })
+    };
+
+    let to_if_alone = match &single.to_if_alone {
+      None => vec![],
+      Some(keys) => modifier_combination.translate_single_to_keys(keys)?
+    };
 
     res.push(s::Mapping {
       from,
       to,
       repeat,
-      absorbing
+      absorbing,
+      tap,
+      one_shot: single.one_shot,
+      to_if_alone,
+      ..Default::default()
     });
   }
   Ok(res)
 }
 
+fn convert_latch<'a>(alias_mappings: &'a HashMap<String, Vec<&'a f::AliasMapping>>, latch: &f::LatchMapping) -> Result<Vec<s::Mapping>, String> {
+  // Holding any one of the alias's keys activates it, so latching
+  // the first one is enough.
+  let alias_keys = match alias_mappings.get(&latch.alias) {
+    Some(mappings) if !mappings.is_empty() => mappings[0].from.keys.clone(),
+    _ => return Err(format!("Alias {} is undefined", latch.alias))
+  };
+  
+  let mut res = Vec::new();
+  let modifier_combinations = build_combinations(alias_mappings, &latch.from.modifiers)?;
+  for modifier_combination in iterate_combinations(&modifier_combinations) {
+    let mut from = modifier_combination.from_modifiers().clone();
+    from.push(latch.from.key);
+    
+    res.push(s::Mapping {
+      from,
+      to: vec![],
+      latch: Some(s::Latch { action: latch.action, keys: alias_keys.clone() }),
+      ..Default::default()
+    });
+  }
+  Ok(res)
+}
+
+fn convert_combo(combo: &f::ComboMapping) -> Result<Vec<s::Mapping>, String> {
+  Ok(vec![s::Mapping {
+    from: combo.keys.clone(),
+    to: translate_plain_to_keys(&combo.to)?,
+    combo: Some(s::Combo { window_ms: combo.window_ms }),
+    ..Default::default()
+  }])
+}
+
+fn convert_sequence(sequence: &f::SequenceMapping) -> Result<Vec<s::Mapping>, String> {
+  Ok(vec![s::Mapping {
+    from: sequence.keys.clone(),
+    to: translate_plain_to_keys(&sequence.to)?,
+    sequence: Some(s::Sequence { timeout_ms: sequence.timeout_ms }),
+    ..Default::default()
+  }])
+}
+
+fn convert_macro<'a>(alias_mappings: &'a HashMap<String, Vec<&'a f::AliasMapping>>, macro_mapping: &f::MacroMapping) -> Result<Vec<s::Mapping>, String> {
+  let mut res = Vec::new();
+  let modifier_combinations = build_combinations(alias_mappings, &macro_mapping.from.modifiers)?;
+  for modifier_combination in iterate_combinations(&modifier_combinations) {
+    let mut from = modifier_combination.from_modifiers().clone();
+    from.push(macro_mapping.from.key);
+    
+    let mut macro_steps = Vec::new();
+    for step in &macro_mapping.steps {
+      match step {
+        f::MacroStep::Tap(keys) => macro_steps.push(s::MacroStep::Tap(modifier_combination.translate_single_to_keys(keys)?)),
+        f::MacroStep::Delay(delay_ms) => macro_steps.push(s::MacroStep::Delay(*delay_ms)),
+        f::MacroStep::Text(text) => macro_steps.append(&mut convert_text(text)?),
+        f::MacroStep::Unicode { text, method } => {
+          for ch in text.chars() {
+            macro_steps.append(&mut unicode_macro_steps(*method, ch));
+          }
+        }
+      };
+    }
+    
+    res.push(s::Mapping {
+      from,
+      to: vec![],
+      macro_steps,
+      ..Default::default()
+    });
+  }
+  Ok(res)
+}
+
+fn convert_action<'a>(alias_mappings: &'a HashMap<String, Vec<&'a f::AliasMapping>>, action: &f::ActionMapping) -> Result<Vec<s::Mapping>, String> {
+  let mut res = Vec::new();
+  let modifier_combinations = build_combinations(alias_mappings, &action.from.modifiers)?;
+  for modifier_combination in iterate_combinations(&modifier_combinations) {
+    let mut from = modifier_combination.from_modifiers().clone();
+    from.push(action.from.key);
+    
+    res.push(s::Mapping {
+      from,
+      to: vec![],
+      action: Some(action.action.clone()),
+      ..Default::default()
+    });
+  }
+  Ok(res)
+}
+
+fn convert_mouse<'a>(alias_mappings: &'a HashMap<String, Vec<&'a f::AliasMapping>>, mouse: &f::MouseMapping) -> Result<Vec<s::Mapping>, String> {
+  let mut res = Vec::new();
+  let modifier_combinations = build_combinations(alias_mappings, &mouse.from.modifiers)?;
+  for modifier_combination in iterate_combinations(&modifier_combinations) {
+    let mut from = modifier_combination.from_modifiers().clone();
+    from.push(mouse.from.key);
+    
+    res.push(s::Mapping {
+      from,
+      to: vec![],
+      mouse: Some(mouse.mouse),
+      ..Default::default()
+    });
+  }
+  Ok(res)
+}
+
+fn convert_text(text: &str) -> Result<Vec<s::MacroStep>, String> {
+  use crate::char_production_map::CHAR_ACCESS_MAP;
+  let mut res = Vec::new();
+  for ch in text.chars() {
+    // Not in CHAR_ACCESS_MAP, since rows use it as well
+    let key = match ch {
+      '\n' => Some(KeyCode::ENTER),
+      '\t' => Some(KeyCode::TAB),
+      _ => None
+    };
+    if let Some(k) = key {
+      res.push(s::MacroStep::Tap(vec![k]));
+      continue;
+    }
+    
+    match CHAR_ACCESS_MAP.get(&ch) {
+      None => res.append(&mut unicode_macro_steps(DEFAULT_UNICODE_METHOD, ch)),
+      Some(sk) => {
+        if sk.sh {
+          res.push(s::MacroStep::Tap(vec![KeyCode::LEFTSHIFT, sk.k]));
+        }
+        else {
+          res.push(s::MacroStep::Tap(vec![sk.k]));
+        }
+      }
+    }
+  }
+  Ok(res)
+}
+
 enum RowRepeatTemplate {
   Normal,
   Disabled,
@@ -152,6 +533,9 @@ enum RowRepeatTemplate {
     terminal: Vec<char>,
     delay_ms: i32,
     interval_ms: i32
+  },
+  AutoShift {
+    delay_ms: i32
   }
 }
 
@@ -183,7 +567,8 @@ fn convert_row<'t>(alias_mappings: &'t HashMap<String, Vec<&'t f::AliasMapping>>
           delay_ms: *delay_ms,
           interval_ms: *interval_ms
         }
-      }
+      },
+      f::RowRepeat::AutoShift { delay_ms } => RowRepeatTemplate::AutoShift { delay_ms: *delay_ms }
     };
     
     use crate::physical_keyboard_layouts::US_KEYBOARD_LAYOUT;
@@ -198,6 +583,21 @@ fn convert_row<'t>(alias_mappings: &'t HashMap<String, Vec<&'t f::AliasMapping>>
         return Err(format!("Don't know which keycode is at index {} in row {:?}", char_i, row_mapping.from.row));
       }
       
+      if !is_us_typeable(to_terminals[char_i]) {
+        // Characters without a key are typed with a macro instead
+        let mut from = from_modifiers.clone();
+        from.push(from_physical_row[char_i]);
+        
+        res.push(s::Mapping {
+          from,
+          to: vec![],
+          absorbing: modifier_combination.reify_modifiers(&row_mapping.absorbing)?,
+          macro_steps: unicode_macro_steps(DEFAULT_UNICODE_METHOD, to_terminals[char_i]),
+          ..Default::default()
+        });
+        continue;
+      }
+      
       let to = convert_row_to(has_right_shift, &to_modifiers, &to_terminals, char_i)?;
       if let Some(to) = to {
         let mut from = from_modifiers.clone();
@@ -211,7 +611,8 @@ fn convert_row<'t>(alias_mappings: &'t HashMap<String, Vec<&'t f::AliasMapping>>
               None => s::Repeat::Normal,
               Some(keys) => s::Repeat::Special { keys, delay_ms: *delay_ms, interval_ms: *interval_ms }
             }
-          }
+          },
+          RowRepeatTemplate::AutoShift { delay_ms } => s::Repeat::AutoShift { keys: shifted_keys(&to), delay_ms: *delay_ms }
         };
 
         let absorbing = modifier_combination.reify_modifiers(&row_mapping.absorbing)?;
@@ -220,7 +621,8 @@ fn convert_row<'t>(alias_mappings: &'t HashMap<String, Vec<&'t f::AliasMapping>>
           from,
           to,
           repeat,
-          absorbing
+          absorbing,
+          ..Default::default()
         });
       }
     }
@@ -228,6 +630,18 @@ fn convert_row<'t>(alias_mappings: &'t HashMap<String, Vec<&'t f::AliasMapping>>
   Ok(res)
 }
 
+// The same keys with Shift held, unless a Shift is already there
+fn shifted_keys(to: &[KeyCode]) -> Vec<KeyCode> {
+  if to.is_empty() || to.contains(&KeyCode::LEFTSHIFT) || to.contains(&KeyCode::RIGHTSHIFT) {
+    to.to_vec()
+  }
+  else {
+    let mut res = to.to_vec();
+    res.insert(to.len() - 1, KeyCode::LEFTSHIFT);
+    res
+  }
+}
+
 fn find_right_shift(from: &Vec<KeyCode>) -> bool {
   for k in from {
     if *k == KeyCode::RIGHTSHIFT {
@@ -237,6 +651,11 @@ fn find_right_shift(from: &Vec<KeyCode>) -> bool {
   return false;
 }
 
+fn is_us_typeable(ch: char) -> bool {
+  use crate::char_production_map::CHAR_ACCESS_MAP;
+  CHAR_ACCESS_MAP.contains_key(&ch)
+}
+
 fn convert_row_to(has_right_shift: bool, modifiers: &Vec<KeyCode>, terminals: &Vec<char>, char_i: usize) -> Result<Option<Vec<KeyCode>>, String> {
   use crate::char_production_map::CHAR_ACCESS_MAP;
   if char_i >= terminals.len() {
@@ -455,12 +874,12 @@ impl <'s> std::iter::Iterator for MultiplyIter<'s> {
   }
 }
 
-fn find_alias_mappings<'a>(f: &'a f::Layout) -> HashMap<String, Vec<&'a AliasMapping>> {
+fn find_alias_mappings(fms: &[f::Mapping]) -> HashMap<String, Vec<&AliasMapping>> {
   use f::*;
   
   let mut res = HashMap::new();
   
-  for m in &f.mappings {
+  for m in fms {
     match m {
       Mapping::Alias(alias) => {
         match res.get_mut(&alias.to.terminal) {
@@ -525,8 +944,8 @@ use super::{multiply, convert_row_to, convert};
   #[test]
   fn test_single_convert_1() {
     let mut alias_mappings = HashMap::new();
-    let leftshift_shift = AM { from: AFK { keys: vec![LEFTSHIFT] }, to: ATK { initial: vec![], terminal: "@shift".o() } };
-    let rightshift_shift = AM { from: AFK { keys: vec![RIGHTSHIFT] }, to: ATK { initial: vec![], terminal: "@shift".o() } };
+    let leftshift_shift = AM { from: AFK { keys: vec![LEFTSHIFT] }, to: ATK { initial: vec![], terminal: "@shift".o() }, tap: None, one_shot: false, to_if_alone: None };
+    let rightshift_shift = AM { from: AFK { keys: vec![RIGHTSHIFT] }, to: ATK { initial: vec![], terminal: "@shift".o() }, tap: None, one_shot: false, to_if_alone: None };
     alias_mappings.insert("@shift".o(), vec![
       &leftshift_shift,
       &rightshift_shift
@@ -536,24 +955,27 @@ use super::{multiply, convert_row_to, convert};
       from: SFK { modifiers: vec![Alias("@shift".o())], key: E },
       to: STK { initial: vec![Alias("@shift".o())], terminal: Physical(DOT) },
       repeat: f::SingleRepeat::Special { keys: STK { initial: vec![Key(LEFTCTRL)], terminal: Physical(K3) }, delay_ms: 50, interval_ms: 30 },
-      absorbing: vec![Alias("@shift".o())]
+      absorbing: vec![Alias("@shift".o())],
+      tap: None,
+      one_shot: false,
+      to_if_alone: None
     };
     
     let res = convert_single(&alias_mappings, &single).unwrap();
     assert_eq!(res.len(), 2);
     assert_eq!(res[0], 
-      s::Mapping { from: vec![LEFTSHIFT, E], to: vec![LEFTSHIFT, DOT], repeat: s::Repeat::Special { keys: vec![LEFTCTRL, K3], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LEFTSHIFT]  }
+      s::Mapping { from: vec![LEFTSHIFT, E], to: vec![LEFTSHIFT, DOT], repeat: s::Repeat::Special { keys: vec![LEFTCTRL, K3], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LEFTSHIFT], ..Default::default() }
     );
     assert_eq!(res[1],
-      s::Mapping { from: vec![RIGHTSHIFT, E], to: vec![RIGHTSHIFT, DOT], repeat: s::Repeat::Special { keys: vec![LEFTCTRL, K3], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RIGHTSHIFT]  }
+      s::Mapping { from: vec![RIGHTSHIFT, E], to: vec![RIGHTSHIFT, DOT], repeat: s::Repeat::Special { keys: vec![LEFTCTRL, K3], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RIGHTSHIFT], ..Default::default() }
     );
   }
   
   #[test]
   fn test_row_convert_1() {
     let mut alias_mappings = HashMap::new();
-    let leftshift_shift = AM { from: AFK { keys: vec![LEFTSHIFT] }, to: ATK { initial: vec![], terminal: "@shift".o() } };
-    let rightshift_shift = AM { from: AFK { keys: vec![RIGHTSHIFT] }, to: ATK { initial: vec![], terminal: "@shift".o() } };
+    let leftshift_shift = AM { from: AFK { keys: vec![LEFTSHIFT] }, to: ATK { initial: vec![], terminal: "@shift".o() }, tap: None, one_shot: false, to_if_alone: None };
+    let rightshift_shift = AM { from: AFK { keys: vec![RIGHTSHIFT] }, to: ATK { initial: vec![], terminal: "@shift".o() }, tap: None, one_shot: false, to_if_alone: None };
     alias_mappings.insert("@shift".o(), vec![
       &leftshift_shift,
       &rightshift_shift
@@ -575,21 +997,21 @@ use super::{multiply, convert_row_to, convert};
     let res = convert_row(&alias_mappings, &row).unwrap();
     assert_eq!(res.len(), 8);
     
-    assert_eq!(res[0], SM { from: vec![LS, A], to: vec![LS, A], repeat: SRS { keys: vec![A], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS]  });
-    assert_eq!(res[1], SM { from: vec![LS, S], to: vec![LS, O], repeat: SRS { keys: vec![O], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS]  });
-    assert_eq!(res[2], SM { from: vec![LS, D], to: vec![LS, E], repeat: SRS { keys: vec![E], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS]  });
-    assert_eq!(res[3], SM { from: vec![LS, F], to: vec![LS, U], repeat: SRS { keys: vec![U], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS]  });
+    assert_eq!(res[0], SM { from: vec![LS, A], to: vec![LS, A], repeat: SRS { keys: vec![A], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS], ..Default::default() });
+    assert_eq!(res[1], SM { from: vec![LS, S], to: vec![LS, O], repeat: SRS { keys: vec![O], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS], ..Default::default() });
+    assert_eq!(res[2], SM { from: vec![LS, D], to: vec![LS, E], repeat: SRS { keys: vec![E], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS], ..Default::default() });
+    assert_eq!(res[3], SM { from: vec![LS, F], to: vec![LS, U], repeat: SRS { keys: vec![U], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS], ..Default::default() });
     
-    assert_eq!(res[4], SM { from: vec![RS, A], to: vec![RS, A], repeat: SRS { keys: vec![A], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS]  });
-    assert_eq!(res[5], SM { from: vec![RS, S], to: vec![RS, O], repeat: SRS { keys: vec![O], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS]  });
-    assert_eq!(res[6], SM { from: vec![RS, D], to: vec![RS, E], repeat: SRS { keys: vec![E], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS]  });
-    assert_eq!(res[7], SM { from: vec![RS, F], to: vec![RS, U], repeat: SRS { keys: vec![U], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS]  });
+    assert_eq!(res[4], SM { from: vec![RS, A], to: vec![RS, A], repeat: SRS { keys: vec![A], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS], ..Default::default() });
+    assert_eq!(res[5], SM { from: vec![RS, S], to: vec![RS, O], repeat: SRS { keys: vec![O], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS], ..Default::default() });
+    assert_eq!(res[6], SM { from: vec![RS, D], to: vec![RS, E], repeat: SRS { keys: vec![E], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS], ..Default::default() });
+    assert_eq!(res[7], SM { from: vec![RS, F], to: vec![RS, U], repeat: SRS { keys: vec![U], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS], ..Default::default() });
   }
   
   #[test]
   fn test_row_convert_2() {
     let mut alias_mappings = HashMap::new();
-    let leftshift_shift = AM { from: AFK { keys: vec![LEFTSHIFT] }, to: ATK { initial: vec![], terminal: "@shift".o() } };
+    let leftshift_shift = AM { from: AFK { keys: vec![LEFTSHIFT] }, to: ATK { initial: vec![], terminal: "@shift".o() }, tap: None, one_shot: false, to_if_alone: None };
     alias_mappings.insert("@shift".o(), vec![
       &leftshift_shift,
     ]);
@@ -607,7 +1029,7 @@ use super::{multiply, convert_row_to, convert};
     
     let res = convert_row(&alias_mappings, &row).unwrap();
     assert_eq!(res.len(), 1);
-    assert_eq!(res[0], SM { from: vec![LS, A], to: vec![LS, A], repeat: s::Repeat::Normal, absorbing: vec![]  });
+    assert_eq!(res[0], SM { from: vec![LS, A], to: vec![LS, A], repeat: s::Repeat::Normal, absorbing: vec![], ..Default::default() });
   }
   
   #[test]
@@ -635,7 +1057,7 @@ use super::{multiply, convert_row_to, convert};
     use s::Mapping as SM;
     use KeyCode::LEFTSHIFT as LS;
     assert_eq!(simple_layout.mappings[0], SM { from: vec![LS, A], to: vec![LS, S], repeat: s::Repeat::Special {
-      keys: vec![F24], delay_ms: 180, interval_ms: 30 }, absorbing: vec![] });
+      keys: vec![F24], delay_ms: 180, interval_ms: 30 }, absorbing: vec![], ..Default::default() });
   }
 
   #[test]
@@ -651,8 +1073,367 @@ use super::{multiply, convert_row_to, convert};
     let simple_layout = convert(&fancy_layout).unwrap();
     assert_eq!(simple_layout.mappings.len(), 2);
     use s::Mapping as SM;
-    assert_eq!(simple_layout.mappings[0], SM { from: vec![CAPSLOCK], to: vec![], repeat: s::Repeat::Normal, absorbing: vec![] });
-    assert_eq!(simple_layout.mappings[1], SM { from: vec![CAPSLOCK, Q], to: vec![ESC], repeat: s::Repeat::Normal, absorbing: vec![] });
+    assert_eq!(simple_layout.mappings[0], SM { from: vec![CAPSLOCK], to: vec![], repeat: s::Repeat::Normal, absorbing: vec![], ..Default::default() });
+    assert_eq!(simple_layout.mappings[1], SM { from: vec![CAPSLOCK, Q], to: vec![ESC], repeat: s::Repeat::Normal, absorbing: vec![], ..Default::default() });
   }
-}
 
+  #[test]
+  fn test_tap_hold() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "LEFTSHIFT", "to": { "tap": ["LEFTSHIFT", "9"], "hold": "@shift" } },
+    { "from": "CAPSLOCK", "to": { "tap": "ESC", "hold": "@movement", "tapping_term_ms": 150 } },
+    { "from": ["@movement", "J"], "to": "LEFT" },
+    { "from": ["@shift", "A"], "to": { "tap": ["@shift", "A"], "hold": "LEFTCTRL" } }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings.len(), 4);
+    use s::Mapping as SM;
+    assert_eq!(simple_layout.mappings[0], SM { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], tap: Some(s::Tap { keys: vec![LEFTSHIFT, K9], tapping_term_ms: 200, extra_taps: vec![] }), ..Default::default() });
+    assert_eq!(simple_layout.mappings[1], SM { from: vec![CAPSLOCK], to: vec![], tap: Some(s::Tap { keys: vec![ESC], tapping_term_ms: 150, extra_taps: vec![] }), ..Default::default() });
+    assert_eq!(simple_layout.mappings[2], SM { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() });
+    assert_eq!(simple_layout.mappings[3], SM { from: vec![LEFTSHIFT, A], to: vec![LEFTCTRL], tap: Some(s::Tap { keys: vec![LEFTSHIFT, A], tapping_term_ms: 200, extra_taps: vec![] }), ..Default::default() });
+  }
+
+  #[test]
+  fn test_one_shot() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "LEFTSHIFT", "to": "@shift", "one_shot": true },
+    { "from": "CAPSLOCK", "to": "@movement", "one_shot": true },
+    { "from": ["@movement", "J"], "to": "LEFT" }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings.len(), 3);
+    use s::Mapping as SM;
+    assert_eq!(simple_layout.mappings[0], SM { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], one_shot: true, ..Default::default() });
+    assert_eq!(simple_layout.mappings[1], SM { from: vec![CAPSLOCK], to: vec![], one_shot: true, ..Default::default() });
+    assert_eq!(simple_layout.mappings[2], SM { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() });
+  }
+
+  #[test]
+  fn test_latch() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "CAPSLOCK", "to": "@numpad" },
+    { "from": "RIGHTALT", "to": "@numpad" },
+    { "from": "SCROLLLOCK", "to": { "lock": "@numpad" } },
+    { "from": ["@numpad", "ESC"], "to": { "clear": "@numpad" } }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings.len(), 4);
+    use s::Mapping as SM;
+    assert_eq!(simple_layout.mappings[1], SM { from: vec![SCROLLLOCK], to: vec![], latch: Some(s::Latch { action: s::LatchAction::Lock, keys: vec![CAPSLOCK] }), ..Default::default() });
+    assert_eq!(simple_layout.mappings[2], SM { from: vec![CAPSLOCK, ESC], to: vec![], latch: Some(s::Latch { action: s::LatchAction::Clear, keys: vec![CAPSLOCK] }), ..Default::default() });
+    assert_eq!(simple_layout.mappings[3], SM { from: vec![RIGHTALT, ESC], to: vec![], latch: Some(s::Latch { action: s::LatchAction::Clear, keys: vec![CAPSLOCK] }), ..Default::default() });
+  }
+
+  #[test]
+  fn test_combo() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "LEFTSHIFT", "to": "@shift" },
+    { "combo": ["D", "F"], "to": ["@shift", "C"] }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    // There is no `from` alias for the `to` alias to follow
+    assert!(convert(&fancy_layout).is_err());
+    
+    let layout_json = r#"{
+  "mappings": [
+    { "combo": ["J", "K"], "to": "ESC", "window_ms": 40 }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![J, K], to: vec![ESC], combo: Some(s::Combo { window_ms: 40 }), ..Default::default() }
+    ]);
+  }
+
+  #[test]
+  fn test_sequence() {
+    let layout_json = r#"{
+  "mappings": [
+    { "sequence": ["RIGHTALT", "G", "G"], "to": ["LEFTCTRL", "HOME"], "timeout_ms": 800 }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![RIGHTALT, G, G], to: vec![LEFTCTRL, HOME], sequence: Some(s::Sequence { timeout_ms: 800 }), ..Default::default() }
+    ]);
+  }
+
+  #[test]
+  fn test_macro() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "LEFTSHIFT", "to": "@shift" },
+    { "from": "RIGHTSHIFT", "to": "@shift" },
+    { "from": ["@shift", "D"], "to": { "macro": [["@shift", "A"], { "delay_ms": 20 }, "DELETE"] } }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    use s::MacroStep::*;
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![LEFTSHIFT, D], to: vec![], macro_steps: vec![Tap(vec![LEFTSHIFT, A]), Delay(20), Tap(vec![DELETE])], ..Default::default() },
+      s::Mapping { from: vec![RIGHTSHIFT, D], to: vec![], macro_steps: vec![Tap(vec![RIGHTSHIFT, A]), Delay(20), Tap(vec![DELETE])], ..Default::default() },
+    ]);
+  }
+
+  #[test]
+  fn test_text() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": ["CAPSLOCK", "E"], "to": { "text": "Hi a@b\t\n" } }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    use s::MacroStep::*;
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![CAPSLOCK, E], to: vec![], macro_steps: vec![
+        Tap(vec![LEFTSHIFT, H]), Tap(vec![I]), Tap(vec![SPACE]), Tap(vec![A]), Tap(vec![LEFTSHIFT, K2]), Tap(vec![B]), Tap(vec![TAB]), Tap(vec![ENTER])
+      ], ..Default::default() },
+    ]);
+  }
+
+  #[test]
+  fn test_unicode() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": ["CAPSLOCK", "K"], "to": { "unicode": "→" } },
+    { "from": ["RIGHTALT", {"row": "1"}], "to": { "letters": "§a" } },
+    { "from": ["RIGHTALT", "E"], "to": { "text": "é!" } }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    use s::MacroStep::*;
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![CAPSLOCK, K], to: vec![], macro_steps: vec![
+        Tap(vec![LEFTCTRL, LEFTSHIFT, U]), Tap(vec![K2]), Tap(vec![K1]), Tap(vec![K9]), Tap(vec![K2]), Tap(vec![SPACE])
+      ], ..Default::default() },
+      s::Mapping { from: vec![RIGHTALT, K1], to: vec![], macro_steps: vec![
+        Tap(vec![LEFTCTRL, LEFTSHIFT, U]), Tap(vec![A]), Tap(vec![K7]), Tap(vec![SPACE])
+      ], ..Default::default() },
+      s::Mapping { from: vec![RIGHTALT, K2], to: vec![A], ..Default::default() },
+      s::Mapping { from: vec![RIGHTALT, E], to: vec![], macro_steps: vec![
+        Tap(vec![LEFTCTRL, LEFTSHIFT, U]), Tap(vec![E]), Tap(vec![K9]), Tap(vec![SPACE]), Tap(vec![LEFTSHIFT, K1])
+      ], ..Default::default() },
+    ]);
+  }
+
+  #[test]
+  fn test_caps_word() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "CAPSLOCK", "to": "@nav" },
+    { "from": ["@nav", "C"], "to": { "action": "caps_word" } }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+      s::Mapping { from: vec![CAPSLOCK, C], to: vec![], action: Some(s::Action::CapsWord), ..Default::default() },
+    ]);
+  }
+
+  #[test]
+  fn test_auto_shift() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": {"row": "1"}, "to": {"letters": "1!"}, "repeat": { "AutoShift": { "delay_ms": 180 } } },
+    { "from": "A", "repeat": { "AutoShift": { "delay_ms": 150 } } },
+    { "from": "CAPSLOCK", "to": "ESC", "repeat": { "AutoShift": { "delay_ms": 200 } } }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![K1], to: vec![K1], repeat: s::Repeat::AutoShift { keys: vec![LEFTSHIFT, K1], delay_ms: 180 }, ..Default::default() },
+      s::Mapping { from: vec![K2], to: vec![LEFTSHIFT, K1], repeat: s::Repeat::AutoShift { keys: vec![LEFTSHIFT, K1], delay_ms: 180 }, ..Default::default() },
+      s::Mapping { from: vec![CAPSLOCK], to: vec![ESC], repeat: s::Repeat::AutoShift { keys: vec![LEFTSHIFT, ESC], delay_ms: 200 }, ..Default::default() },
+      s::Mapping { from: vec![A], to: vec![A], repeat: s::Repeat::AutoShift { keys: vec![LEFTSHIFT, A], delay_ms: 150 }, ..Default::default() },
+    ]);
+  }
+
+  #[test]
+  fn test_mouse() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "CAPSLOCK", "to": "@nav" },
+    { "from": ["@nav", "L"], "to": { "mouse_move": [10, 0] } },
+    { "from": ["@nav", "N"], "to": { "mouse_scroll": [0, 1] } }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+      s::Mapping { from: vec![CAPSLOCK, L], to: vec![], mouse: Some(s::Mouse::Move { x: 10, y: 0 }), ..Default::default() },
+      s::Mapping { from: vec![CAPSLOCK, N], to: vec![], mouse: Some(s::Mouse::Scroll { x: 0, y: 1 }), ..Default::default() },
+    ]);
+  }
+
+  #[test]
+  fn test_default_layouts_convert() {
+    for (name, text) in crate::default_fancy_layouts::DEFAULT_LAYOUTS.iter() {
+      let layout_v = serde_json::from_str(text).unwrap();
+      let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+      assert!(convert(&fancy_layout).is_ok(), "{}: {:?}", name, convert(&fancy_layout).map(|_| ()));
+    }
+  }
+
+  #[test]
+  fn test_priority_and_ties() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "LEFTSHIFT", "to": "@shift" },
+    { "from": "RIGHTSHIFT", "to": "@shift" },
+    { "from": ["@shift", "SPACE"], "to": "BACKSPACE", "priority": 2 },
+    { "from": ["LEFTSHIFT", "SPACE"], "to": "DELETE" }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![LEFTSHIFT, SPACE], to: vec![BACKSPACE], priority: 2, ..Default::default() },
+      s::Mapping { from: vec![RIGHTSHIFT, SPACE], to: vec![BACKSPACE], priority: 2, ..Default::default() },
+      s::Mapping { from: vec![LEFTSHIFT, SPACE], to: vec![DELETE], ..Default::default() },
+    ]);
+    
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "LEFTSHIFT", "to": "@shift" },
+    { "from": "RIGHTSHIFT", "to": "@shift" },
+    { "from": ["@shift", "SPACE"], "to": "BACKSPACE" },
+    { "from": ["LEFTSHIFT", "SPACE"], "to": "DELETE" }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    assert_eq!(
+      Err("There are two mappings from LEFTSHIFT, SPACE with priority 0; give one of them a different \"priority\"".to_owned()),
+      convert(&fancy_layout).map(|_| ())
+    );
+  }
+
+  #[test]
+  fn test_overlapping_ties() {
+    let convert_text = |text: &str| {
+      let layout_v = serde_json::from_str(text).unwrap();
+      convert(&crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap()).map(|_| ())
+    };
+    
+    // Both apply with Shift and Ctrl held
+    assert_eq!(
+      Err("The mappings from LEFTSHIFT, SPACE and from LEFTCTRL, SPACE both apply when all of their keys are held, with priority 0; give one of them a different \"priority\"".to_owned()),
+      convert_text(r#"{ "mappings": [ { "from": ["LEFTSHIFT", "SPACE"], "to": "BACKSPACE" }, { "from": ["LEFTCTRL", "SPACE"], "to": "DELETE" } ] }"#)
+    );
+    
+    // A priority settles it
+    assert_eq!(Ok(()), convert_text(r#"{ "mappings": [ { "from": ["LEFTSHIFT", "SPACE"], "to": "BACKSPACE" }, { "from": ["LEFTCTRL", "SPACE"], "to": "DELETE", "priority": 1 } ] }"#));
+    // So does `unless`, since then they never both apply
+    assert_eq!(Ok(()), convert_text(r#"{ "mappings": [ { "from": ["LEFTSHIFT", "SPACE"], "to": "BACKSPACE", "unless": ["LEFTCTRL"] }, { "from": ["LEFTCTRL", "SPACE"], "to": "DELETE", "unless": ["LEFTSHIFT"] } ] }"#));
+    // Doing the same thing is no tie
+    assert_eq!(Ok(()), convert_text(r#"{ "mappings": [ { "from": ["LEFTSHIFT", "SPACE"], "to": "BACKSPACE" }, { "from": ["LEFTCTRL", "SPACE"], "to": "BACKSPACE" } ] }"#));
+    // Nor are the alias expansions of one mapping
+    assert_eq!(Ok(()), convert_text(r#"{ "mappings": [ { "from": "LEFTSHIFT", "to": "@shift" }, { "from": "RIGHTSHIFT", "to": "@shift" }, { "from": ["@shift", {"row": "Q"}], "to": {"letters": "QWERTY"} } ] }"#));
+  }
+
+  #[test]
+  fn test_unless() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "LEFTCTRL", "to": "@ctrl" },
+    { "from": "RIGHTCTRL", "to": "@ctrl" },
+    { "from": ["LEFTSHIFT", "SPACE"], "to": "BACKSPACE", "unless": ["@ctrl", "LEFTALT"] },
+    { "from": ["LEFTSHIFT", "SPACE"], "to": "DELETE", "exclusive": true }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![LEFTSHIFT, SPACE], to: vec![BACKSPACE], unless: vec![LEFTCTRL, RIGHTCTRL, LEFTALT], ..Default::default() },
+      s::Mapping { from: vec![LEFTSHIFT, SPACE], to: vec![DELETE], exclusive: true, ..Default::default() },
+    ]);
+  }
+
+  #[test]
+  fn test_to_if_alone() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "LEFTSHIFT", "to": "@shift", "to_if_alone": ["LEFTSHIFT", "9"] },
+    { "from": "RIGHTSHIFT", "to": "RIGHTSHIFT", "to_if_alone": ["RIGHTSHIFT", "0"] }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    
+    let restringed = crate::layout_parsing_formatting::format_layout_as_json(&fancy_layout);
+    assert_eq!(layout_v, restringed);
+    
+    let simple_layout = convert(&fancy_layout).unwrap();
+    assert_eq!(simple_layout.mappings, vec![
+      s::Mapping { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], to_if_alone: vec![LEFTSHIFT, K9], ..Default::default() },
+      s::Mapping { from: vec![RIGHTSHIFT], to: vec![RIGHTSHIFT], to_if_alone: vec![RIGHTSHIFT, K0], ..Default::default() },
+    ]);
+  }
+
+  #[test]
+  fn test_named_layouts() {
+    let layout_json = r#"{
+  "mappings": [
+    { "from": "F12", "to": { "action": "next_layout" } }
+  ],
+  "layouts": [
+    { "name": "qwerty", "mappings": [] },
+    { "name": "swapped", "mappings": [ { "from": "A", "to": "B" }, { "from": "F11", "to": { "action": "switch_layout", "layout": "qwerty" } } ] }
+  ]
+}"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    let simple_layout = convert(&fancy_layout).unwrap();
+    
+    let next_layout = s::Mapping { from: vec![F12], to: vec![], action: Some(s::Action::NextLayout), ..Default::default() };
+    assert_eq!(simple_layout.mappings, vec![next_layout.clone()]);
+    assert_eq!(simple_layout.named_layouts, vec![
+      s::NamedLayout { name: "qwerty".to_owned(), mappings: vec![next_layout.clone()] },
+      s::NamedLayout { name: "swapped".to_owned(), mappings: vec![
+        next_layout.clone(),
+        s::Mapping { from: vec![A], to: vec![B], ..Default::default() },
+        s::Mapping { from: vec![F11], to: vec![], action: Some(s::Action::SwitchLayout("qwerty".to_owned())), ..Default::default() },
+      ] },
+    ]);
+    
+    let layout_json = r#"{ "layouts": [ { "name": "qwerty", "mappings": [ { "from": "F11", "to": { "action": "switch_layout", "layout": "dvorak" } } ] } ] }"#;
+    let layout_v = serde_json::from_str(layout_json).unwrap();
+    let fancy_layout = crate::layout_parsing_formatting::parse_layout_from_json(&layout_v).unwrap();
+    assert!(convert(&fancy_layout).is_err());
+  }
+}
@@ -262,6 +262,11 @@ pub enum KeyCode {
   WWAN = 246,
   RFKILL = 247,
   MICMUTE = 248,
+  BTN_LEFT = 272,
+  BTN_RIGHT = 273,
+  BTN_MIDDLE = 274,
+  BTN_SIDE = 275,
+  BTN_EXTRA = 276,
   OK = 352,
   SELECT = 353,
   GOTO = 354,
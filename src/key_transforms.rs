@@ -1,10 +1,17 @@
 
 // vim: shiftwidth=2
 
-use crate::keys::{Layout, Mapping, KeyCode, Pressed, Released, Event, Repeat};
+use crate::keys::{Layout, NamedLayout, Mapping, KeyCode, Pressed, Released, Event, Repeat, Latch, LatchAction, MacroStep, Action, Mouse, Axis, Led};
 
 use std::collections::HashMap;
 
+pub const MOUSE_TICK_MS: i32 = 20;
+// Time for held mouse keys to reach full speed
+const MOUSE_ACCELERATION_MS: i32 = 1000;
+const MOUSE_MAX_SPEED: i32 = 4;
+// Scrolling happens every this many ticks
+const MOUSE_SCROLL_TICKS: i32 = 5;
+
 fn final_key(trigger: &Vec<KeyCode>) -> KeyCode {
   return trigger[trigger.len() - 1];
 }
@@ -18,6 +25,19 @@ fn is_supported(trigger: &Vec<KeyCode>, pressed_keys: &Vec<KeyCode>, absorbed_ke
   return true;
 }
 
+fn conditions_hold(mapping: &Mapping, pressed_keys: &[KeyCode], new_key: &KeyCode, leds_on: &[Led]) -> bool {
+  if pressed_keys.iter().any(|k| mapping.unless.contains(k)) {
+    return false;
+  }
+  if mapping.exclusive && pressed_keys.iter().any(|k| k != new_key && !mapping.from.contains(k)) {
+    return false;
+  }
+  if !mapping.if_led.iter().all(|led| leds_on.contains(led)) {
+    return false;
+  }
+  true
+}
+
 fn fails_when_released(trigger: &Vec<KeyCode>, key: &KeyCode) -> bool {
   for k in trigger {
     if k == key {
@@ -35,7 +55,54 @@ struct State {
   mapped_output_keys: Vec<KeyCode>,
   mapped_absorbed_keys: Vec<KeyCode>,
   absorbing_trigger: Option<KeyCode>,
-  repeating_trigger: Option<KeyCode>
+  repeating_trigger: Option<KeyCode>,
+  pending_tap: Option<PendingTap>,
+  pending_auto_shift: Option<PendingTap>,
+  pending_tap_dance: Option<PendingTapDance>,
+  if_alone: Option<PendingTap>,
+  one_shot_candidate: Option<KeyCode>,
+  latched_one_shot_keys: Vec<KeyCode>,
+  one_shot_trigger: Option<KeyCode>,
+  latched_layer_keys: Vec<KeyCode>,
+  combo_buffer: Vec<KeyCode>,
+  sequence_leader_candidate: Option<KeyCode>,
+  sequence_progress: Vec<KeyCode>,
+  caps_word: bool,
+  mouse_ticks: i32,
+  // Modifiers released by `clear_modifiers` mappings, whether held or
+  // output by other mappings
+  cleared_modifiers: Vec<KeyCode>,
+  paused: bool,
+  // Set when a `TogglePause` mapping is triggered, until the caller
+  // takes it
+  pause_toggled: bool,
+  // Set when a `SwitchLayout` or `NextLayout` mapping is triggered,
+  // until the caller takes it
+  layout_switch: Option<Action>,
+  leds_on: Vec<Led>,
+  // The rest of a macro, waiting for a `MacroStep::Delay` to pass, and
+  // the timer that the step which queued it asked for
+  delayed_events: Vec<Event>,
+  timer_after_delay: ResultingTimer
+}
+
+// A key with a tap or auto-shift mapping that has been pressed but not
+// yet decided between tap and hold, or one with `to_if_alone` that has
+// been pressed on its own so far.
+#[derive(Debug)]
+struct PendingTap {
+  key: KeyCode,
+  mapping: Mapping
+}
+
+// A key with several taps that is being tapped: how many times so far,
+// and whether it is down right now.
+#[derive(Debug)]
+struct PendingTapDance {
+  key: KeyCode,
+  mapping: Mapping,
+  count: usize,
+  held: bool
 }
 
 impl State {
@@ -48,18 +115,45 @@ impl State {
       mapped_absorbed_keys: Vec::new(),
       absorbing_trigger: None,
       repeating_trigger: None,
+      pending_tap: None,
+      pending_auto_shift: None,
+      pending_tap_dance: None,
+      if_alone: None,
+      one_shot_candidate: None,
+      latched_one_shot_keys: Vec::new(),
+      one_shot_trigger: None,
+      latched_layer_keys: Vec::new(),
+      combo_buffer: Vec::new(),
+      sequence_leader_candidate: None,
+      sequence_progress: Vec::new(),
+      caps_word: false,
+      mouse_ticks: 0,
+      cleared_modifiers: Vec::new(),
+      paused: false,
+      pause_toggled: false,
+      layout_switch: None,
+      leds_on: Vec::new(),
+      delayed_events: Vec::new(),
+      timer_after_delay: ResultingTimer::NoChange,
     };
   }
 }
 
 struct HashedLayout {
-  mappings: HashMap<KeyCode, Vec<Mapping>>
+  mappings: HashMap<KeyCode, Vec<Mapping>>,
+  combos: Vec<Mapping>,
+  sequences: Vec<Mapping>
 }
 
-fn make_hashed_layout(layout: &Layout) -> HashedLayout {
+fn make_hashed_layout(layout_mappings: &[Mapping]) -> HashedLayout {
   let mut mappings: HashMap<KeyCode, Vec<Mapping>> = HashMap::new();
 
-  for mapping in &layout.mappings {
+  for mapping in layout_mappings {
+    // A sequence may use the same key more than once
+    if mapping.sequence.is_some() {
+      continue;
+    }
+    
     for i in 0 .. mapping.from.len() {
       for j in i+1 .. mapping.from.len() {
         if mapping.from[i] == mapping.from[j] {
@@ -77,7 +171,20 @@ fn make_hashed_layout(layout: &Layout) -> HashedLayout {
     }
   }
   
-  for mapping in &layout.mappings {
+  let mut combos: Vec<Mapping> = Vec::new();
+  let mut sequences: Vec<Mapping> = Vec::new();
+  
+  for mapping in layout_mappings {
+    if mapping.combo.is_some() {
+      // Combos don't depend on the order of `from`
+      combos.push(mapping.clone());
+      continue;
+    }
+    if mapping.sequence.is_some() {
+      sequences.push(mapping.clone());
+      continue;
+    }
+    
     let last = final_key(&mapping.from);
     
     match mappings.get_mut(&last) {
@@ -90,11 +197,22 @@ fn make_hashed_layout(layout: &Layout) -> HashedLayout {
     }
   }
   
-  HashedLayout { mappings }
+  // Candidates are tried from the end, so the winner goes last: highest
+  // `priority`, then most keys in `from`, then a conditional mapping over
+  // an unconditional one. Layouts that would still need the order in the
+  // file to pick between two mappings are rejected when they are loaded.
+  for candidates in mappings.values_mut() {
+    candidates.sort_by_key(|m| (m.priority, m.from.len(), m.exclusive || !m.unless.is_empty() || !m.if_led.is_empty()));
+  }
+  
+  HashedLayout { mappings, combos, sequences }
 }
 
 pub struct Mapper {
   layout: HashedLayout,
+  named_layouts: Vec<NamedLayout>,
+  active_layout_name: Option<String>,
+  switch_all_keyboards: bool,
   state: State
 }
 
@@ -109,22 +227,37 @@ pub enum ResultingRepeat {
   },
 }
 
+// Asks the caller to call `Mapper::timeout()` once `delay_ms` has passed.
+#[derive(Debug, Eq, PartialEq)]
+pub enum ResultingTimer {
+  Disabled,
+  NoChange,
+  After {
+    delay_ms: i32
+  }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct StepResult {
   pub events: Vec<Event>,
-  pub repeat: ResultingRepeat
+  pub repeat: ResultingRepeat,
+  pub timer: ResultingTimer
 }
 
 impl StepResult {
   fn empty() -> StepResult {
     StepResult {
       events: vec![],
-      repeat: ResultingRepeat::Disabled
+      repeat: ResultingRepeat::Disabled,
+      timer: ResultingTimer::NoChange
     }
   }
   
   fn append(&mut self, mut other: StepResult) {
     self.repeat = other.repeat;
+    if other.timer != ResultingTimer::NoChange {
+      self.timer = other.timer;
+    }
     self.events.append(&mut other.events);
   }
 }
@@ -132,44 +265,188 @@ impl StepResult {
 impl Mapper {
   pub fn for_layout(layout: &Layout) -> Mapper {
     Mapper {
-      layout: make_hashed_layout(layout),
+      layout: make_hashed_layout(&layout.mappings),
+      named_layouts: layout.named_layouts.clone(),
+      active_layout_name: layout.named_layouts.first().map(|named| named.name.clone()),
+      switch_all_keyboards: layout.switch_all_keyboards,
       state: State::init()
     }
   }
   
+  // For a new layout, after `release_all()`. LEDs and pausing carry over,
+  // and so does the named layout in use if the new layout still has it.
+  pub fn replace_layout(self: &mut Mapper, layout: &Layout) {
+    let previous_name = self.active_layout_name.take();
+    
+    self.layout = make_hashed_layout(&layout.mappings);
+    self.named_layouts = layout.named_layouts.clone();
+    self.active_layout_name = layout.named_layouts.first().map(|named| named.name.clone());
+    self.switch_all_keyboards = layout.switch_all_keyboards;
+    
+    if let Some(name) = previous_name {
+      self.select_layout(&name);
+    }
+  }
+  
   pub fn step(self: &mut Mapper, input: Event) -> StepResult {
-    let state = &mut self.state;
-
-    match input {
-      Pressed(k) => {
-        if !state.input_pressed_keys.contains(&k) {
-          newly_press(self, k)
-        }
-        else {
-          StepResult {
-            events: vec![],
-            repeat: ResultingRepeat::NoChange
-          }
+    if self.state.paused {
+      return step_paused(self, input);
+    }
+    
+    let shift_held = is_output_shift_held(&self.state);
+    let mut res = step_key(self, input);
+    res.events = apply_caps_word(&mut self.state, shift_held, res.events);
+    delay_macro_rest(&mut self.state, res)
+  }
+  
+  // Called when the delay from the last `ResultingTimer::After` has passed.
+  pub fn timeout(self: &mut Mapper) -> StepResult {
+    if !self.state.delayed_events.is_empty() {
+      let res = StepResult {
+        events: std::mem::take(&mut self.state.delayed_events),
+        repeat: ResultingRepeat::NoChange,
+        timer: std::mem::replace(&mut self.state.timer_after_delay, ResultingTimer::NoChange)
+      };
+      return delay_macro_rest(&mut self.state, res);
+    }
+    
+    let shift_held = is_output_shift_held(&self.state);
+    let mut res = {
+      if !self.state.combo_buffer.is_empty() {
+        flush_combo_buffer(self)
+      }
+      else if !self.state.sequence_progress.is_empty() {
+        self.state.sequence_progress.clear();
+        StepResult {
+          events: vec![],
+          repeat: ResultingRepeat::NoChange,
+          timer: ResultingTimer::Disabled
         }
+      }
+      else if self.state.pending_auto_shift.is_some() {
+        resolve_pending_auto_shift(&mut self.state, true)
+      }
+      else if self.state.pending_tap_dance.is_some() {
+        resolve_pending_tap_dance(&mut self.state)
+      }
+      else {
+        resolve_pending_tap(&mut self.state)
+      }
+    };
+    res.events = apply_caps_word(&mut self.state, shift_held, res.events);
+    res
+  }
+  
+  // How often `mouse_tick()` should be called, or `None` if no mouse
+  // mapping is held.
+  pub fn mouse_interval_ms(self: &Mapper) -> Option<i32> {
+    if held_mouse(&self.state).is_empty() {
+      None
+    }
+    else {
+      Some(MOUSE_TICK_MS)
+    }
+  }
+  
+  pub fn mouse_tick(self: &mut Mapper) -> Vec<Event> {
+    let held = held_mouse(&self.state);
+    if held.is_empty() {
+      self.state.mouse_ticks = 0;
+      vec![]
+    }
+    else {
+      self.state.mouse_ticks += 1;
+      mouse_events(&held, self.state.mouse_ticks)
+    }
+  }
+  
+  pub fn is_paused(self: &Mapper) -> bool {
+    self.state.paused
+  }
+  
+  // Whether a `TogglePause` mapping was triggered since the last call.
+  // The caller should then `release_all()` and flip `set_paused()`.
+  pub fn take_pause_toggle(self: &mut Mapper) -> bool {
+    std::mem::take(&mut self.state.pause_toggled)
+  }
+  
+  pub fn set_paused(self: &mut Mapper, paused: bool) {
+    self.state.paused = paused;
+  }
+  
+  // The named layout in use, if the layout has several
+  pub fn active_layout_name(self: &Mapper) -> Option<&str> {
+    self.active_layout_name.as_deref()
+  }
+  
+  pub fn switches_all_keyboards(self: &Mapper) -> bool {
+    self.switch_all_keyboards
+  }
+  
+  // The named layout a `SwitchLayout` or `NextLayout` mapping asked for
+  // since the last call. The caller should then `release_all()` and
+  // `select_layout()`.
+  pub fn take_layout_switch(self: &mut Mapper) -> Option<String> {
+    match self.state.layout_switch.take() {
+      Some(Action::SwitchLayout(name)) => Some(name),
+      Some(Action::NextLayout) => {
+        let current = self.named_layouts.iter().position(|named| Some(&named.name) == self.active_layout_name.as_ref());
+        let next = match current {
+          None => 0,
+          Some(i) => (i + 1) % self.named_layouts.len()
+        };
+        self.named_layouts.get(next).map(|named| named.name.clone())
       },
-      Released(k) => {
-        if state.input_pressed_keys.contains(&k) {
-          newly_release(self, k)
-        }
-        else {
-          StepResult {
-            events: vec![],
-            repeat: ResultingRepeat::NoChange
-          }
-        }
+      _ => None
+    }
+  }
+  
+  // Returns false if there is no named layout called `name`
+  pub fn select_layout(self: &mut Mapper, name: &str) -> bool {
+    match self.named_layouts.iter().find(|named| named.name == name) {
+      None => false,
+      Some(named) => {
+        self.layout = make_hashed_layout(&named.mappings);
+        self.active_layout_name = Some(named.name.clone());
+        true
       }
     }
   }
   
+  // Called when the virtual keyboard's LEDs change, for `if_led`.
+  pub fn set_led(self: &mut Mapper, led: Led, on: bool) {
+    self.state.leds_on.retain(|l| *l != led);
+    if on {
+      self.state.leds_on.push(led);
+    }
+  }
+  
   pub fn release_all(self: &mut Mapper) -> Vec<Event> {
-    let to_release = self.state.input_pressed_keys.clone();
+    // Letting go of everything should not count as a tap
+    self.state.pending_tap = None;
+    self.state.pending_auto_shift = None;
+    self.state.pending_tap_dance = None;
+    self.state.if_alone = None;
+    self.state.one_shot_candidate = None;
+    self.state.latched_one_shot_keys.clear();
+    self.state.one_shot_trigger = None;
+    self.state.combo_buffer.clear();
+    self.state.sequence_leader_candidate = None;
+    self.state.sequence_progress.clear();
+    self.state.caps_word = false;
+    self.state.cleared_modifiers.clear();
     
-    let mut events: Vec<Event> = Vec::new();
+    // The rest of a macro goes out now instead of after its delay
+    let mut events = std::mem::take(&mut self.state.delayed_events);
+    self.state.timer_after_delay = ResultingTimer::NoChange;
+    
+    // Mappings kept active by a latch, not by any key that is down
+    let latched = std::mem::take(&mut self.state.latched_layer_keys);
+    for k in latched {
+      events.append(&mut remove_mappings_using(&mut self.state, k));
+    }
+    
+    let to_release = self.state.input_pressed_keys.clone();
     
     for k in to_release {
       let mut chunk = self.step(Released(k));
@@ -180,6 +457,185 @@ impl Mapper {
   }
 }
 
+// While paused every key goes out unchanged; only a `TogglePause`
+// mapping is still looked for, so that remapping can be resumed.
+fn step_paused(mapper: &mut Mapper, input: Event) -> StepResult {
+  let state = &mut mapper.state;
+  let mut res = StepResult::empty();
+  
+  match input {
+    Pressed(k) => {
+      let resumes = match mapper.layout.mappings.get(&k) {
+        None => false,
+        Some(mappings) => mappings.iter().any(|m| {
+          m.action == Some(Action::TogglePause)
+            && is_supported(&m.from, &state.input_pressed_keys, &vec![], &k)
+            && conditions_hold(m, &state.input_pressed_keys, &k, &state.leds_on)
+        })
+      };
+      if resumes {
+        state.pause_toggled = true;
+      }
+      else {
+        if !state.input_pressed_keys.contains(&k) {
+          state.input_pressed_keys.push(k);
+        }
+        res.events.push(Pressed(k));
+      }
+    },
+    Released(k) => {
+      // Keys held from before pausing were already released
+      if state.input_pressed_keys.contains(&k) {
+        state.input_pressed_keys.retain(|k2| *k2 != k);
+        res.events.push(Released(k));
+      }
+    },
+    other => res.events.push(other)
+  }
+  
+  res
+}
+
+fn step_key(mapper: &mut Mapper, input: Event) -> StepResult {
+  match input {
+    Pressed(k) => {
+      let mut res = StepResult::empty();
+      
+      // `to_if_alone` only applies if nothing else was pressed meanwhile
+      mapper.state.if_alone = None;
+      
+      if !mapper.state.sequence_progress.is_empty() {
+        match advance_sequence(mapper, k) {
+          Some(consumed) => return consumed,
+          None => res.timer = ResultingTimer::Disabled
+        }
+      }
+      
+      mapper.state.sequence_leader_candidate = {
+        if is_sequence_leader(&mapper.layout, &k) { Some(k) } else { None }
+      };
+      
+      res.append(press_key(mapper, k));
+      res
+    },
+    Released(k) => {
+      let mut res = release_key(mapper, k);
+      
+      if mapper.state.sequence_leader_candidate == Some(k) {
+        // The leader was tapped on its own, so start a sequence
+        mapper.state.sequence_leader_candidate = None;
+        mapper.state.sequence_progress = vec![k];
+        if let Some(timeout_ms) = sequence_timeout_ms(&mapper.layout, &mapper.state.sequence_progress) {
+          res.timer = ResultingTimer::After { delay_ms: timeout_ms };
+        }
+      }
+      
+      res
+    },
+    Event::Moved { .. } | Event::Sync { .. } => StepResult::empty()
+  }
+}
+
+fn held_mouse(state: &State) -> Vec<Mouse> {
+  state.active_mappings.iter().filter_map(|m| m.mouse).collect()
+}
+
+fn mouse_events(held: &Vec<Mouse>, ticks: i32) -> Vec<Event> {
+  let elapsed_ms = ticks.saturating_mul(MOUSE_TICK_MS).min(MOUSE_ACCELERATION_MS);
+  // Saturates rather than overflowing for huge amounts
+  let accelerate = |v: i32| {
+    let speed = (MOUSE_ACCELERATION_MS + (MOUSE_MAX_SPEED - 1) * elapsed_ms) as i64;
+    (v as i64 * speed / MOUSE_ACCELERATION_MS as i64).clamp(i32::MIN as i64, i32::MAX as i64) as i32
+  };
+  
+  let (mut move_x, mut move_y, mut scroll_x, mut scroll_y): (i32, i32, i32, i32) = (0, 0, 0, 0);
+  for m in held {
+    match m {
+      Mouse::Move { x, y } => { move_x = move_x.saturating_add(*x); move_y = move_y.saturating_add(*y); },
+      Mouse::Scroll { x, y } => { scroll_x = scroll_x.saturating_add(*x); scroll_y = scroll_y.saturating_add(*y); }
+    }
+  }
+  
+  let mut events: Vec<Event> = Vec::new();
+  
+  if move_x != 0 {
+    events.push(Event::Moved { axis: Axis::X, delta: accelerate(move_x) });
+  }
+  if move_y != 0 {
+    events.push(Event::Moved { axis: Axis::Y, delta: accelerate(move_y) });
+  }
+  
+  // Scrolling doesn't speed up, since each notch is already a big step
+  if ticks % MOUSE_SCROLL_TICKS == 0 {
+    if scroll_y != 0 {
+      // The wheel axis counts up as positive
+      events.push(Event::Moved { axis: Axis::Wheel, delta: scroll_y.saturating_neg() });
+    }
+    if scroll_x != 0 {
+      events.push(Event::Moved { axis: Axis::HWheel, delta: scroll_x });
+    }
+  }
+  
+  events
+}
+
+fn is_shift_key(k: &KeyCode) -> bool {
+  *k == KeyCode::LEFTSHIFT || *k == KeyCode::RIGHTSHIFT
+}
+
+fn is_output_shift_held(state: &State) -> bool {
+  state.pass_through_keys.iter().chain(state.mapped_output_keys.iter()).any(is_shift_key)
+}
+
+fn is_letter_key(k: &KeyCode) -> bool {
+  use KeyCode::*;
+  
+  matches!(k,
+    A | B | C | D | E | F | G | H | I | J | K | L | M |
+    N | O | P | Q | R | S | T | U | V | W | X | Y | Z
+  )
+}
+
+// Keys that can appear inside a word without ending caps word
+fn continues_caps_word(k: &KeyCode) -> bool {
+  use KeyCode::*;
+  
+  match k {
+    K1 | K2 | K3 | K4 | K5 | K6 | K7 | K8 | K9 | K0 => true,
+    MINUS | BACKSPACE | DELETE => true,
+    _ => !is_action_key(k)
+  }
+}
+
+// While caps word is on, shift each letter that isn't already shifted.
+// Any other key that doesn't belong in a word turns it off.
+fn apply_caps_word(state: &mut State, shift_held: bool, events: Vec<Event>) -> Vec<Event> {
+  if !state.caps_word {
+    return events;
+  }
+  
+  let mut shift_held = shift_held;
+  let mut res: Vec<Event> = Vec::new();
+  
+  for ev in events {
+    match ev {
+      Pressed(k) if is_shift_key(&k) => shift_held = true,
+      Released(k) if is_shift_key(&k) => shift_held = is_output_shift_held(state),
+      Pressed(k) if state.caps_word && is_letter_key(&k) && !shift_held => {
+        res.push(Pressed(KeyCode::LEFTSHIFT));
+        res.push(ev);
+        res.push(Released(KeyCode::LEFTSHIFT));
+        continue;
+      },
+      Pressed(k) if !is_letter_key(&k) && !continues_caps_word(&k) => state.caps_word = false,
+      _ => ()
+    };
+    res.push(ev);
+  }
+  
+  res
+}
+
 fn is_action_key(k: &KeyCode) -> bool {
   use KeyCode::{LEFTSHIFT, RIGHTSHIFT, LEFTMETA, RIGHTMETA, LEFTCTRL, RIGHTCTRL, LEFTALT, RIGHTALT};
   
@@ -197,7 +653,10 @@ fn is_action_key(k: &KeyCode) -> bool {
 }
 
 fn is_action_mapping(m: &Mapping) -> bool {
-  if m.to.len() == 0 {
+  if !m.macro_steps.is_empty() {
+    true
+  }
+  else if m.to.is_empty() {
     false
   }
   else {
@@ -261,6 +720,10 @@ fn add_new_mapping(state: &mut State, new_key: &KeyCode, m: &Mapping) -> StepRes
     }
   });
   
+  if m.clear_modifiers {
+    events.append(&mut clear_modifiers(state, m));
+  }
+  
   if is_action_mapping(m) {
     events.append(&mut release_action_mappings(state));
     let should_absorb = {
@@ -301,6 +764,10 @@ fn add_new_mapping(state: &mut State, new_key: &KeyCode, m: &Mapping) -> StepRes
     }
   }
   
+  if !m.macro_steps.is_empty() {
+    events.append(&mut macro_events(&m.macro_steps, !events.is_empty()));
+  }
+  
   for absorbed_key in &m.absorbing {
     if !state.mapped_absorbed_keys.contains(absorbed_key) {
       state.mapped_absorbed_keys.push(*absorbed_key);
@@ -314,7 +781,8 @@ fn add_new_mapping(state: &mut State, new_key: &KeyCode, m: &Mapping) -> StepRes
   
   let mut res = StepResult {
     events,
-    repeat: ResultingRepeat::Disabled
+    repeat: ResultingRepeat::Disabled,
+    timer: ResultingTimer::NoChange
   };
   
   match &m.repeat {
@@ -339,12 +807,125 @@ fn add_new_mapping(state: &mut State, new_key: &KeyCode, m: &Mapping) -> StepRes
       // Save the key that triggered it so we can stop
       // the mapping when the key is released
       state.repeating_trigger = Some(*new_key);
+    },
+    Repeat::AutoShift { .. } => {
+      // Only applies when the key is pressed without a tap; see `newly_press`
     }
   };
         
   res
 }
 
+fn macro_events(steps: &Vec<MacroStep>, after_other_events: bool) -> Vec<Event> {
+  let mut events: Vec<Event> = Vec::new();
+  
+  if after_other_events {
+    events.push(Event::Sync { delay_ms: 0 });
+  }
+  
+  for step in steps {
+    match step {
+      MacroStep::Tap(keys) => {
+        events.extend(keys.iter().map(|k| Pressed(*k)));
+        events.extend(keys.iter().rev().map(|k| Released(*k)));
+        events.push(Event::Sync { delay_ms: 0 });
+      },
+      MacroStep::Delay(delay_ms) => {
+        match events.last_mut() {
+          Some(Event::Sync { delay_ms: d }) => *d += delay_ms,
+          _ => events.push(Event::Sync { delay_ms: *delay_ms })
+        }
+      }
+    }
+  }
+  
+  events
+}
+
+// Holds back what comes after a macro delay for `timeout()` to send, so
+// that the caller never has to sleep.
+fn delay_macro_rest(state: &mut State, mut res: StepResult) -> StepResult {
+  if !state.delayed_events.is_empty() {
+    // Still waiting; this goes out after the rest of the macro
+    state.delayed_events.append(&mut res.events);
+    match std::mem::replace(&mut res.timer, ResultingTimer::NoChange) {
+      ResultingTimer::NoChange => (),
+      timer => state.timer_after_delay = timer
+    }
+    return res;
+  }
+  
+  let delay = res.events.iter().enumerate().find_map(|(i, ev)| match ev {
+    Event::Sync { delay_ms } if *delay_ms > 0 => Some((i, *delay_ms)),
+    _ => None
+  });
+  
+  if let Some((i, delay_ms)) = delay {
+    state.delayed_events = res.events.split_off(i + 1);
+    res.events[i] = Event::Sync { delay_ms: 0 };
+    if !state.delayed_events.is_empty() {
+      state.timer_after_delay = std::mem::replace(&mut res.timer, ResultingTimer::After { delay_ms });
+    }
+  }
+  
+  res
+}
+
+fn clear_modifiers(state: &mut State, m: &Mapping) -> Vec<Event> {
+  let mut events: Vec<Event> = Vec::new();
+  let cleared_modifiers = &mut state.cleared_modifiers;
+  
+  state.pass_through_keys.retain(|k| {
+    if !is_action_key(k) && !m.to.contains(k) {
+      events.push(Released(*k));
+      cleared_modifiers.push(*k);
+      false
+    }
+    else {
+      true
+    }
+  });
+  
+  state.mapped_output_keys.retain(|k| {
+    if !is_action_key(k) && !m.to.contains(k) {
+      events.push(Released(*k));
+      cleared_modifiers.push(*k);
+      false
+    }
+    else {
+      true
+    }
+  });
+  
+  events
+}
+
+// Once no `clear_modifiers` mapping is left, modifiers that are still
+// output by another mapping or physically held come back
+fn restore_cleared_modifiers(state: &mut State, removed_key: KeyCode) -> Vec<Event> {
+  let mut events: Vec<Event> = Vec::new();
+  
+  if state.active_mappings.iter().any(|m| m.clear_modifiers) {
+    return events;
+  }
+  
+  for k in std::mem::take(&mut state.cleared_modifiers) {
+    if state.pass_through_keys.contains(&k) || state.mapped_output_keys.contains(&k) {
+      continue;
+    }
+    if state.active_mappings.iter().any(|m| m.to.contains(&k)) {
+      events.push(Pressed(k));
+      state.mapped_output_keys.push(k);
+    }
+    else if state.input_pressed_keys.contains(&k) && k != removed_key {
+      events.push(Pressed(k));
+      state.pass_through_keys.push(k);
+    }
+  }
+  
+  events
+}
+
 fn release_all_action_keys(state: &mut State) -> Vec<Event> {
   let mut to_release: Vec<KeyCode> = Vec::new();
   
@@ -379,43 +960,445 @@ fn release_absorbed_keys(state: &mut State) -> Vec<Event> {
   state.absorbing_trigger = None;
   
   for k in to_remove {
-    {
-      let mut i: isize = state.active_mappings.len() as isize - 1;
-      while i >= 0 {
-        if fails_when_released(&state.active_mappings[i as usize].from, &k) {
-          events.append(&mut remove_mapping(state, i as usize, k));
-        }
-        i -= 1;
-      }
-    }
-    
-    for i in (0 .. state.pass_through_keys.len()).rev() {
-      if state.pass_through_keys[i] == k {
-        events.push(Released(k));
-        state.pass_through_keys.remove(i);
-        break;
-      }
-    }
-    
-    state.input_pressed_keys.retain(|k2| *k2 != k);
+    events.append(&mut release_input_key(state, k));
   }
   
   events
 }
 
-fn newly_press(mapper: &mut Mapper, k: KeyCode) -> StepResult {
-  let mappings = &mapper.layout.mappings;
-  let mut state = &mut mapper.state;
+fn release_one_shot_keys(state: &mut State) -> Vec<Event> {
+  let mut events: Vec<Event> = Vec::new();
   
-  let mut res: StepResult = StepResult::empty();
+  let mut to_remove: Vec<KeyCode> = Vec::new();
+  to_remove.append(&mut state.latched_one_shot_keys);
+  state.one_shot_trigger = None;
   
-  let mut any_hit: bool = false;
+  for k in to_remove {
+    events.append(&mut release_input_key(state, k));
+  }
   
-  state.mapped_absorbed_keys.retain(|k2| *k2 != k);
-  state.repeating_trigger = None;
+  events
+}
+
+fn release_one_shot_key(state: &mut State, k: KeyCode) -> Vec<Event> {
+  state.latched_one_shot_keys.retain(|k2| *k2 != k);
+  if state.latched_one_shot_keys.is_empty() {
+    state.one_shot_trigger = None;
+  }
   
-  if let Some(mappings) = mappings.get(&k) {
-    let should_absorb = {
+  release_input_key(state, k)
+}
+
+fn release_input_key(state: &mut State, k: KeyCode) -> Vec<Event> {
+  let mut events: Vec<Event> = Vec::new();
+  
+  // Mappings using a latched key stay active
+  if !state.latched_layer_keys.contains(&k) {
+    events.append(&mut remove_mappings_using(state, k));
+  }
+  
+  for i in (0 .. state.pass_through_keys.len()).rev() {
+    if state.pass_through_keys[i] == k {
+      events.push(Released(k));
+      state.pass_through_keys.remove(i);
+      break;
+    }
+  }
+  
+  state.input_pressed_keys.retain(|k2| *k2 != k);
+  
+  events
+}
+
+fn remove_mappings_using(state: &mut State, k: KeyCode) -> Vec<Event> {
+  let mut events: Vec<Event> = Vec::new();
+  
+  let mut i: isize = state.active_mappings.len() as isize - 1;
+  while i >= 0 {
+    if fails_when_released(&state.active_mappings[i as usize].from, &k) {
+      events.append(&mut remove_mapping(state, i as usize, k));
+    }
+    i -= 1;
+  }
+  
+  events
+}
+
+fn apply_latch(state: &mut State, latch: &Latch) -> Vec<Event> {
+  let all_latched = latch.keys.iter().all(|k| state.latched_layer_keys.contains(k));
+  
+  let should_latch = match latch.action {
+    LatchAction::Toggle => !all_latched,
+    LatchAction::Lock => true,
+    LatchAction::Clear => false
+  };
+  
+  let mut events: Vec<Event> = Vec::new();
+  
+  for k in &latch.keys {
+    if should_latch {
+      if !state.latched_layer_keys.contains(k) {
+        state.latched_layer_keys.push(*k);
+      }
+    }
+    else if state.latched_layer_keys.contains(k) {
+      state.latched_layer_keys.retain(|k2| k2 != k);
+      if !state.input_pressed_keys.contains(k) {
+        events.append(&mut remove_mappings_using(state, *k));
+      }
+    }
+  }
+  
+  events
+}
+
+// A mapping from a single key to itself only exists to carry
+// options like `tap` or `one_shot`.
+fn is_identity(m: &Mapping) -> bool {
+  m.from.len() == 1 && m.to == m.from
+}
+
+fn resolve_pending_tap(state: &mut State) -> StepResult {
+  match state.pending_tap.take() {
+    None => StepResult::empty(),
+    Some(pending) => {
+      let mut res = {
+        if is_identity(&pending.mapping) {
+          // Holding a key that maps to itself is the same as having no mapping
+          state.pass_through_keys.push(pending.key);
+          StepResult {
+            events: vec![Pressed(pending.key)],
+            repeat: ResultingRepeat::Disabled,
+            timer: ResultingTimer::NoChange
+          }
+        }
+        else {
+          add_new_mapping(state, &pending.key, &pending.mapping)
+        }
+      };
+      res.timer = ResultingTimer::Disabled;
+      res
+    }
+  }
+}
+
+// Types the pending key's output once, shifted if it was held long enough
+fn resolve_pending_auto_shift(state: &mut State, held: bool) -> StepResult {
+  match state.pending_auto_shift.take() {
+    None => StepResult::empty(),
+    Some(pending) => {
+      let keys = match &pending.mapping.repeat {
+        Repeat::AutoShift { keys, delay_ms: _ } if held => keys.clone(),
+        _ => pending.mapping.to.clone()
+      };
+      
+      StepResult {
+        events: type_once(state, keys),
+        repeat: ResultingRepeat::Disabled,
+        timer: ResultingTimer::Disabled
+      }
+    }
+  }
+}
+
+fn type_once(state: &State, keys: Vec<KeyCode>) -> Vec<Event> {
+  // Keys that are already held, like a physical Shift, stay held
+  let keys: Vec<KeyCode> = keys.into_iter()
+    .filter(|k| !state.pass_through_keys.contains(k) && !state.mapped_output_keys.contains(k))
+    .collect();
+  
+  let mut events: Vec<Event> = keys.iter().map(|k| Pressed(*k)).collect();
+  events.extend(keys.iter().rev().map(|k| Released(*k)));
+  events
+}
+
+fn tap_dance_outputs(mapping: &Mapping) -> Vec<Vec<KeyCode>> {
+  match &mapping.tap {
+    None => vec![],
+    Some(tap) => std::iter::once(tap.keys.clone()).chain(tap.extra_taps.iter().cloned()).collect()
+  }
+}
+
+fn tapping_term_ms(mapping: &Mapping) -> i32 {
+  mapping.tap.as_ref().map(|tap| tap.tapping_term_ms).unwrap_or(0)
+}
+
+// Types the output for the number of taps so far, or if the key is still
+// held after the first press, applies the mapping like a held tap key.
+fn resolve_pending_tap_dance(state: &mut State) -> StepResult {
+  match state.pending_tap_dance.take() {
+    None => StepResult::empty(),
+    Some(dance) => {
+      if dance.held && dance.count == 1 {
+        state.pending_tap = Some(PendingTap { key: dance.key, mapping: dance.mapping });
+        resolve_pending_tap(state)
+      }
+      else {
+        let outputs = tap_dance_outputs(&dance.mapping);
+        let keys = outputs[dance.count.min(outputs.len()) - 1].clone();
+        
+        StepResult {
+          events: type_once(state, keys),
+          repeat: ResultingRepeat::Disabled,
+          timer: ResultingTimer::Disabled
+        }
+      }
+    }
+  }
+}
+
+fn tap_pending(state: &mut State, k: KeyCode) -> StepResult {
+  let mut events: Vec<Event> = Vec::new();
+  
+  if let Some(pending) = state.pending_tap.take() {
+    if let Some(tap) = &pending.mapping.tap {
+      for tap_key in &tap.keys {
+        events.push(Pressed(*tap_key));
+      }
+      for tap_key in tap.keys.iter().rev() {
+        events.push(Released(*tap_key));
+      }
+    }
+  }
+  
+  state.input_pressed_keys.retain(|&old_key| {
+    old_key != k
+  });
+  
+  StepResult {
+    events,
+    repeat: ResultingRepeat::Disabled,
+    timer: ResultingTimer::Disabled
+  }
+}
+
+fn press_key(mapper: &mut Mapper, k: KeyCode) -> StepResult {
+  let state = &mut mapper.state;
+  
+  if state.latched_one_shot_keys.contains(&k) {
+    // Pressing a latched one-shot key again cancels the latch and
+    // leaves it acting as an ordinary held key.
+    let mut events = release_one_shot_key(state, k);
+    let mut res = newly_press(mapper, k);
+    mapper.state.one_shot_candidate = None;
+    events.append(&mut res.events);
+    res.events = events;
+    res
+  }
+  else if state.input_pressed_keys.contains(&k) || state.combo_buffer.contains(&k) {
+    StepResult {
+      events: vec![],
+      repeat: ResultingRepeat::NoChange,
+      timer: ResultingTimer::NoChange
+    }
+  }
+  else if !state.combo_buffer.is_empty() || is_combo_key(&mapper.layout, &k) {
+    press_with_combos(mapper, k)
+  }
+  else {
+    newly_press(mapper, k)
+  }
+}
+
+fn release_key(mapper: &mut Mapper, k: KeyCode) -> StepResult {
+  let state = &mut mapper.state;
+  
+  if !state.combo_buffer.is_empty() {
+    // Any release ends the chance of a combo
+    let mut res = flush_combo_buffer(mapper);
+    if mapper.state.input_pressed_keys.contains(&k) {
+      res.append(newly_release(mapper, k));
+    }
+    res
+  }
+  else if state.input_pressed_keys.contains(&k) {
+    newly_release(mapper, k)
+  }
+  else {
+    StepResult {
+      events: vec![],
+      repeat: ResultingRepeat::NoChange,
+      timer: ResultingTimer::NoChange
+    }
+  }
+}
+
+fn is_sequence_leader(layout: &HashedLayout, k: &KeyCode) -> bool {
+  layout.sequences.iter().any(|m| m.from[0] == *k)
+}
+
+fn sequence_timeout_ms(layout: &HashedLayout, progress: &[KeyCode]) -> Option<i32> {
+  layout.sequences.iter()
+    .filter(|m| m.from.starts_with(progress))
+    .filter_map(|m| m.sequence.as_ref().map(|seq| seq.timeout_ms))
+    .min()
+}
+
+// Returns `None` if `k` doesn't continue any sequence, in which case
+// the sequence is abandoned and `k` should be handled as usual.
+fn advance_sequence(mapper: &mut Mapper, k: KeyCode) -> Option<StepResult> {
+  let sequences = &mapper.layout.sequences;
+  let state = &mut mapper.state;
+  
+  let mut progress = state.sequence_progress.clone();
+  progress.push(k);
+  state.sequence_progress.clear();
+  
+  if let Some(done) = sequences.iter().find(|m| m.from == progress) {
+    let mut events: Vec<Event> = done.to.iter().map(|k2| Pressed(*k2)).collect();
+    events.extend(done.to.iter().rev().map(|k2| Released(*k2)));
+    Some(StepResult {
+      events,
+      repeat: ResultingRepeat::NoChange,
+      timer: ResultingTimer::Disabled
+    })
+  }
+  else if let Some(timeout_ms) = sequence_timeout_ms(&mapper.layout, &progress) {
+    state.sequence_progress = progress;
+    Some(StepResult {
+      events: vec![],
+      repeat: ResultingRepeat::NoChange,
+      timer: ResultingTimer::After { delay_ms: timeout_ms }
+    })
+  }
+  else if k == KeyCode::ESC {
+    // Cancels the sequence without typing anything
+    Some(StepResult {
+      events: vec![],
+      repeat: ResultingRepeat::NoChange,
+      timer: ResultingTimer::Disabled
+    })
+  }
+  else {
+    None
+  }
+}
+
+fn is_combo_key(layout: &HashedLayout, k: &KeyCode) -> bool {
+  layout.combos.iter().any(|m| m.from.contains(k))
+}
+
+fn is_same_key_set(a: &[KeyCode], b: &[KeyCode]) -> bool {
+  a.len() == b.len() && a.iter().all(|k| b.contains(k))
+}
+
+fn press_with_combos(mapper: &mut Mapper, k: KeyCode) -> StepResult {
+  let combos = &mapper.layout.combos;
+  let state = &mut mapper.state;
+  
+  let mut res: StepResult = resolve_pending_tap(state);
+  res.append(resolve_pending_auto_shift(state, false));
+  res.append(resolve_pending_tap_dance(state));
+  
+  let mut candidate = state.combo_buffer.clone();
+  candidate.push(k);
+  
+  let window_ms = combos.iter()
+    .filter(|m| candidate.iter().all(|k2| m.from.contains(k2)))
+    .filter_map(|m| m.combo.as_ref().map(|c| c.window_ms))
+    .min();
+  
+  if let Some(combo) = combos.iter().find(|m| is_same_key_set(&m.from, &candidate)) {
+    state.combo_buffer.clear();
+    res.append(activate_combo(state, combo, k));
+    res.timer = ResultingTimer::Disabled;
+  }
+  else if let Some(window_ms) = window_ms {
+    // Wait to see whether the rest of a combo follows
+    if state.combo_buffer.is_empty() {
+      res.timer = ResultingTimer::After { delay_ms: window_ms };
+    }
+    state.combo_buffer.push(k);
+  }
+  else {
+    res.append(flush_combo_buffer(mapper));
+    if is_combo_key(&mapper.layout, &k) {
+      res.append(press_with_combos(mapper, k));
+    }
+    else {
+      res.append(newly_press(mapper, k));
+    }
+  }
+  
+  res
+}
+
+fn flush_combo_buffer(mapper: &mut Mapper) -> StepResult {
+  let mut res = StepResult::empty();
+  res.timer = ResultingTimer::Disabled;
+  
+  let buffered: Vec<KeyCode> = mapper.state.combo_buffer.drain(..).collect();
+  for k in buffered {
+    res.append(newly_press(mapper, k));
+  }
+  
+  res
+}
+
+fn activate_combo(state: &mut State, combo: &Mapping, k: KeyCode) -> StepResult {
+  let mut events: Vec<Event> = Vec::new();
+  
+  if state.one_shot_trigger.is_some() {
+    events.append(&mut release_one_shot_keys(state));
+  }
+  state.one_shot_candidate = None;
+  
+  for k2 in &combo.from {
+    if *k2 != k {
+      state.input_pressed_keys.push(*k2);
+    }
+  }
+  
+  let mut res = add_new_mapping(state, &k, combo);
+  state.input_pressed_keys.push(k);
+  
+  if is_action_mapping(combo) && !state.latched_one_shot_keys.is_empty() {
+    state.one_shot_trigger = Some(k);
+  }
+  
+  events.append(&mut res.events);
+  res.events = events;
+  res
+}
+
+fn newly_press(mapper: &mut Mapper, k: KeyCode) -> StepResult {
+  let mappings = &mapper.layout.mappings;
+  let mut state = &mut mapper.state;
+  
+  if let Some(dance) = &mut state.pending_tap_dance {
+    if dance.key == k {
+      // Tapped again within the tapping term
+      dance.count += 1;
+      dance.held = true;
+      let delay_ms = tapping_term_ms(&dance.mapping);
+      state.input_pressed_keys.push(k);
+      return StepResult {
+        events: vec![],
+        repeat: ResultingRepeat::Disabled,
+        timer: ResultingTimer::After { delay_ms }
+      };
+    }
+  }
+  
+  // Pressing any other key means a pending tap key is being held
+  let mut res: StepResult = resolve_pending_tap(state);
+  res.append(resolve_pending_auto_shift(state, false));
+  res.append(resolve_pending_tap_dance(state));
+  
+  // A latched one-shot key has already been used by an earlier press
+  if state.one_shot_trigger.is_some() {
+    res.events.append(&mut release_one_shot_keys(state));
+  }
+  state.one_shot_candidate = None;
+  
+  let mut any_hit: bool = false;
+  let mut is_action: bool = false;
+  
+  state.mapped_absorbed_keys.retain(|k2| *k2 != k);
+  state.repeating_trigger = None;
+  
+  if let Some(mappings) = mappings.get(&k) {
+    let should_absorb = {
       match &state.absorbing_trigger {
         Some(absorbing_trigger) => *absorbing_trigger != k,
         None => true
@@ -431,9 +1414,71 @@ fn newly_press(mapper: &mut Mapper, k: KeyCode) -> StepResult {
       }
     };
     
+    let mut held_keys = state.input_pressed_keys.clone();
+    held_keys.extend(&state.latched_layer_keys);
+    
     for mapping in mappings.iter().rev() {
-      if is_supported(&mapping.from, &state.input_pressed_keys, &absorbed_keys, &k) {
-        res.append(add_new_mapping(&mut state, &k, &mapping));
+      if is_supported(&mapping.from, &held_keys, &absorbed_keys, &k) && conditions_hold(mapping, &held_keys, &k, &state.leds_on) {
+        if let Some(latch) = &mapping.latch {
+          res.events.append(&mut apply_latch(state, latch));
+        }
+        
+        match &mapping.action {
+          Some(Action::CapsWord) => state.caps_word = !state.caps_word,
+          Some(Action::TogglePause) => state.pause_toggled = true,
+          Some(action @ (Action::SwitchLayout(_) | Action::NextLayout)) => state.layout_switch = Some(action.clone()),
+          None => ()
+        };
+        
+        if mapping.one_shot {
+          state.one_shot_candidate = Some(k);
+        }
+        
+        if let Some(tap) = &mapping.tap {
+          res.append(StepResult {
+            events: vec![],
+            repeat: ResultingRepeat::Disabled,
+            timer: ResultingTimer::After { delay_ms: tap.tapping_term_ms }
+          });
+          if tap.extra_taps.is_empty() {
+            state.pending_tap = Some(PendingTap { key: k, mapping: mapping.clone() });
+          }
+          else {
+            state.pending_tap_dance = Some(PendingTapDance { key: k, mapping: mapping.clone(), count: 1, held: true });
+          }
+        }
+        else if let Repeat::AutoShift { keys: _, delay_ms } = &mapping.repeat {
+          // Nothing is typed until we know how long the key is held
+          is_action = true;
+          res.append(StepResult {
+            events: vec![],
+            repeat: ResultingRepeat::Disabled,
+            timer: ResultingTimer::After { delay_ms: *delay_ms }
+          });
+          state.pending_auto_shift = Some(PendingTap { key: k, mapping: mapping.clone() });
+        }
+        else if mapping.one_shot && is_identity(mapping) {
+          // Handled as a pass-through below
+          break;
+        }
+        else {
+          let was_moving = !held_mouse(state).is_empty();
+          
+          is_action = is_action_mapping(mapping);
+          res.append(add_new_mapping(state, &k, mapping));
+          
+          if !mapping.to_if_alone.is_empty() {
+            state.if_alone = Some(PendingTap { key: k, mapping: mapping.clone() });
+          }
+          
+          if let Some(mouse) = mapping.mouse {
+            // Move once right away so that a quick tap still does something
+            if !was_moving {
+              state.mouse_ticks = 0;
+            }
+            res.events.append(&mut mouse_events(&vec![mouse], 0));
+          }
+        }
         any_hit = true;
         break;
       }
@@ -456,6 +1501,7 @@ fn newly_press(mapper: &mut Mapper, k: KeyCode) -> StepResult {
   if !any_hit {
     if !state.pass_through_keys.contains(&k) {
       if is_action_key(&k) {
+        is_action = true;
         res.events.append(&mut release_action_mappings(&mut state));
         res.events.append(&mut release_absorbed_keys(&mut state));
       }
@@ -467,6 +1513,10 @@ fn newly_press(mapper: &mut Mapper, k: KeyCode) -> StepResult {
   
   state.input_pressed_keys.push(k);
   
+  if is_action && !state.latched_one_shot_keys.is_empty() {
+    state.one_shot_trigger = Some(k);
+  }
+  
   res
 }
 
@@ -518,7 +1568,10 @@ fn remove_mapping(state: &mut State, i: usize, removed_key: KeyCode) -> Vec<Even
     }
   }
     
-  active_mappings.remove(i);
+  let removed = active_mappings.remove(i);
+  if removed.clear_modifiers {
+    res.append(&mut restore_cleared_modifiers(state, removed_key));
+  }
   
   return res;
 }
@@ -526,36 +1579,70 @@ fn remove_mapping(state: &mut State, i: usize, removed_key: KeyCode) -> Vec<Even
 fn newly_release(mapper: &mut Mapper, k: KeyCode) -> StepResult {
   let state = &mut mapper.state;
   
-  let mut events: Vec<Event> = Vec::new();
+  if let Some(dance) = &mut state.pending_tap_dance {
+    if dance.key == k {
+      dance.held = false;
+      let is_last_tap = dance.count >= tap_dance_outputs(&dance.mapping).len();
+      let delay_ms = tapping_term_ms(&dance.mapping);
+      state.input_pressed_keys.retain(|k2| *k2 != k);
+      if is_last_tap {
+        // There is nothing more to wait for
+        return resolve_pending_tap_dance(state);
+      }
+      return StepResult {
+        events: vec![],
+        repeat: ResultingRepeat::Disabled,
+        timer: ResultingTimer::After { delay_ms }
+      };
+    }
+  }
   
-  let mut i: isize = state.active_mappings.len() as isize - 1;
-  while i >= 0 {
-    if fails_when_released(&state.active_mappings[i as usize].from, &k) {
-      events.append(&mut remove_mapping(state, i as usize, k));
+  if let Some(pending) = &state.pending_tap {
+    if pending.key == k {
+      return tap_pending(state, k);
     }
-    i -= 1;
   }
   
-  for i in (0 .. state.pass_through_keys.len()).rev() {
-    if state.pass_through_keys[i] == k {
-      events.push(Released(k));
-      state.pass_through_keys.remove(i);
-      break;
+  if let Some(pending) = &state.pending_auto_shift {
+    if pending.key == k {
+      let mut res = resolve_pending_auto_shift(state, false);
+      state.input_pressed_keys.retain(|k2| *k2 != k);
+      if state.one_shot_trigger == Some(k) {
+        res.events.append(&mut release_one_shot_keys(state));
+      }
+      return res;
     }
   }
   
-  state.input_pressed_keys.retain(|&old_key| {
-    old_key != k
-  });
+  if state.one_shot_candidate == Some(k) {
+    // Keep the key held until the next action key has been released
+    state.one_shot_candidate = None;
+    state.latched_one_shot_keys.push(k);
+    return StepResult::empty();
+  }
+  
+  let mut events = release_input_key(state, k);
+  
+  if state.if_alone.as_ref().map(|alone| alone.key) == Some(k) {
+    if let Some(alone) = state.if_alone.take() {
+      events.append(&mut type_once(state, alone.mapping.to_if_alone));
+    }
+  }
+  
+  if state.one_shot_trigger == Some(k) {
+    events.append(&mut release_one_shot_keys(state));
+  }
   
   let repeat = ResultingRepeat::Disabled;
+  let timer = ResultingTimer::NoChange;
   
-  StepResult { events, repeat }
+  StepResult { events, repeat, timer }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::keys::{Tap, Latch, LatchAction, Combo, Sequence, MacroStep, Action, Mouse, Axis};
   use KeyCode::*;
   use std::default::Default;
   
@@ -564,7 +1651,8 @@ mod tests {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![A], to: vec![B], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     let mut mapper = Mapper::for_layout(&layout);
     assert_eq!(vec![Pressed(B)], mapper.step(Pressed(A)).events);
@@ -575,7 +1663,8 @@ mod tests {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![A], to: vec![B], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     let mut mapper = Mapper::for_layout(&layout);
     assert_eq!(vec![Pressed(B)], mapper.step(Pressed(A)).events);
@@ -593,7 +1682,8 @@ mod tests {
         Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
         Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
         Mapping { from: vec![CAPSLOCK, U], to: vec![EQUAL], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     let mut mapper = Mapper::for_layout(&layout);
     let empty: Vec<Event> = Vec::new();
@@ -616,7 +1706,8 @@ mod tests {
         Mapping { from: vec![CAPSLOCK, N], to: vec![LEFTSHIFT, K1], ..Default::default() },
         Mapping { from: vec![TAB, M], to: vec![PAGEDOWN], ..Default::default() },
         Mapping { from: vec![TAB, N], to: vec![LEFTCTRL, LEFT], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     let mut mapper = Mapper::for_layout(&layout);
     
@@ -640,15 +1731,16 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Normal, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
     
-    assert_eq!(StepResult { events: vec![Pressed(A), Released(A)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(A)));
-    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled }, mapper.step(Released(A)));
-    assert_eq!(StepResult { events: vec![Pressed(B)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(B)));
-    assert_eq!(StepResult { events: vec![Released(B)], repeat: ResultingRepeat::Disabled }, mapper.step(Released(B)));
+    assert_eq!(StepResult { events: vec![Pressed(A), Released(A)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(A)));
+    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Released(A)));
+    assert_eq!(StepResult { events: vec![Pressed(B)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(B)));
+    assert_eq!(StepResult { events: vec![Released(B)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Released(B)));
   }
   
   #[test]
@@ -657,16 +1749,17 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Normal, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
     
-    assert_eq!(StepResult { events: vec![Pressed(LEFTSHIFT)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(LEFTSHIFT)));
-    assert_eq!(StepResult { events: vec![Pressed(A), Released(A)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(A)));
-    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled }, mapper.step(Released(A)));
-    assert_eq!(StepResult { events: vec![Pressed(B)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(B)));
-    assert_eq!(StepResult { events: vec![Released(B)], repeat: ResultingRepeat::Disabled }, mapper.step(Released(B)));
+    assert_eq!(StepResult { events: vec![Pressed(LEFTSHIFT)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(LEFTSHIFT)));
+    assert_eq!(StepResult { events: vec![Pressed(A), Released(A)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(A)));
+    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Released(A)));
+    assert_eq!(StepResult { events: vec![Pressed(B)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(B)));
+    assert_eq!(StepResult { events: vec![Released(B)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Released(B)));
   }
   
   #[test]
@@ -675,16 +1768,17 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Special { keys: vec![C], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
     
-    assert_eq!(StepResult { events: vec![Pressed(LEFTSHIFT)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(LEFTSHIFT)));
-    assert_eq!(StepResult { events: vec![Pressed(A), Released(A)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(A)));
-    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled }, mapper.step(Released(A)));
-    assert_eq!(StepResult { events: vec![Pressed(B), Released(B)], repeat: ResultingRepeat::Repeating { keys: vec![C], delay_ms: 130, interval_ms: 30 } }, mapper.step(Pressed(B)));
-    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled }, mapper.step(Released(B)));
+    assert_eq!(StepResult { events: vec![Pressed(LEFTSHIFT)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(LEFTSHIFT)));
+    assert_eq!(StepResult { events: vec![Pressed(A), Released(A)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(A)));
+    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Released(A)));
+    assert_eq!(StepResult { events: vec![Pressed(B), Released(B)], repeat: ResultingRepeat::Repeating { keys: vec![C], delay_ms: 130, interval_ms: 30 }, timer: ResultingTimer::NoChange }, mapper.step(Pressed(B)));
+    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Released(B)));
   }
 
   #[test]
@@ -693,16 +1787,17 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Special { keys: vec![LEFTCTRL, C], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
     
-    assert_eq!(StepResult { events: vec![Pressed(LEFTSHIFT)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(LEFTSHIFT)));
-    assert_eq!(StepResult { events: vec![Pressed(A), Released(A)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(A)));
-    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled }, mapper.step(Released(A)));
-    assert_eq!(StepResult { events: vec![Pressed(B), Released(B)], repeat: ResultingRepeat::Repeating { keys: vec![LEFTCTRL, C], delay_ms: 130, interval_ms: 30 } }, mapper.step(Pressed(B)));
-    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled }, mapper.step(Released(B)));
+    assert_eq!(StepResult { events: vec![Pressed(LEFTSHIFT)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(LEFTSHIFT)));
+    assert_eq!(StepResult { events: vec![Pressed(A), Released(A)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(A)));
+    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Released(A)));
+    assert_eq!(StepResult { events: vec![Pressed(B), Released(B)], repeat: ResultingRepeat::Repeating { keys: vec![LEFTCTRL, C], delay_ms: 130, interval_ms: 30 }, timer: ResultingTimer::NoChange }, mapper.step(Pressed(B)));
+    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Released(B)));
   }
 
   #[test]
@@ -711,24 +1806,26 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![C], repeat: Repeat::Normal, ..Default::default() },
         Mapping { from: vec![B], to: vec![D], repeat: Repeat::Special { keys: vec![E], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
     
     assert_eq!(
-      StepResult { events: vec![Pressed(C)], repeat: ResultingRepeat::Disabled },
+      StepResult { events: vec![Pressed(C)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange },
       mapper.step(Pressed(A))
     );
     assert_eq!(
       StepResult {
         events: vec![Pressed(D), Released(C), Released(D)],
-        repeat: ResultingRepeat::Repeating { keys: vec![E], delay_ms: 130, interval_ms: 30 }
+        repeat: ResultingRepeat::Repeating { keys: vec![E], delay_ms: 130, interval_ms: 30 },
+        timer: ResultingTimer::NoChange
       },
       mapper.step(Pressed(B))
     );
     assert_eq!(
-      StepResult { events: vec![], repeat: ResultingRepeat::Disabled },
+      StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange },
       mapper.step(Released(A))
     );
   }
@@ -738,14 +1835,15 @@ mod tests {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![LEFTSHIFT, A], to: vec![LEFTSHIFT, A], absorbing: vec![LEFTSHIFT], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
     
-    assert_eq!(StepResult { events: vec![Pressed(LEFTSHIFT)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(LEFTSHIFT)));
-    assert_eq!(StepResult { events: vec![Pressed(A)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(A)));
-    assert_eq!(StepResult { events: vec![Released(A), Released(LEFTSHIFT), Pressed(B)], repeat: ResultingRepeat::Disabled }, mapper.step(Pressed(B)));
+    assert_eq!(StepResult { events: vec![Pressed(LEFTSHIFT)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(LEFTSHIFT)));
+    assert_eq!(StepResult { events: vec![Pressed(A)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(A)));
+    assert_eq!(StepResult { events: vec![Released(A), Released(LEFTSHIFT), Pressed(B)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Pressed(B)));
   }
   
   #[test]
@@ -754,7 +1852,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![LEFTSHIFT, A], to: vec![LEFTSHIFT, A], absorbing: vec![LEFTSHIFT], ..Default::default() },
         Mapping { from: vec![LEFTSHIFT, B], to: vec![LEFTSHIFT, B], absorbing: vec![LEFTSHIFT], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -771,7 +1870,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![Z], to: vec![APOSTROPHE], ..Default::default() },
         Mapping { from: vec![RIGHTSHIFT, Z], to: vec![LEFTSHIFT, APOSTROPHE], absorbing: vec![RIGHTSHIFT], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -789,7 +1889,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![B], ..Default::default() },
         Mapping { from: vec![C], to: vec![D], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -805,7 +1906,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![LEFTSHIFT, B], ..Default::default() },
         Mapping { from: vec![C], to: vec![D], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -813,5 +1915,935 @@ mod tests {
     assert_eq!(vec![Pressed(LEFTSHIFT), Pressed(B)], mapper.step(Pressed(A)).events);
     assert_eq!(vec![Released(B), Released(LEFTSHIFT), Pressed(D)], mapper.step(Pressed(C)).events);
   }
-}
-
+  
+  #[test]
+  fn tap_hold_tap_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], tap: Some(Tap { keys: vec![ESC], tapping_term_ms: 200, extra_taps: vec![] }), ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::After { delay_ms: 200 } }, mapper.step(Pressed(CAPSLOCK)));
+    assert_eq!(StepResult { events: vec![Pressed(ESC), Released(ESC)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::Disabled }, mapper.step(Released(CAPSLOCK)));
+    assert_eq!(vec![Pressed(J)], mapper.step(Pressed(J)).events);
+  }
+  
+  #[test]
+  fn tap_hold_other_key_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], tap: Some(Tap { keys: vec![ESC], tapping_term_ms: 200, extra_taps: vec![] }), ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(StepResult { events: vec![Pressed(LEFT)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::Disabled }, mapper.step(Pressed(J)));
+    assert_eq!(vec![Released(LEFT)], mapper.step(Released(J)).events);
+    assert_eq!(empty, mapper.step(Released(CAPSLOCK)).events);
+  }
+  
+  #[test]
+  fn tap_hold_timeout_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![LEFTCTRL], tap: Some(Tap { keys: vec![A], tapping_term_ms: 180, extra_taps: vec![] }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(A)).events);
+    assert_eq!(StepResult { events: vec![Pressed(LEFTCTRL)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::Disabled }, mapper.timeout());
+    assert_eq!(vec![Pressed(C)], mapper.step(Pressed(C)).events);
+    assert_eq!(vec![Released(C)], mapper.step(Released(C)).events);
+    assert_eq!(vec![Released(LEFTCTRL)], mapper.step(Released(A)).events);
+  }
+  
+  #[test]
+  fn tap_hold_release_all_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], tap: Some(Tap { keys: vec![ESC], tapping_term_ms: 200, extra_taps: vec![] }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(empty, mapper.release_all());
+  }
+  
+  #[test]
+  fn one_shot_modifier_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], one_shot: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(empty, mapper.step(Released(LEFTSHIFT)).events);
+    assert_eq!(vec![Pressed(A)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(A), Released(LEFTSHIFT)], mapper.step(Released(A)).events);
+    assert_eq!(vec![Pressed(B)], mapper.step(Pressed(B)).events);
+  }
+  
+  #[test]
+  fn one_shot_modifier_held_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], one_shot: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(vec![Pressed(A)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(A)], mapper.step(Released(A)).events);
+    assert_eq!(vec![Released(LEFTSHIFT)], mapper.step(Released(LEFTSHIFT)).events);
+  }
+  
+  #[test]
+  fn one_shot_layer_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], one_shot: true, ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(empty, mapper.step(Released(CAPSLOCK)).events);
+    assert_eq!(vec![Pressed(LEFT)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(LEFT)], mapper.step(Released(J)).events);
+    assert_eq!(vec![Pressed(J)], mapper.step(Pressed(J)).events);
+  }
+  
+  #[test]
+  fn one_shot_stacking_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], one_shot: true, ..Default::default() },
+        Mapping { from: vec![LEFTCTRL], to: vec![LEFTCTRL], one_shot: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(empty, mapper.step(Released(LEFTSHIFT)).events);
+    assert_eq!(vec![Pressed(LEFTCTRL)], mapper.step(Pressed(LEFTCTRL)).events);
+    assert_eq!(empty, mapper.step(Released(LEFTCTRL)).events);
+    assert_eq!(vec![Pressed(A)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(A), Released(LEFTSHIFT), Released(LEFTCTRL)], mapper.step(Released(A)).events);
+  }
+  
+  #[test]
+  fn one_shot_rolling_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], one_shot: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    mapper.step(Pressed(LEFTSHIFT));
+    mapper.step(Released(LEFTSHIFT));
+    assert_eq!(vec![Pressed(A)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(LEFTSHIFT), Pressed(B)], mapper.step(Pressed(B)).events);
+    assert_eq!(vec![Released(A)], mapper.step(Released(A)).events);
+    assert_eq!(vec![Released(B)], mapper.step(Released(B)).events);
+  }
+  
+  #[test]
+  fn one_shot_cancel_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], one_shot: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    mapper.step(Pressed(LEFTSHIFT));
+    mapper.step(Released(LEFTSHIFT));
+    assert_eq!(vec![Released(LEFTSHIFT), Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(vec![Released(LEFTSHIFT)], mapper.step(Released(LEFTSHIFT)).events);
+    assert_eq!(empty, mapper.release_all());
+  }
+  
+  #[test]
+  fn one_shot_release_all_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], one_shot: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    mapper.step(Pressed(LEFTSHIFT));
+    mapper.step(Released(LEFTSHIFT));
+    assert_eq!(vec![Released(LEFTSHIFT)], mapper.release_all());
+  }
+  
+  #[test]
+  fn latch_toggle_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![K1], ..Default::default() },
+        Mapping { from: vec![SCROLLLOCK], to: vec![], latch: Some(Latch { action: LatchAction::Toggle, keys: vec![CAPSLOCK] }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(vec![Pressed(J)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(J)], mapper.step(Released(J)).events);
+    assert_eq!(empty, mapper.step(Pressed(SCROLLLOCK)).events);
+    assert_eq!(empty, mapper.step(Released(SCROLLLOCK)).events);
+    assert_eq!(vec![Pressed(K1)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(K1)], mapper.step(Released(J)).events);
+    assert_eq!(vec![Pressed(K1)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(K1)], mapper.step(Released(J)).events);
+    assert_eq!(empty, mapper.step(Pressed(SCROLLLOCK)).events);
+    assert_eq!(empty, mapper.step(Released(SCROLLLOCK)).events);
+    assert_eq!(vec![Pressed(J)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(J)], mapper.step(Released(J)).events);
+  }
+  
+  #[test]
+  fn latch_lock_clear_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![K1], ..Default::default() },
+        Mapping { from: vec![SCROLLLOCK], to: vec![], latch: Some(Latch { action: LatchAction::Lock, keys: vec![CAPSLOCK] }), ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, ESC], to: vec![], latch: Some(Latch { action: LatchAction::Clear, keys: vec![CAPSLOCK] }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    mapper.step(Pressed(SCROLLLOCK));
+    mapper.step(Released(SCROLLLOCK));
+    mapper.step(Pressed(SCROLLLOCK));
+    mapper.step(Released(SCROLLLOCK));
+    assert_eq!(vec![Pressed(K1)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(K1)], mapper.step(Released(J)).events);
+    assert_eq!(empty, mapper.step(Pressed(ESC)).events);
+    assert_eq!(empty, mapper.step(Released(ESC)).events);
+    assert_eq!(vec![Pressed(J)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Pressed(ESC)], mapper.step(Pressed(ESC)).events);
+  }
+  
+  #[test]
+  fn latch_held_key_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![K1], ..Default::default() },
+        Mapping { from: vec![SCROLLLOCK], to: vec![], latch: Some(Latch { action: LatchAction::Toggle, keys: vec![CAPSLOCK] }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    mapper.step(Pressed(SCROLLLOCK));
+    mapper.step(Released(SCROLLLOCK));
+    assert_eq!(vec![Pressed(K1)], mapper.step(Pressed(J)).events);
+    // Toggling the layer off while J is held ends the mapping
+    assert_eq!(vec![Released(K1)], mapper.step(Pressed(SCROLLLOCK)).events);
+    assert_eq!(empty, mapper.step(Released(J)).events);
+    assert_eq!(empty, mapper.release_all());
+  }
+  
+  #[test]
+  fn combo_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![J, K], to: vec![ESC], combo: Some(Combo { window_ms: 30 }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    let res = mapper.step(Pressed(K));
+    assert_eq!(empty, res.events);
+    assert_eq!(ResultingTimer::After { delay_ms: 30 }, res.timer);
+    let res = mapper.step(Pressed(J));
+    assert_eq!(vec![Pressed(ESC)], res.events);
+    assert_eq!(ResultingTimer::Disabled, res.timer);
+    assert_eq!(vec![Released(ESC)], mapper.step(Released(J)).events);
+    assert_eq!(empty, mapper.step(Released(K)).events);
+  }
+  
+  #[test]
+  fn combo_timeout_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![J, K], to: vec![ESC], combo: Some(Combo { window_ms: 30 }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(J)).events);
+    let res = mapper.timeout();
+    assert_eq!(vec![Pressed(J)], res.events);
+    assert_eq!(ResultingTimer::Disabled, res.timer);
+    assert_eq!(empty, mapper.step(Pressed(K)).events);
+    assert_eq!(vec![Pressed(K), Released(J)], mapper.step(Released(J)).events);
+    assert_eq!(vec![Released(K)], mapper.step(Released(K)).events);
+  }
+  
+  #[test]
+  fn combo_no_match_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![J, K], to: vec![ESC], combo: Some(Combo { window_ms: 30 }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Pressed(J), Pressed(L)], mapper.step(Pressed(L)).events);
+    assert_eq!(vec![Released(L)], mapper.step(Released(L)).events);
+    
+    // A release in the window also ends the combo
+    assert_eq!(vec![Released(J)], mapper.step(Released(J)).events);
+    assert_eq!(empty, mapper.step(Pressed(K)).events);
+    assert_eq!(vec![Pressed(K), Released(K)], mapper.step(Released(K)).events);
+  }
+  
+  #[test]
+  fn combo_three_keys_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![J, K], to: vec![ESC], combo: Some(Combo { window_ms: 30 }), ..Default::default() },
+        Mapping { from: vec![J, K, L], to: vec![TAB], combo: Some(Combo { window_ms: 50 }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(ResultingTimer::After { delay_ms: 50 }, mapper.step(Pressed(L)).timer);
+    assert_eq!(empty, mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Pressed(TAB)], mapper.step(Pressed(K)).events);
+    assert_eq!(vec![Released(TAB)], mapper.step(Released(K)).events);
+    assert_eq!(empty, mapper.release_all());
+  }
+  
+  #[test]
+  fn sequence_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![RIGHTALT], to: vec![], ..Default::default() },
+        Mapping { from: vec![RIGHTALT, G, G], to: vec![LEFTCTRL, HOME], sequence: Some(Sequence { timeout_ms: 1000 }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(RIGHTALT)).events);
+    let res = mapper.step(Released(RIGHTALT));
+    assert_eq!(empty, res.events);
+    assert_eq!(ResultingTimer::After { delay_ms: 1000 }, res.timer);
+    assert_eq!(empty, mapper.step(Pressed(G)).events);
+    assert_eq!(empty, mapper.step(Released(G)).events);
+    let res = mapper.step(Pressed(G));
+    assert_eq!(vec![Pressed(LEFTCTRL), Pressed(HOME), Released(HOME), Released(LEFTCTRL)], res.events);
+    assert_eq!(ResultingTimer::Disabled, res.timer);
+    assert_eq!(empty, mapper.step(Released(G)).events);
+    assert_eq!(vec![Pressed(G)], mapper.step(Pressed(G)).events);
+  }
+  
+  #[test]
+  fn sequence_abandon_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![RIGHTALT, G, G], to: vec![HOME], sequence: Some(Sequence { timeout_ms: 1000 }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    // Holding the leader as a modifier doesn't start a sequence
+    mapper.step(Pressed(RIGHTALT));
+    assert_eq!(vec![Pressed(G)], mapper.step(Pressed(G)).events);
+    mapper.step(Released(G));
+    mapper.step(Released(RIGHTALT));
+    assert_eq!(vec![Pressed(G)], mapper.step(Pressed(G)).events);
+    mapper.step(Released(G));
+    
+    // A key that isn't part of the sequence ends it
+    mapper.step(Pressed(RIGHTALT));
+    mapper.step(Released(RIGHTALT));
+    assert_eq!(empty, mapper.step(Pressed(G)).events);
+    let res = mapper.step(Pressed(H));
+    assert_eq!(vec![Pressed(H)], res.events);
+    assert_eq!(ResultingTimer::Disabled, res.timer);
+    mapper.step(Released(H));
+    
+    // Escape cancels
+    mapper.step(Pressed(RIGHTALT));
+    mapper.step(Released(RIGHTALT));
+    assert_eq!(empty, mapper.step(Pressed(ESC)).events);
+    assert_eq!(empty, mapper.step(Released(ESC)).events);
+    assert_eq!(vec![Pressed(G)], mapper.step(Pressed(G)).events);
+    mapper.step(Released(G));
+    
+    // So does the timeout
+    mapper.step(Pressed(RIGHTALT));
+    mapper.step(Released(RIGHTALT));
+    assert_eq!(empty, mapper.step(Pressed(G)).events);
+    mapper.step(Released(G));
+    assert_eq!(ResultingTimer::Disabled, mapper.timeout().timer);
+    assert_eq!(vec![Pressed(G)], mapper.step(Pressed(G)).events);
+  }
+  
+  #[test]
+  fn macro_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, D], to: vec![], macro_steps: vec![MacroStep::Tap(vec![LEFTCTRL, A]), MacroStep::Delay(20), MacroStep::Tap(vec![DELETE])], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(StepResult {
+      events: vec![Pressed(LEFTCTRL), Pressed(A), Released(A), Released(LEFTCTRL), Event::Sync { delay_ms: 0 }],
+      repeat: ResultingRepeat::Disabled,
+      timer: ResultingTimer::After { delay_ms: 20 }
+    }, mapper.step(Pressed(D)));
+    // Keys during the delay wait for the macro
+    assert_eq!(empty, mapper.step(Released(D)).events);
+    assert_eq!(empty, mapper.step(Pressed(X)).events);
+    assert_eq!(StepResult {
+      events: vec![Pressed(DELETE), Released(DELETE), Event::Sync { delay_ms: 0 }, Pressed(X)],
+      repeat: ResultingRepeat::NoChange,
+      timer: ResultingTimer::NoChange
+    }, mapper.timeout());
+    assert_eq!(vec![Released(X)], mapper.step(Released(X)).events);
+    assert_eq!(empty, mapper.step(Released(CAPSLOCK)).events);
+  }
+  
+  #[test]
+  fn macro_after_modifier_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT, D], to: vec![], macro_steps: vec![MacroStep::Tap(vec![DELETE])], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(vec![
+      Released(LEFTSHIFT), Event::Sync { delay_ms: 0 },
+      Pressed(DELETE), Released(DELETE), Event::Sync { delay_ms: 0 }
+    ], mapper.step(Pressed(D)).events);
+    assert_eq!(empty, mapper.step(Released(D)).events);
+  }
+  
+  #[test]
+  fn caps_word_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, C], to: vec![], action: Some(Action::CapsWord), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(empty, mapper.step(Pressed(C)).events);
+    assert_eq!(empty, mapper.step(Released(C)).events);
+    assert_eq!(empty, mapper.step(Released(CAPSLOCK)).events);
+    assert_eq!(vec![Pressed(LEFTSHIFT), Pressed(A), Released(LEFTSHIFT)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(A)], mapper.step(Released(A)).events);
+    assert_eq!(vec![Pressed(K1)], mapper.step(Pressed(K1)).events);
+    assert_eq!(vec![Released(K1)], mapper.step(Released(K1)).events);
+    assert_eq!(vec![Pressed(LEFTSHIFT), Pressed(B), Released(LEFTSHIFT)], mapper.step(Pressed(B)).events);
+    assert_eq!(vec![Released(B)], mapper.step(Released(B)).events);
+    assert_eq!(vec![Pressed(SPACE)], mapper.step(Pressed(SPACE)).events);
+    assert_eq!(vec![Released(SPACE)], mapper.step(Released(SPACE)).events);
+    assert_eq!(vec![Pressed(A)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(A)], mapper.step(Released(A)).events);
+  }
+  
+  #[test]
+  fn caps_word_held_shift_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![F1], to: vec![], action: Some(Action::CapsWord), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(F1)).events);
+    assert_eq!(empty, mapper.step(Released(F1)).events);
+    
+    // Shift + MINUS for an underscore keeps the word going
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(vec![Pressed(MINUS)], mapper.step(Pressed(MINUS)).events);
+    assert_eq!(vec![Released(MINUS)], mapper.step(Released(MINUS)).events);
+    assert_eq!(vec![Pressed(X)], mapper.step(Pressed(X)).events);
+    assert_eq!(vec![Released(X)], mapper.step(Released(X)).events);
+    assert_eq!(vec![Released(LEFTSHIFT)], mapper.step(Released(LEFTSHIFT)).events);
+    assert_eq!(vec![Pressed(LEFTSHIFT), Pressed(Y), Released(LEFTSHIFT)], mapper.step(Pressed(Y)).events);
+    assert_eq!(vec![Released(Y)], mapper.step(Released(Y)).events);
+    
+    // Pressing the action again turns it off
+    assert_eq!(empty, mapper.step(Pressed(F1)).events);
+    assert_eq!(empty, mapper.step(Released(F1)).events);
+    assert_eq!(vec![Pressed(Y)], mapper.step(Pressed(Y)).events);
+  }
+  
+  #[test]
+  fn auto_shift_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![A], repeat: Repeat::AutoShift { keys: vec![LEFTSHIFT, A], delay_ms: 180 }, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::After { delay_ms: 180 } }, mapper.step(Pressed(A)));
+    assert_eq!(StepResult { events: vec![Pressed(A), Released(A)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::Disabled }, mapper.step(Released(A)));
+    
+    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::After { delay_ms: 180 } }, mapper.step(Pressed(A)));
+    assert_eq!(StepResult { events: vec![Pressed(LEFTSHIFT), Pressed(A), Released(A), Released(LEFTSHIFT)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::Disabled }, mapper.timeout());
+    assert_eq!(StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::NoChange }, mapper.step(Released(A)));
+  }
+  
+  #[test]
+  fn auto_shift_rolling_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![A], repeat: Repeat::AutoShift { keys: vec![LEFTSHIFT, A], delay_ms: 180 }, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(A)).events);
+    assert_eq!(StepResult { events: vec![Pressed(A), Released(A), Pressed(B)], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::Disabled }, mapper.step(Pressed(B)));
+    assert_eq!(empty, mapper.step(Released(A)).events);
+    assert_eq!(vec![Released(B)], mapper.step(Released(B)).events);
+    
+    // A physically held Shift isn't released by the shifted output
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(empty, mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Pressed(A), Released(A)], mapper.timeout().events);
+    assert_eq!(empty, mapper.step(Released(A)).events);
+    assert_eq!(vec![Released(LEFTSHIFT)], mapper.step(Released(LEFTSHIFT)).events);
+  }
+  
+  #[test]
+  fn mouse_move_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, L], to: vec![], mouse: Some(Mouse::Move { x: 10, y: 0 }), ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![], mouse: Some(Mouse::Move { x: 0, y: 10 }), ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, SPACE], to: vec![BTN_LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(None, mapper.mouse_interval_ms());
+    assert_eq!(vec![Event::Moved { axis: Axis::X, delta: 10 }], mapper.step(Pressed(L)).events);
+    assert_eq!(Some(MOUSE_TICK_MS), mapper.mouse_interval_ms());
+    assert_eq!(vec![Event::Moved { axis: Axis::X, delta: 10 }], mapper.mouse_tick());
+    assert_eq!(vec![Event::Moved { axis: Axis::Y, delta: 10 }], mapper.step(Pressed(J)).events);
+    
+    // Full speed after a second
+    for _ in 0 .. 48 {
+      mapper.mouse_tick();
+    }
+    assert_eq!(vec![Event::Moved { axis: Axis::X, delta: 40 }, Event::Moved { axis: Axis::Y, delta: 40 }], mapper.mouse_tick());
+    
+    assert_eq!(empty, mapper.step(Released(J)).events);
+    assert_eq!(empty, mapper.step(Released(L)).events);
+    assert_eq!(None, mapper.mouse_interval_ms());
+    
+    // Buttons are held like keys, so dragging works
+    assert_eq!(vec![Pressed(BTN_LEFT)], mapper.step(Pressed(SPACE)).events);
+    assert_eq!(vec![Event::Moved { axis: Axis::X, delta: 10 }], mapper.step(Pressed(L)).events);
+    assert_eq!(empty, mapper.step(Released(L)).events);
+    assert_eq!(vec![Released(BTN_LEFT)], mapper.step(Released(SPACE)).events);
+  }
+  
+  #[test]
+  fn mouse_scroll_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![F1], to: vec![], mouse: Some(Mouse::Scroll { x: 0, y: 1 }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(vec![Event::Moved { axis: Axis::Wheel, delta: -1 }], mapper.step(Pressed(F1)).events);
+    for _ in 1 .. MOUSE_SCROLL_TICKS {
+      assert_eq!(empty, mapper.mouse_tick());
+    }
+    assert_eq!(vec![Event::Moved { axis: Axis::Wheel, delta: -1 }], mapper.mouse_tick());
+    assert_eq!(empty, mapper.step(Released(F1)).events);
+  }
+  
+  #[test]
+  fn mouse_huge_amount_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![L], to: vec![], mouse: Some(Mouse::Move { x: i32::MAX, y: 0 }), ..Default::default() },
+        Mapping { from: vec![K], to: vec![], mouse: Some(Mouse::Move { x: i32::MAX, y: i32::MIN }), ..Default::default() },
+        Mapping { from: vec![F1], to: vec![], mouse: Some(Mouse::Scroll { x: 0, y: i32::MIN }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    assert_eq!(vec![Event::Moved { axis: Axis::X, delta: i32::MAX }], mapper.step(Pressed(L)).events);
+    mapper.step(Pressed(K));
+    for _ in 0 .. 48 {
+      mapper.mouse_tick();
+    }
+    assert_eq!(vec![Event::Moved { axis: Axis::X, delta: i32::MAX }, Event::Moved { axis: Axis::Y, delta: i32::MIN }], mapper.mouse_tick());
+    assert_eq!(vec![Event::Moved { axis: Axis::Wheel, delta: i32::MAX }], mapper.step(Pressed(F1)).events);
+  }
+  
+  #[test]
+  fn test_precedence() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+        Mapping { from: vec![J], to: vec![K], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, U], to: vec![HOME], ..Default::default() },
+        Mapping { from: vec![U], to: vec![I], priority: 1, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    // The mapping with more keys wins even though it comes first
+    assert_eq!(empty, mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(vec![Pressed(LEFT)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(LEFT)], mapper.step(Released(J)).events);
+    // ... unless the other one has a higher priority
+    assert_eq!(vec![Pressed(I)], mapper.step(Pressed(U)).events);
+    assert_eq!(vec![Released(I)], mapper.step(Released(U)).events);
+  }
+  
+  #[test]
+  fn test_unless_and_exclusive() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT, SPACE], to: vec![BACKSPACE], unless: vec![LEFTCTRL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], exclusive: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(vec![Released(LEFTSHIFT), Pressed(BACKSPACE)], mapper.step(Pressed(SPACE)).events);
+    assert_eq!(vec![Released(BACKSPACE)], mapper.step(Released(SPACE)).events);
+    // With Ctrl held the mapping doesn't apply and Space passes through
+    assert_eq!(vec![Pressed(LEFTCTRL)], mapper.step(Pressed(LEFTCTRL)).events);
+    assert_eq!(vec![Pressed(SPACE)], mapper.step(Pressed(SPACE)).events);
+    assert_eq!(vec![Released(SPACE)], mapper.step(Released(SPACE)).events);
+    assert_eq!(vec![Released(LEFTCTRL)], mapper.step(Released(LEFTCTRL)).events);
+    mapper.step(Released(LEFTSHIFT));
+    
+    assert_eq!(vec![Pressed(CAPSLOCK)], mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(vec![Released(CAPSLOCK), Pressed(LEFT)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(LEFT)], mapper.step(Released(J)).events);
+    // Not while anything else is held
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(vec![Pressed(J)], mapper.step(Pressed(J)).events);
+  }
+  
+  #[test]
+  fn tap_dance_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![APOSTROPHE], to: vec![], tap: Some(Tap { keys: vec![APOSTROPHE], tapping_term_ms: 200, extra_taps: vec![vec![LEFTSHIFT, APOSTROPHE], vec![GRAVE]] }), ..Default::default() },
+        Mapping { from: vec![APOSTROPHE, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    let waiting = StepResult { events: vec![], repeat: ResultingRepeat::Disabled, timer: ResultingTimer::After { delay_ms: 200 } };
+    
+    // One tap
+    assert_eq!(waiting, mapper.step(Pressed(APOSTROPHE)));
+    assert_eq!(waiting, mapper.step(Released(APOSTROPHE)));
+    assert_eq!(vec![Pressed(APOSTROPHE), Released(APOSTROPHE)], mapper.timeout().events);
+    
+    // Two taps
+    assert_eq!(waiting, mapper.step(Pressed(APOSTROPHE)));
+    assert_eq!(waiting, mapper.step(Released(APOSTROPHE)));
+    assert_eq!(waiting, mapper.step(Pressed(APOSTROPHE)));
+    assert_eq!(waiting, mapper.step(Released(APOSTROPHE)));
+    assert_eq!(vec![Pressed(LEFTSHIFT), Pressed(APOSTROPHE), Released(APOSTROPHE), Released(LEFTSHIFT)], mapper.timeout().events);
+    
+    // Three taps need no timeout, since there is no fourth
+    mapper.step(Pressed(APOSTROPHE));
+    mapper.step(Released(APOSTROPHE));
+    mapper.step(Pressed(APOSTROPHE));
+    mapper.step(Released(APOSTROPHE));
+    assert_eq!(waiting, mapper.step(Pressed(APOSTROPHE)));
+    assert_eq!(vec![Pressed(GRAVE), Released(GRAVE)], mapper.step(Released(APOSTROPHE)).events);
+    
+    // A tap, then typing another key
+    mapper.step(Pressed(APOSTROPHE));
+    mapper.step(Released(APOSTROPHE));
+    assert_eq!(vec![Pressed(APOSTROPHE), Released(APOSTROPHE), Pressed(J)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(J)], mapper.step(Released(J)).events);
+    
+    // Holding it acts as a layer
+    assert_eq!(waiting, mapper.step(Pressed(APOSTROPHE)));
+    assert_eq!(vec![Pressed(LEFT)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(LEFT)], mapper.step(Released(J)).events);
+    assert_eq!(empty, mapper.step(Released(APOSTROPHE)).events);
+    
+    // Also when held past the tapping term
+    mapper.step(Pressed(APOSTROPHE));
+    assert_eq!(empty, mapper.timeout().events);
+    assert_eq!(vec![Pressed(LEFT)], mapper.step(Pressed(J)).events);
+  }
+  
+  #[test]
+  fn to_if_alone_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], to_if_alone: vec![LEFTSHIFT, K9], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    // Shift is pressed right away, and the extra output comes on release
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(vec![Released(LEFTSHIFT), Pressed(LEFTSHIFT), Pressed(K9), Released(K9), Released(LEFTSHIFT)], mapper.step(Released(LEFTSHIFT)).events);
+    
+    // Used as a modifier, it is only Shift
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(vec![Pressed(A)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(A)], mapper.step(Released(A)).events);
+    assert_eq!(vec![Released(LEFTSHIFT)], mapper.step(Released(LEFTSHIFT)).events);
+  }
+  
+  #[test]
+  fn clear_modifiers_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![RIGHTALT, K], to: vec![LEFTSHIFT, K9], clear_modifiers: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    // A held Ctrl is let go of while the mapping is active
+    assert_eq!(vec![Pressed(LEFTCTRL)], mapper.step(Pressed(LEFTCTRL)).events);
+    assert_eq!(vec![Pressed(RIGHTALT)], mapper.step(Pressed(RIGHTALT)).events);
+    assert_eq!(vec![Released(RIGHTALT), Released(LEFTCTRL), Pressed(LEFTSHIFT), Pressed(K9)], mapper.step(Pressed(K)).events);
+    assert_eq!(vec![Released(K9), Released(LEFTSHIFT), Pressed(LEFTCTRL)], mapper.step(Released(K)).events);
+    assert_eq!(vec![Released(LEFTCTRL)], mapper.step(Released(LEFTCTRL)).events);
+    assert_eq!(empty, mapper.step(Released(RIGHTALT)).events);
+    
+    // Not brought back if it was let go of meanwhile
+    mapper.step(Pressed(LEFTCTRL));
+    mapper.step(Pressed(RIGHTALT));
+    mapper.step(Pressed(K));
+    assert_eq!(empty, mapper.step(Released(LEFTCTRL)).events);
+    assert_eq!(vec![Released(K9), Released(LEFTSHIFT)], mapper.step(Released(K)).events);
+  }
+  
+  #[test]
+  fn clear_modifiers_mapped_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![LEFTCTRL], ..Default::default() },
+        Mapping { from: vec![RIGHTALT, K], to: vec![LEFTSHIFT, K9], clear_modifiers: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    // A Ctrl output by another mapping is let go of too, and comes back
+    // while that mapping is still active
+    assert_eq!(vec![Pressed(LEFTCTRL)], mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(vec![Pressed(RIGHTALT)], mapper.step(Pressed(RIGHTALT)).events);
+    assert_eq!(vec![Released(RIGHTALT), Released(LEFTCTRL), Pressed(LEFTSHIFT), Pressed(K9)], mapper.step(Pressed(K)).events);
+    assert_eq!(vec![Released(K9), Released(LEFTSHIFT), Pressed(LEFTCTRL)], mapper.step(Released(K)).events);
+    assert_eq!(vec![Released(LEFTCTRL)], mapper.step(Released(CAPSLOCK)).events);
+    assert_eq!(empty, mapper.step(Released(RIGHTALT)).events);
+    
+    // Not brought back once that mapping has ended
+    mapper.step(Pressed(CAPSLOCK));
+    mapper.step(Pressed(RIGHTALT));
+    mapper.step(Pressed(K));
+    assert_eq!(empty, mapper.step(Released(CAPSLOCK)).events);
+    assert_eq!(vec![Released(K9), Released(LEFTSHIFT)], mapper.step(Released(K)).events);
+  }
+  
+  #[test]
+  fn named_layouts_test_1() {
+    let next_layout = Mapping { from: vec![F12], to: vec![], action: Some(Action::NextLayout), ..Default::default() };
+    let plain = vec![next_layout.clone()];
+    let swapped = vec![
+      next_layout.clone(),
+      Mapping { from: vec![A], to: vec![B], ..Default::default() },
+    ];
+    let layout = Layout {
+      mappings: plain.clone(),
+      named_layouts: vec![
+        NamedLayout { name: "plain".to_owned(), mappings: plain },
+        NamedLayout { name: "swapped".to_owned(), mappings: swapped },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    assert_eq!(Some("plain"), mapper.active_layout_name());
+    assert_eq!(vec![Pressed(A)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(A)], mapper.step(Released(A)).events);
+    assert_eq!(None, mapper.take_layout_switch());
+    
+    assert_eq!(empty, mapper.step(Pressed(F12)).events);
+    assert_eq!(Some("swapped".to_owned()), mapper.take_layout_switch());
+    assert_eq!(None, mapper.take_layout_switch());
+    mapper.release_all();
+    assert!(mapper.select_layout("swapped"));
+    assert_eq!(vec![Pressed(B)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(B)], mapper.step(Released(A)).events);
+    
+    // Cycles back to the first
+    assert_eq!(empty, mapper.step(Pressed(F12)).events);
+    assert_eq!(Some("plain".to_owned()), mapper.take_layout_switch());
+    
+    assert!(!mapper.select_layout("dvorak"));
+    assert_eq!(Some("swapped"), mapper.active_layout_name());
+    
+    // Reloading keeps the named layout in use
+    mapper.replace_layout(&layout);
+    assert_eq!(Some("swapped"), mapper.active_layout_name());
+    assert_eq!(vec![Pressed(B)], mapper.step(Pressed(A)).events);
+  }
+  
+  #[test]
+  fn release_all_latched_layer_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![LEFTCTRL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![K1], ..Default::default() },
+        Mapping { from: vec![SCROLLLOCK], to: vec![], latch: Some(Latch { action: LatchAction::Toggle, keys: vec![CAPSLOCK] }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(vec![Pressed(LEFTCTRL)], mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(empty, mapper.step(Pressed(SCROLLLOCK)).events);
+    assert_eq!(empty, mapper.step(Released(SCROLLLOCK)).events);
+    // Held by the latch
+    assert_eq!(empty, mapper.step(Released(CAPSLOCK)).events);
+    assert_eq!(vec![Pressed(K1)], mapper.step(Pressed(J)).events);
+    
+    let released = mapper.release_all();
+    assert_eq!(2, released.len());
+    assert!(released.contains(&Released(LEFTCTRL)));
+    assert!(released.contains(&Released(K1)));
+    assert_eq!(vec![Pressed(J)], mapper.step(Pressed(J)).events);
+  }
+}
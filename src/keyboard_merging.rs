@@ -0,0 +1,82 @@
+// vim: shiftwidth=2
+
+use crate::keys::{Event, KeyCode};
+use Event::{Pressed, Released};
+
+// Sits between several keyboards and the one mapper they share. A key
+// held on two keyboards at once is pressed once, and released when the
+// last of them lets go.
+pub struct MergedHeldKeys {
+  // Which keyboard holds which key
+  held: Vec<(usize, KeyCode)>
+}
+
+impl MergedHeldKeys {
+  pub fn new() -> MergedHeldKeys {
+    MergedHeldKeys {
+      held: Vec::new()
+    }
+  }
+
+  // Whether `ev` from keyboard `source` should be passed on.
+  pub fn accept(&mut self, source: usize, ev: &Event) -> bool {
+    match ev {
+      Pressed(k) => {
+        let held_elsewhere = self.held.iter().any(|(_, k2)| k2 == k);
+        if !self.held.contains(&(source, *k)) {
+          self.held.push((source, *k));
+        }
+        !held_elsewhere
+      },
+      Released(k) => {
+        if !self.held.contains(&(source, *k)) {
+          return false;
+        }
+        self.held.retain(|held| *held != (source, *k));
+        !self.held.iter().any(|(_, k2)| k2 == k)
+      },
+      _ => true
+    }
+  }
+
+  // For when keyboard `source` goes away: the releases for the keys it
+  // was holding that nothing else holds.
+  pub fn remove_source(&mut self, source: usize) -> Vec<Event> {
+    let keys: Vec<KeyCode> = self.held.iter().filter(|(s, _)| *s == source).map(|(_, k)| *k).collect();
+    keys.into_iter().filter(|k| self.accept(source, &Released(*k))).map(Released).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use KeyCode::*;
+
+  #[test]
+  fn test_same_key_on_two_keyboards() {
+    let mut held = MergedHeldKeys::new();
+
+    assert!(held.accept(0, &Pressed(LEFTSHIFT)));
+    assert!(!held.accept(1, &Pressed(LEFTSHIFT)));
+    assert!(!held.accept(0, &Released(LEFTSHIFT)));
+    assert!(held.accept(1, &Pressed(A)));
+    assert!(held.accept(1, &Released(A)));
+    assert!(held.accept(1, &Released(LEFTSHIFT)));
+
+    // Held from before it was merged
+    assert!(!held.accept(0, &Released(B)));
+  }
+
+  #[test]
+  fn test_remove_source() {
+    let mut held = MergedHeldKeys::new();
+
+    held.accept(0, &Pressed(LEFTCTRL));
+    held.accept(0, &Pressed(LEFTSHIFT));
+    held.accept(1, &Pressed(LEFTSHIFT));
+    held.accept(0, &Pressed(C));
+
+    assert_eq!(vec![Released(LEFTCTRL), Released(C)], held.remove_source(0));
+    assert!(held.accept(1, &Released(LEFTSHIFT)));
+  }
+}
@@ -4,7 +4,8 @@
 use serde::{Deserialize, Serialize};
 pub use crate::key_codes::KeyCode; 
 use std::default::Default;
-pub use crate::events::Event;
+use std::collections::BTreeMap;
+pub use crate::events::{Event, Axis};
 pub use Event::Pressed;
 pub use Event::Released;
 
@@ -15,7 +16,41 @@ pub struct Mapping {
   #[serde(default = "normal_repeat")]
   pub repeat: Repeat,
   #[serde(default = "Vec::new")]
-  pub absorbing: Vec<KeyCode>
+  pub absorbing: Vec<KeyCode>,
+  #[serde(default = "no_tap")]
+  pub tap: Option<Tap>,
+  #[serde(default)]
+  pub one_shot: bool,
+  #[serde(default = "no_latch")]
+  pub latch: Option<Latch>,
+  #[serde(default = "no_combo")]
+  pub combo: Option<Combo>,
+  #[serde(default = "no_sequence")]
+  pub sequence: Option<Sequence>,
+  #[serde(default = "Vec::new")]
+  pub macro_steps: Vec<MacroStep>,
+  #[serde(default = "no_action")]
+  pub action: Option<Action>,
+  #[serde(default = "no_mouse")]
+  pub mouse: Option<Mouse>,
+  #[serde(default = "default_priority")]
+  pub priority: i32,
+  // None of these keys may be held
+  #[serde(default = "Vec::new")]
+  pub unless: Vec<KeyCode>,
+  // No keys other than `from` may be held
+  #[serde(default)]
+  pub exclusive: bool,
+  // All of these keyboard LEDs must be on
+  #[serde(default = "Vec::new")]
+  pub if_led: Vec<Led>,
+  // Typed when the last key of `from` is released, if no other key was
+  // pressed while it was down
+  #[serde(default = "Vec::new")]
+  pub to_if_alone: Vec<KeyCode>,
+  // Other output modifiers are released while the mapping is active
+  #[serde(default)]
+  pub clear_modifiers: bool
 }
 
 impl Default for Mapping {
@@ -24,7 +59,21 @@ impl Default for Mapping {
       from: vec![],
       to: vec![],
       repeat: Repeat::Normal,
-      absorbing: vec![]
+      absorbing: vec![],
+      tap: None,
+      one_shot: false,
+      latch: None,
+      combo: None,
+      sequence: None,
+      macro_steps: vec![],
+      action: None,
+      mouse: None,
+      priority: 0,
+      unless: vec![],
+      exclusive: false,
+      if_led: vec![],
+      to_if_alone: vec![],
+      clear_modifiers: false
     }
   }
 }
@@ -37,6 +86,13 @@ pub enum Repeat {
     keys: Vec<KeyCode>,
     delay_ms: i32,
     interval_ms: i32
+  },
+  // Instead of repeating, a key held for `delay_ms` types `keys`
+  // (usually the shifted form of `to`) once. Releasing it sooner
+  // types `to` as usual.
+  AutoShift {
+    keys: Vec<KeyCode>,
+    delay_ms: i32
   }
 }
 
@@ -44,8 +100,174 @@ pub fn normal_repeat() -> Repeat {
   Repeat::Normal
 }
 
+// When a mapping has a tap, `to` is only applied once the key is held
+// past `tapping_term_ms` or another key is pressed. Releasing it before
+// then types `keys` instead. With `extra_taps` the key can be tapped
+// again within `tapping_term_ms` to type the second, third, ... of them
+// instead (a tap dance).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Tap {
+  pub keys: Vec<KeyCode>,
+  pub tapping_term_ms: i32,
+  #[serde(default = "Vec::new")]
+  pub extra_taps: Vec<Vec<KeyCode>>
+}
+
+pub fn no_tap() -> Option<Tap> {
+  None
+}
+
+// Latched keys count as held for matching `from` until they are
+// cleared again, without being physically pressed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Latch {
+  pub action: LatchAction,
+  pub keys: Vec<KeyCode>
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LatchAction {
+  Toggle,
+  Lock,
+  Clear
+}
+
+pub fn no_latch() -> Option<Latch> {
+  None
+}
+
+// A combo matches when all keys in `from` are pressed, in any order,
+// within `window_ms` of the first one.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Combo {
+  pub window_ms: i32
+}
+
+pub fn no_combo() -> Option<Combo> {
+  None
+}
+
+// A sequence matches when the keys in `from` are tapped one after
+// another, each within `timeout_ms` of the last. The first key starts
+// the sequence when it is tapped on its own.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Sequence {
+  pub timeout_ms: i32
+}
+
+pub fn no_sequence() -> Option<Sequence> {
+  None
+}
+
+// When a mapping has macro steps, pressing it taps each chord in turn
+// instead of holding `to`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MacroStep {
+  Tap(Vec<KeyCode>),
+  Delay(i32)
+}
+
+// Actions change how the mapper behaves rather than producing keys.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Action {
+  // Shift letters until a key that isn't part of a word is pressed
+  CapsWord,
+  // Stop remapping, passing every key through unchanged, until
+  // triggered again
+  TogglePause,
+  // Switch to one of the layout's `named_layouts`
+  SwitchLayout(String),
+  // Switch to the `named_layouts` entry after the current one
+  NextLayout
+}
+
+pub fn no_action() -> Option<Action> {
+  None
+}
+
+// While a mouse mapping is held the pointer moves or scrolls by this
+// much every tick, speeding up the longer it is held. Positive `y`
+// is down.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Mouse {
+  Move { x: i32, y: i32 },
+  Scroll { x: i32, y: i32 }
+}
+
+pub fn no_mouse() -> Option<Mouse> {
+  None
+}
+
+// The lock LEDs, as set on the virtual keyboard by whoever owns them
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Led {
+  Num,
+  Caps,
+  Scroll
+}
+
+pub fn default_priority() -> i32 {
+  0
+}
+
+// Drops a press that follows a release of the same key within
+// `delay_ms`, for keyboards whose switches chatter. `device` is a
+// glob-style pattern matched against the keyboard name; without one the
+// rule applies to every keyboard. `keys` overrides the delay per key.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Debounce {
+  #[serde(default = "any_device")]
+  pub device: Option<String>,
+  pub delay_ms: i32,
+  #[serde(default = "BTreeMap::new")]
+  pub keys: BTreeMap<KeyCode, i32>
+}
+
+pub fn any_device() -> Option<String> {
+  None
+}
+
+pub fn no_debounce() -> Vec<Debounce> {
+  Vec::new()
+}
+
+pub fn default_emergency_chord() -> Vec<KeyCode> {
+  vec![KeyCode::BACKSPACE, KeyCode::ESC, KeyCode::ENTER]
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Layout {
+  pub mappings: Vec<Mapping>,
+  #[serde(default = "no_debounce")]
+  pub debounce: Vec<Debounce>,
+  // Holding all of these together ungrabs the keyboard and exits, in
+  // case a layout leaves the keyboard unusable
+  #[serde(default = "default_emergency_chord")]
+  pub emergency_chord: Vec<KeyCode>,
+  // Layouts that mappings can switch between, when the file has
+  // several. `mappings` is the first of them.
+  #[serde(default = "Vec::new")]
+  pub named_layouts: Vec<NamedLayout>,
+  // Whether switching on one keyboard switches all of them
+  #[serde(default)]
+  pub switch_all_keyboards: bool
+}
+
+impl Default for Layout {
+  fn default() -> Self {
+    Layout {
+      mappings: vec![],
+      debounce: no_debounce(),
+      emergency_chord: default_emergency_chord(),
+      named_layouts: vec![],
+      switch_all_keyboards: false
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct NamedLayout {
+  pub name: String,
   pub mappings: Vec<Mapping>
 }
 
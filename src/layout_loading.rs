@@ -3,6 +3,7 @@
 
 use crate::keys::Layout;
 use std::fs::OpenOptions;
+use std::io::Read;
 
 fn convert_io_error<T>(whats_happening: &str, res: Result<T, std::io::Error>) -> Result<T, String> {
   match res {
@@ -19,24 +20,32 @@ fn convert_json_error<T>(whats_happening: &str, res: Result<T, serde_json::Error
 }
 
 pub fn load_layout_from_file(path: &str) -> Result<Layout, String> {
-  let file_in =
+  load_layout_and_json_from_file(path).map(|(_, layout)| layout)
+}
+
+// The file's text as well, so it is only read once
+pub fn load_layout_and_json_from_file(path: &str) -> Result<(String, Layout), String> {
+  let mut file_in =
     convert_io_error(
       format!("reading {}", path).as_str(),
       OpenOptions::new()
         .truncate(false).read(true).create(false).write(false)
         .open(path)
     )?;
-      
-  let buf_reader = std::io::BufReader::new(file_in);
   
-  crate::fancy_layout_interpreting::convert(
+  let mut text = String::new();
+  convert_io_error(format!("reading {}", path).as_str(), file_in.read_to_string(&mut text))?;
+  
+  let layout = crate::fancy_layout_interpreting::convert(
     &crate::layout_parsing_formatting::parse_layout_from_json(
       &convert_json_error(
         format!("parsing {}", path).as_str(),
-        serde_json::from_reader(buf_reader)
+        serde_json::from_str(&text)
       )?
     )?
-  )
+  )?;
+  
+  Ok((text, layout))
 }
 
 #[cfg(test)]
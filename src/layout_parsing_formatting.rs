@@ -3,58 +3,61 @@ use std::str::FromStr;
 use key_codes::KeyCode;
 use serde_json::{Value, Map};
 use Value::{Object, Array};
-use crate::{fancy_keys::{Layout, Mapping, SingleMapping, AliasMapping, RowMapping, Modifier, SingleFromKeys, RowFromKeys, SingleToKeys, RowToKeys, SingleTerminalToKey, SingleRepeat, RowRepeat, Row, AliasToKeys, AliasFromKeys, RepeatOnlySingleMapping}, key_codes};
+use crate::{fancy_keys::{Layout, Mapping, SingleMapping, AliasMapping, RowMapping, Modifier, SingleFromKeys, RowFromKeys, SingleToKeys, RowToKeys, SingleTerminalToKey, SingleRepeat, RowRepeat, Row, AliasToKeys, AliasFromKeys, RepeatOnlySingleMapping, Tap, DEFAULT_TAPPING_TERM_MS, LatchMapping, LatchAction, ComboMapping, DEFAULT_COMBO_WINDOW_MS, SequenceMapping, DEFAULT_SEQUENCE_TIMEOUT_MS, MAX_MOUSE_AMOUNT, MacroMapping, MacroStep, ActionMapping, Action, MouseMapping, Mouse, Debounce, NamedLayout, PriorityMapping, ConditionalMapping, ClearModifiersMapping, Led}, key_codes};
 use serde_json::Value as j;
+use crate::unicode_input::{DEFAULT_UNICODE_METHOD, parse_unicode_method, unicode_method_name};
 use serde_json::json;
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::collections::{HashMap, BTreeMap};
 
 pub fn parse_layout_from_json(root: &Value) -> Result<Layout, String> {
   match root {
     Object(root_values) => {
-      if has_exactly_keys(root_values, &vec!["mappings"]) {
-        let mappings_v = root_values.get("mappings").unwrap();
-        match mappings_v {
-          Array(mapping_vs) => {
-            let mut mappings = Vec::new();
-              
-            for mapping_v in mapping_vs {
-              match parse_mapping_from_json(mapping_v) {
-                Ok(m) => mappings.push(m),
-                Err(e) => return Err(format!("Malformed mapping {}: {}", mapping_v, e)),
-              }
-            }
-            
-            let mut defined_alias_names = std::collections::HashSet::new();
-            for m in &mappings {
-              match m {
-                Mapping::Alias(alias) => {
-                  defined_alias_names.insert(alias.to.terminal.clone());
-                }
-                _ => ()
-              }
-            }
-            
-            for m in &mappings {
-              let used_aliases = mapping_all_used_aliases(m);
-              for a in &used_aliases {
-                if !defined_alias_names.contains(a) {
-                  return Err(format!("Error in mapping {}: alias {} is not defined", format_mapping(m), a));
-                }
-              }
-            }
-            
-            Ok(Layout {
-              mappings
-            })
-          },
-          _ => {
-            Err("\"mappings\" must be an array".to_owned())
-          }
+      let known_keys = ["mappings", "debounce", "layouts", "switch_all_keyboards", "emergency_chord"];
+      if root_values.keys().all(|k| known_keys.contains(&k.as_str())) && (root_values.contains_key("mappings") || root_values.contains_key("layouts")) {
+        let mappings = match root_values.get("mappings") {
+          None => Vec::new(),
+          Some(v) => parse_mappings(v)?
+        };
+        
+        let named_layouts = match root_values.get("layouts") {
+          None => Vec::new(),
+          Some(v) => parse_named_layouts(v)?
+        };
+        
+        if named_layouts.is_empty() {
+          check_aliases_defined(&mappings, &[])?;
         }
+        for named in &named_layouts {
+          check_aliases_defined(&mappings, &named.mappings).map_err(|e| format!("In layout {}: {}", named.name, e))?;
+        }
+        
+        let debounce = match root_values.get("debounce") {
+          None => Vec::new(),
+          Some(v) => parse_debounce(v)?
+        };
+        
+        let switch_all_keyboards = match root_values.get("switch_all_keyboards") {
+          None => false,
+          Some(j::Bool(b)) => *b,
+          Some(v) => Err(format!("\"switch_all_keyboards\" must be true or false, found {}", v))?
+        };
+        
+        let emergency_chord = match root_values.get("emergency_chord") {
+          None => None,
+          Some(v) => Some(parse_emergency_chord(v)?)
+        };
+        
+        Ok(Layout {
+          mappings,
+          debounce,
+          named_layouts,
+          switch_all_keyboards,
+          emergency_chord
+        })
       }
       else {
-        Err("Layout must have a single field \"mappings\"".to_owned())
+        Err(format!("Layout must have the field \"mappings\" or \"layouts\", and optionally \"debounce\", \"switch_all_keyboards\" and \"emergency_chord\", found {}", keys_string(root_values)))
       }
     },
     _ => {
@@ -63,6 +66,75 @@ pub fn parse_layout_from_json(root: &Value) -> Result<Layout, String> {
   }
 }
 
+fn parse_mappings(v: &Value) -> Result<Vec<Mapping>, String> {
+  match v {
+    Array(mapping_vs) => {
+      let mut mappings = Vec::new();
+      
+      for mapping_v in mapping_vs {
+        match parse_mapping_from_json(mapping_v) {
+          Ok(m) => mappings.push(m),
+          Err(e) => return Err(format!("Malformed mapping {}: {}", mapping_v, e)),
+        }
+      }
+      
+      Ok(mappings)
+    },
+    _ => {
+      Err("\"mappings\" must be an array".to_owned())
+    }
+  }
+}
+
+// Named layouts can use aliases from the shared mappings as well as
+// their own
+fn check_aliases_defined(shared: &[Mapping], own: &[Mapping]) -> Result<(), String> {
+  let mut defined_alias_names = std::collections::HashSet::new();
+  for m in shared.iter().chain(own) {
+    if let Mapping::Alias(alias) = m {
+      defined_alias_names.insert(alias.to.terminal.clone());
+    }
+  }
+  
+  for m in shared.iter().chain(own) {
+    let used_aliases = mapping_all_used_aliases(m);
+    for a in &used_aliases {
+      if !defined_alias_names.contains(a) {
+        return Err(format!("Error in mapping {}: alias {} is not defined", format_mapping(m), a));
+      }
+    }
+  }
+  
+  Ok(())
+}
+
+fn parse_named_layouts(v: &Value) -> Result<Vec<NamedLayout>, String> {
+  let layout_vs = match v {
+    Array(layout_vs) if !layout_vs.is_empty() => layout_vs,
+    _ => return Err(format!("\"layouts\" must be a non-empty array, found {}", v))
+  };
+  
+  let mut res: Vec<NamedLayout> = Vec::new();
+  for layout_v in layout_vs {
+    match layout_v {
+      Object(layout_values) if has_exactly_keys(layout_values, &vec!["name", "mappings"]) => {
+        let name = match layout_values.get("name").unwrap() {
+          j::String(name) => name.clone(),
+          name_v => return Err(format!("Layout `name` must be a string, found {}", name_v))
+        };
+        if res.iter().any(|named| named.name == name) {
+          return Err(format!("There is more than one layout named {}", name));
+        }
+        let mappings = parse_mappings(layout_values.get("mappings").unwrap()).map_err(|e| format!("In layout {}: {}", name, e))?;
+        res.push(NamedLayout { name, mappings });
+      },
+      _ => return Err(format!("Each of \"layouts\" must have only `name` and `mappings`, found {}", layout_v))
+    }
+  }
+  
+  Ok(res)
+}
+
 fn just_mods(m: &Modifier) -> Option<String> {
   match m {
     Modifier::Alias(name) => Some(name.clone()),
@@ -82,6 +154,14 @@ fn mapping_all_used_aliases(m: &Mapping) -> Vec<String> {
           _ => &none
         }).iter().filter_map(just_mods))
         .chain(single.absorbing.iter().filter_map(just_mods))
+        .chain((match &single.to_if_alone {
+          Some(keys) => &keys.initial,
+          None => &none
+        }).iter().filter_map(just_mods))
+        .chain((match &single.tap {
+          Some(tap) => std::iter::once(&tap.keys).chain(tap.extra_taps.iter()).flat_map(|keys| keys.initial.iter()).collect(),
+          None => vec![]
+        }).into_iter().filter_map(just_mods))
         .collect()
     },
     Mapping::Row(row) => {
@@ -94,6 +174,40 @@ fn mapping_all_used_aliases(m: &Mapping) -> Vec<String> {
         .chain(row.absorbing.iter().filter_map(just_mods))
         .collect()
     },
+    Mapping::Latch(latch) => {
+      latch.from.modifiers.iter().filter_map(just_mods)
+        .chain(std::iter::once(latch.alias.clone()))
+        .collect()
+    },
+    Mapping::Combo(combo) => {
+      combo.to.initial.iter().filter_map(just_mods).collect()
+    },
+    Mapping::Sequence(sequence) => {
+      sequence.to.initial.iter().filter_map(just_mods).collect()
+    },
+    Mapping::Macro(macro_mapping) => {
+      macro_mapping.from.modifiers.iter().filter_map(just_mods)
+        .chain(macro_mapping.steps.iter().flat_map(|step| match step {
+          MacroStep::Tap(keys) => keys.initial.iter().filter_map(just_mods).collect(),
+          MacroStep::Delay(_) => vec![],
+          MacroStep::Text(_) => vec![],
+          MacroStep::Unicode { .. } => vec![]
+        }))
+        .collect()
+    },
+    Mapping::Action(action) => {
+      action.from.modifiers.iter().filter_map(just_mods).collect()
+    },
+    Mapping::Mouse(mouse) => {
+      mouse.from.modifiers.iter().filter_map(just_mods).collect()
+    },
+    Mapping::Priority(priority) => mapping_all_used_aliases(&priority.mapping),
+    Mapping::Conditional(conditional) => {
+      conditional.unless.iter().filter_map(just_mods)
+        .chain(mapping_all_used_aliases(&conditional.mapping))
+        .collect()
+    },
+    Mapping::ClearModifiers(clear) => mapping_all_used_aliases(&clear.mapping),
     Mapping::RepeatOnlySingle(single) => {
       single.from.modifiers.iter().filter_map(just_mods)
         .chain((match &single.repeat {
@@ -108,11 +222,106 @@ fn mapping_all_used_aliases(m: &Mapping) -> Vec<String> {
 fn parse_mapping_from_json(mapping_v: &Value) -> Result<Mapping, String> {
   match mapping_v {
     Object(mapping_values) => {
-      if has_at_least_keys(mapping_values, &vec!["from", "to"]) {
+      if let Some(priority_v) = mapping_values.get("priority") {
+        let priority = parse_priority(priority_v)?;
+        let mut rest = mapping_values.clone();
+        rest.remove("priority");
+        match parse_mapping_from_json(&Object(rest))? {
+          Mapping::Alias(_) | Mapping::RepeatOnlySingle(_) | Mapping::Combo(_) | Mapping::Sequence(_) => {
+            Err("`priority` is not allowed for alias, repeat-only, combo or sequence mappings".to_owned())
+          },
+          mapping => Ok(Mapping::Priority(PriorityMapping { priority, mapping: Box::new(mapping) }))
+        }
+      }
+      else if mapping_values.contains_key("unless") || mapping_values.contains_key("exclusive") || mapping_values.contains_key("if_led") {
+        let unless = match mapping_values.get("unless") {
+          None => vec![],
+          Some(j::Array(mod_vs)) => parse_from_modifiers(mod_vs)?,
+          Some(v) => Err(format!("`unless` must be a list of keys, found {}", v))?
+        };
+        let exclusive = match mapping_values.get("exclusive") {
+          None => false,
+          Some(j::Bool(b)) => *b,
+          Some(v) => Err(format!("`exclusive` must be true or false, found {}", v))?
+        };
+        let if_led = match mapping_values.get("if_led") {
+          None => vec![],
+          Some(j::Array(led_vs)) => led_vs.iter().map(parse_led).collect::<Result<Vec<Led>, String>>()?,
+          Some(v) => Err(format!("`if_led` must be a list of LEDs, found {}", v))?
+        };
+        let mut rest = mapping_values.clone();
+        rest.remove("unless");
+        rest.remove("exclusive");
+        rest.remove("if_led");
+        match parse_mapping_from_json(&Object(rest))? {
+          Mapping::Alias(_) | Mapping::RepeatOnlySingle(_) | Mapping::Combo(_) | Mapping::Sequence(_) => {
+            Err("`unless`, `exclusive` and `if_led` are not allowed for alias, repeat-only, combo or sequence mappings".to_owned())
+          },
+          mapping => Ok(Mapping::Conditional(ConditionalMapping { unless, exclusive, if_led, mapping: Box::new(mapping) }))
+        }
+      }
+      else if let Some(clear_v) = mapping_values.get("clear_modifiers") {
+        let clear = match clear_v {
+          j::Bool(b) => *b,
+          v => Err(format!("`clear_modifiers` must be true or false, found {}", v))?
+        };
+        let mut rest = mapping_values.clone();
+        rest.remove("clear_modifiers");
+        match parse_mapping_from_json(&Object(rest))? {
+          Mapping::Alias(_) | Mapping::RepeatOnlySingle(_) | Mapping::Combo(_) | Mapping::Sequence(_) => {
+            Err("`clear_modifiers` is not allowed for alias, repeat-only, combo or sequence mappings".to_owned())
+          },
+          mapping if clear => Ok(Mapping::ClearModifiers(ClearModifiersMapping { mapping: Box::new(mapping) })),
+          mapping => Ok(mapping)
+        }
+      }
+      else if mapping_values.contains_key("combo") {
+        parse_combo_mapping(mapping_values)
+      }
+      else if mapping_values.contains_key("sequence") {
+        parse_sequence_mapping(mapping_values)
+      }
+      else if has_at_least_keys(mapping_values, &vec!["from", "to"]) {
         let from = parse_from(mapping_values.get("from").unwrap())?;
         match from {
           FromKeys::Single(from) => {
-            let to = parse_single_or_alias_to(mapping_values.get("to").unwrap())?;
+            if let Some((action, alias)) = parse_latch_to(mapping_values.get("to").unwrap())? {
+              if !has_exactly_keys(mapping_values, &vec!["from", "to"]) {
+                Err(format!("Latch mappings must have only `from` and `to`, found {}", keys_string(mapping_values)))?;
+              }
+              return Ok(Mapping::Latch(LatchMapping { from, action, alias }));
+            }
+            
+            if let Some(action) = parse_action_to(mapping_values.get("to").unwrap())? {
+              if !has_exactly_keys(mapping_values, &vec!["from", "to"]) {
+                Err(format!("Action mappings must have only `from` and `to`, found {}", keys_string(mapping_values)))?;
+              }
+              return Ok(Mapping::Action(ActionMapping { from, action }));
+            }
+            
+            if let Some(mouse) = parse_mouse_to(mapping_values.get("to").unwrap())? {
+              if !has_exactly_keys(mapping_values, &vec!["from", "to"]) {
+                Err(format!("Mouse mappings must have only `from` and `to`, found {}", keys_string(mapping_values)))?;
+              }
+              return Ok(Mapping::Mouse(MouseMapping { from, mouse }));
+            }
+            
+            if let Some(steps) = parse_macro_to(mapping_values.get("to").unwrap())? {
+              if !has_exactly_keys(mapping_values, &vec!["from", "to"]) {
+                Err(format!("Macro mappings must have only `from` and `to`, found {}", keys_string(mapping_values)))?;
+              }
+              return Ok(Mapping::Macro(MacroMapping { from, steps }));
+            }
+            
+            let (to, tap) = parse_tap_hold_or_single_or_alias_to(mapping_values.get("to").unwrap())?;
+            let one_shot = parse_one_shot(&mapping_values.get("one_shot"))?;
+            if one_shot && !from.modifiers.is_empty() {
+              return Err(format!("Error in mapping {}: a `one_shot` mapping must be from a single key", mapping_v));
+            }
+            let to_if_alone = parse_to_if_alone(&mapping_values.get("to_if_alone"))?;
+            if to_if_alone.is_some() && (tap.is_some() || one_shot) {
+              return Err(format!("Error in mapping {}: `to_if_alone` can't be combined with a tap or `one_shot`", mapping_v));
+            }
             match to {
               SingleOrAliasToKeys::Single(to) => {
                 let repeat = parse_single_repeat(&mapping_values.get("repeat"))?;
@@ -124,17 +333,19 @@ fn parse_mapping_from_json(mapping_v: &Value) -> Result<Mapping, String> {
                 }
                 
                 Ok(Mapping::Single(SingleMapping {
-                  from, to, repeat, absorbing
+                  from, to, repeat, absorbing, tap, one_shot, to_if_alone
                 }))
               },
               SingleOrAliasToKeys::Alias(to) => {
                 if mapping_values.contains_key("repeat") { Err("`repeat` not allowed for alias mappings")?; }
                 if mapping_values.contains_key("absorbing") { Err("`absorbing` not allowed for alias mappings")?; }
-                Ok(Mapping::Alias(AliasMapping { from: single_to_alias_from(&from)?, to }))
+                Ok(Mapping::Alias(AliasMapping { from: single_to_alias_from(&from)?, to, tap, one_shot, to_if_alone }))
               }
             }
           },
           FromKeys::Row(from) => {
+            if mapping_values.contains_key("one_shot") { Err("`one_shot` not allowed for row mappings")?; }
+            if mapping_values.contains_key("to_if_alone") { Err("`to_if_alone` not allowed for row mappings")?; }
             let to = parse_row_to(mapping_values.get("to").unwrap())?;
             let repeat = parse_row_repeat(&mapping_values.get("repeat"))?;
             match &repeat {
@@ -189,6 +400,69 @@ fn parse_mapping_from_json(mapping_v: &Value) -> Result<Mapping, String> {
   }
 }
 
+fn parse_emergency_chord(v: &Value) -> Result<Vec<KeyCode>, String> {
+  let keys = match v {
+    j::Array(elems) => elems.iter().map(parse_key_code_j).collect::<Result<Vec<KeyCode>, String>>()?,
+    v => Err(format!("\"emergency_chord\" must be a list of keys, found {}", v))?
+  };
+  // An empty or one-key chord would leave no way out, or exit by accident
+  if keys.len() < 2 {
+    Err(format!("\"emergency_chord\" must have at least two keys, found {}", v))?;
+  }
+  Ok(keys)
+}
+
+fn parse_combo_mapping(mapping_values: &Map<String, Value>) -> Result<Mapping, String> {
+  if has_exactly_keys(mapping_values, &vec!["combo", "to"]) || has_exactly_keys(mapping_values, &vec!["combo", "to", "window_ms"]) {
+    let keys = match mapping_values.get("combo").unwrap() {
+      j::Array(elems) => elems.iter().map(parse_key_code_j).collect::<Result<Vec<KeyCode>, String>>()?,
+      v => Err(format!("`combo` must be a list of keys, found {}", v))?
+    };
+    if keys.len() < 2 {
+      Err("`combo` must have at least two keys")?;
+    }
+    for i in 0 .. keys.len() {
+      if keys[i+1 ..].contains(&keys[i]) {
+        Err(format!("Key {} appears twice in `combo`", keys[i]))?;
+      }
+    }
+    
+    let to = parse_single_to(mapping_values.get("to").unwrap())?;
+    let window_ms = match mapping_values.get("window_ms") {
+      None => DEFAULT_COMBO_WINDOW_MS,
+      Some(v) => parse_combo_window_ms(v)?
+    };
+    
+    Ok(Mapping::Combo(ComboMapping { keys, to, window_ms }))
+  }
+  else {
+    Err(format!("Combo mapping must have attributes `combo`, `to`, and optionally `window_ms`, found {}", keys_string(mapping_values)))
+  }
+}
+
+fn parse_sequence_mapping(mapping_values: &Map<String, Value>) -> Result<Mapping, String> {
+  if has_exactly_keys(mapping_values, &vec!["sequence", "to"]) || has_exactly_keys(mapping_values, &vec!["sequence", "to", "timeout_ms"]) {
+    let keys = match mapping_values.get("sequence").unwrap() {
+      j::Array(elems) => elems.iter().map(parse_key_code_j).collect::<Result<Vec<KeyCode>, String>>()?,
+      v => Err(format!("`sequence` must be a list of keys, found {}", v))?
+    };
+    if keys.len() < 2 {
+      Err("`sequence` must have at least two keys")?;
+    }
+    
+    let to = parse_single_to(mapping_values.get("to").unwrap())?;
+    let timeout_ms = match mapping_values.get("timeout_ms") {
+      None => DEFAULT_SEQUENCE_TIMEOUT_MS,
+      Some(v) => parse_sequence_timeout_ms(v)?
+    };
+    
+    Ok(Mapping::Sequence(SequenceMapping { keys, to, timeout_ms }))
+  }
+  else {
+    Err(format!("Sequence mapping must have attributes `sequence`, `to`, and optionally `timeout_ms`, found {}", keys_string(mapping_values)))
+  }
+}
+
 fn single_to_alias_from(from: &SingleFromKeys) -> Result<AliasFromKeys, String> {
   let mut keys = Vec::new();
   
@@ -348,6 +622,207 @@ fn parse_single_or_alias_to(to_v: &Value) -> Result<SingleOrAliasToKeys, String>
   }
 }
 
+fn parse_tap_hold_or_single_or_alias_to(to_v: &Value) -> Result<(SingleOrAliasToKeys, Option<Tap>), String> {
+  if let j::Object(to_attrs) = to_v {
+    if to_attrs.contains_key("tap") || to_attrs.contains_key("hold") {
+      return parse_tap_hold_to(to_attrs);
+    }
+  }
+  
+  Ok((parse_single_or_alias_to(to_v)?, None))
+}
+
+fn parse_tap_hold_to(to_attrs: &Map<String, Value>) -> Result<(SingleOrAliasToKeys, Option<Tap>), String> {
+  let tap_name = if to_attrs.contains_key("taps") { "taps" } else { "tap" };
+  if has_exactly_keys(to_attrs, &vec![tap_name, "hold"]) || has_exactly_keys(to_attrs, &vec![tap_name, "hold", "tapping_term_ms"]) {
+    let hold = parse_single_or_alias_to(to_attrs.get("hold").unwrap())?;
+    let (keys, extra_taps) = match to_attrs.get(tap_name).unwrap() {
+      j::Array(tap_vs) if tap_name == "taps" => {
+        if tap_vs.len() < 2 {
+          Err("`taps` must have at least two entries; use `tap` for just one")?;
+        }
+        let mut taps = tap_vs.iter().map(parse_single_to).collect::<Result<Vec<SingleToKeys>, String>>()?;
+        let keys = taps.remove(0);
+        (keys, taps)
+      },
+      v if tap_name == "taps" => Err(format!("`taps` must be a list, found {}", v))?,
+      v => (parse_single_to(v)?, vec![])
+    };
+    let tapping_term_ms = match to_attrs.get("tapping_term_ms") {
+      None => DEFAULT_TAPPING_TERM_MS,
+      Some(v) => parse_tapping_term_ms(v)?
+    };
+    
+    Ok((hold, Some(Tap { keys, tapping_term_ms, extra_taps })))
+  }
+  else {
+    Err(format!("Tap-hold `to` must have attributes `tap` or `taps`, `hold`, and optionally `tapping_term_ms`, found {}", keys_string(to_attrs)))
+  }
+}
+
+fn parse_latch_to(to_v: &Value) -> Result<Option<(LatchAction, String)>, String> {
+  if let j::Object(to_attrs) = to_v {
+    for (name, action) in [("toggle", LatchAction::Toggle), ("lock", LatchAction::Lock), ("clear", LatchAction::Clear)] {
+      if let Some(alias_v) = to_attrs.get(name) {
+        if to_attrs.len() != 1 {
+          Err(format!("Latch `to` must have only one of `toggle`, `lock` or `clear`, found {}", keys_string(to_attrs)))?;
+        }
+        return match alias_v {
+          j::String(alias) if alias.starts_with("@") => Ok(Some((action, alias.clone()))),
+          _ => Err(format!("`{}` must name an alias, found {}", name, alias_v))
+        };
+      }
+    }
+  }
+  
+  Ok(None)
+}
+
+fn parse_action_to(to_v: &Value) -> Result<Option<Action>, String> {
+  if let j::Object(to_attrs) = to_v {
+    if let Some(action_v) = to_attrs.get("action") {
+      let name = match action_v {
+        j::String(name) => name,
+        _ => return Err(format!("`action` must be a string, found {}", action_v))
+      };
+      if name == "switch_layout" {
+        if !has_exactly_keys(to_attrs, &vec!["action", "layout"]) {
+          Err(format!("A `switch_layout` action must have only `action` and `layout`, found {}", keys_string(to_attrs)))?;
+        }
+        return match to_attrs.get("layout").unwrap() {
+          j::String(layout) => Ok(Some(Action::SwitchLayout(layout.clone()))),
+          layout_v => Err(format!("`layout` must be a string, found {}", layout_v))
+        };
+      }
+      if to_attrs.len() != 1 {
+        Err(format!("Action `to` must have only `action`, found {}", keys_string(to_attrs)))?;
+      }
+      return Ok(Some(parse_action(name)?));
+    }
+  }
+  
+  Ok(None)
+}
+
+fn parse_led(v: &Value) -> Result<Led, String> {
+  match v.as_str() {
+    Some("NUMLOCK") => Ok(Led::Num),
+    Some("CAPSLOCK") => Ok(Led::Caps),
+    Some("SCROLLLOCK") => Ok(Led::Scroll),
+    _ => Err(format!("Unknown LED: {}; expected NUMLOCK, CAPSLOCK or SCROLLLOCK", v))
+  }
+}
+
+fn format_led(led: &Led) -> Value {
+  json!(match led {
+    Led::Num => "NUMLOCK",
+    Led::Caps => "CAPSLOCK",
+    Led::Scroll => "SCROLLLOCK"
+  })
+}
+
+fn parse_action(name: &str) -> Result<Action, String> {
+  match name {
+    "caps_word" => Ok(Action::CapsWord),
+    "toggle_pause" => Ok(Action::TogglePause),
+    "next_layout" => Ok(Action::NextLayout),
+    _ => Err(format!("Unknown action: {}", name))
+  }
+}
+
+fn parse_mouse_to(to_v: &Value) -> Result<Option<Mouse>, String> {
+  if let j::Object(to_attrs) = to_v {
+    for name in ["mouse_move", "mouse_scroll"] {
+      if let Some(amount_v) = to_attrs.get(name) {
+        if to_attrs.len() != 1 {
+          Err(format!("Mouse `to` must have only one of `mouse_move` or `mouse_scroll`, found {}", keys_string(to_attrs)))?;
+        }
+        let (x, y) = parse_mouse_amount(name, amount_v)?;
+        return Ok(Some(if name == "mouse_move" { Mouse::Move { x, y } } else { Mouse::Scroll { x, y } }));
+      }
+    }
+  }
+  
+  Ok(None)
+}
+
+fn parse_mouse_amount(name: &str, v: &Value) -> Result<(i32, i32), String> {
+  match v {
+    j::Array(elems) if elems.len() == 2 => {
+      let mut amounts = Vec::new();
+      for elem in elems {
+        match elem.as_i64() {
+          Some(n) if (-MAX_MOUSE_AMOUNT as i64 ..= MAX_MOUSE_AMOUNT as i64).contains(&n) => amounts.push(n as i32),
+          Some(_) => Err(format!("`{}` amounts must be from -{} to {}, found {}", name, MAX_MOUSE_AMOUNT, MAX_MOUSE_AMOUNT, elem))?,
+          None => Err(format!("`{}` amounts must be whole numbers, found {}", name, elem))?
+        }
+      }
+      Ok((amounts[0], amounts[1]))
+    },
+    _ => Err(format!("`{}` must be a list of two numbers [x, y], found {}", name, v))
+  }
+}
+
+fn parse_macro_to(to_v: &Value) -> Result<Option<Vec<MacroStep>>, String> {
+  if let j::Object(to_attrs) = to_v {
+    if to_attrs.contains_key("text") || to_attrs.contains_key("unicode") {
+      return Ok(Some(vec![parse_macro_step(to_v)?]));
+    }
+    if let Some(steps_v) = to_attrs.get("macro") {
+      if to_attrs.len() != 1 {
+        Err(format!("Macro `to` must have only `macro`, found {}", keys_string(to_attrs)))?;
+      }
+      if let j::Array(step_vs) = steps_v {
+        let mut steps = Vec::new();
+        for step_v in step_vs {
+          steps.push(parse_macro_step(step_v)?);
+        }
+        if steps.is_empty() {
+          Err("`macro` must have at least one step")?;
+        }
+        return Ok(Some(steps));
+      }
+      else {
+        Err(format!("`macro` must be a list of steps, found {}", steps_v))?;
+      }
+    }
+  }
+  
+  Ok(None)
+}
+
+fn parse_macro_step(step_v: &Value) -> Result<MacroStep, String> {
+  if let j::Object(step_attrs) = step_v {
+    if has_exactly_keys(step_attrs, &vec!["delay_ms"]) {
+      Ok(MacroStep::Delay(parse_ms(step_attrs.get("delay_ms").unwrap(), "macro delay_ms")?))
+    }
+    else if has_exactly_keys(step_attrs, &vec!["text"]) {
+      match step_attrs.get("text").unwrap() {
+        j::String(text) => Ok(MacroStep::Text(text.clone())),
+        text_v => Err(format!("`text` must be a string, found {}", text_v))
+      }
+    }
+    else if has_exactly_keys(step_attrs, &vec!["unicode"]) || has_exactly_keys(step_attrs, &vec!["unicode", "method"]) {
+      let text = match step_attrs.get("unicode").unwrap() {
+        j::String(text) => text.clone(),
+        text_v => Err(format!("`unicode` must be a string, found {}", text_v))?
+      };
+      let method = match step_attrs.get("method") {
+        None => DEFAULT_UNICODE_METHOD,
+        Some(j::String(name)) => parse_unicode_method(name)?,
+        Some(method_v) => Err(format!("`method` must be a string, found {}", method_v))?
+      };
+      Ok(MacroStep::Unicode { text, method })
+    }
+    else {
+      Err(format!("Macro step objects must have only `delay_ms`, `text` or `unicode`, found {}", keys_string(step_attrs)))
+    }
+  }
+  else {
+    Ok(MacroStep::Tap(parse_single_to(step_v)?))
+  }
+}
+
 fn parse_single_to(to_v: &Value) -> Result<SingleToKeys, String> {
   if let j::Array(to_elems) = to_v {
     parse_single_to_array(to_elems)
@@ -527,6 +1002,52 @@ fn parse_row_to_obj(to_attrs: &Map<String, Value>) -> Result<String, String> {
   }
 }
 
+fn parse_debounce(v: &Value) -> Result<Vec<Debounce>, String> {
+  match v {
+    j::Array(rule_vs) => rule_vs.iter().map(parse_debounce_rule).collect(),
+    _ => Err(format!("\"debounce\" must be an array, found {}", v))
+  }
+}
+
+fn parse_debounce_rule(v: &Value) -> Result<Debounce, String> {
+  match v {
+    j::Object(rule_values) => {
+      for k in rule_values.keys() {
+        if !["device", "delay_ms", "keys"].contains(&k.as_str()) {
+          Err(format!("Debounce rule must have attributes `delay_ms` and optionally `device` and `keys`, found {}", keys_string(rule_values)))?;
+        }
+      }
+      
+      let device = match rule_values.get("device") {
+        None => None,
+        Some(j::String(pattern)) => Some(pattern.clone()),
+        Some(v) => Err(format!("`device` must be a string, found {}", v))?
+      };
+      let delay_ms = match rule_values.get("delay_ms") {
+        None => Err(format!("Debounce rule must have `delay_ms`, found {}", keys_string(rule_values)))?,
+        Some(v) => parse_debounce_delay_ms(v)?
+      };
+      let mut keys = BTreeMap::new();
+      match rule_values.get("keys") {
+        None => (),
+        Some(j::Object(key_values)) => {
+          for (key, v) in key_values {
+            keys.insert(parse_key_code(key)?, parse_debounce_delay_ms(v)?);
+          }
+        },
+        Some(v) => Err(format!("`keys` must be an object mapping keys to delays, found {}", v))?
+      };
+      
+      Ok(Debounce { device, delay_ms, keys })
+    },
+    _ => Err(format!("Debounce rule must be an object, found {}", v))
+  }
+}
+
+fn parse_debounce_delay_ms(v: &Value) -> Result<i32, String> {
+  parse_ms(v, "debounce delay_ms")
+}
+
 fn parse_key_code_j(v: &Value) -> Result<KeyCode, String> {
   if let j::String(text) = v {
     parse_key_code(text)
@@ -536,7 +1057,7 @@ fn parse_key_code_j(v: &Value) -> Result<KeyCode, String> {
   }
 }
 
-fn parse_key_code(text: &str) -> Result<KeyCode, String> {
+pub fn parse_key_code(text: &str) -> Result<KeyCode, String> {
   if text.starts_with("@") {
     Err(format!("A real key was expected, but alias modifier {} was found", text))
   }
@@ -602,6 +1123,9 @@ fn parse_single_repeat(v: &Option<&Value>) -> Result<SingleRepeat, String> {
           Err(format!("`Special` repeat must be an object, found {}", special))
         }
       }
+      else if has_exactly_keys(params, &vec!["AutoShift"]) {
+        Ok(SingleRepeat::AutoShift { delay_ms: parse_auto_shift(params.get("AutoShift").unwrap())? })
+      }
       else {
         Err(format!("Unknown repeat style: {}", v))
       }
@@ -651,6 +1175,9 @@ fn parse_row_repeat(v: &Option<&Value>) -> Result<RowRepeat, String> {
           Err(format!("`Special` repeat must be an object, found {}", special))
         }
       }
+      else if has_exactly_keys(params, &vec!["AutoShift"]) {
+        Ok(RowRepeat::AutoShift { delay_ms: parse_auto_shift(params.get("AutoShift").unwrap())? })
+      }
       else {
         Err(format!("Unknown repeat style: {}", v))
       }
@@ -664,6 +1191,20 @@ fn parse_row_repeat(v: &Option<&Value>) -> Result<RowRepeat, String> {
   }
 }
 
+fn parse_auto_shift(v: &Value) -> Result<i32, String> {
+  if let j::Object(params) = v {
+    if has_exactly_keys(params, &vec!["delay_ms"]) {
+      parse_ms(params.get("delay_ms").unwrap(), "AutoShift delay_ms")
+    }
+    else {
+      Err(format!("`AutoShift` repeat must have only the attribute `delay_ms`, found {}", keys_string(params)))
+    }
+  }
+  else {
+    Err(format!("`AutoShift` repeat must be an object, found {}", v))
+  }
+}
+
 fn parse_single_repeat_keys(v: &Value) -> Result<SingleToKeys, String> {
   parse_single_to(v)
 }
@@ -690,6 +1231,53 @@ fn parse_repeat_interval_ms(v: &Value) -> Result<i32, String> {
   }
 }
 
+fn parse_tapping_term_ms(v: &Value) -> Result<i32, String> {
+  parse_ms(v, "tapping_term_ms")
+}
+
+fn parse_priority(v: &Value) -> Result<i32, String> {
+  if let j::Number(n) = v {
+    Ok(n.as_i64().ok_or(format!("Invalid priority number: {}", v))? as i32)
+  }
+  else {
+    Err(format!("priority must be a number, found {}", v))
+  }
+}
+
+fn parse_combo_window_ms(v: &Value) -> Result<i32, String> {
+  parse_ms(v, "window_ms")
+}
+
+fn parse_sequence_timeout_ms(v: &Value) -> Result<i32, String> {
+  parse_ms(v, "timeout_ms")
+}
+
+// A number of milliseconds, small enough to add to an `Instant`
+fn parse_ms(v: &Value, what: &str) -> Result<i32, String> {
+  match v {
+    j::Number(n) => match n.as_i64() {
+      Some(ms) if (0 ..= i32::MAX as i64).contains(&ms) => Ok(ms as i32),
+      _ => Err(format!("Invalid {}: {}", what, v))
+    },
+    _ => Err(format!("{} must be a number, found {}", what, v))
+  }
+}
+
+fn parse_one_shot(v: &Option<&Value>) -> Result<bool, String> {
+  match v {
+    None => Ok(false),
+    Some(j::Bool(b)) => Ok(*b),
+    Some(v) => Err(format!("`one_shot` must be true or false, found {}", v))
+  }
+}
+
+fn parse_to_if_alone(v: &Option<&Value>) -> Result<Option<SingleToKeys>, String> {
+  match v {
+    None => Ok(None),
+    Some(v) => Ok(Some(parse_single_to(v)?))
+  }
+}
+
 fn parse_absorbing(v: &Option<&Value>) -> Result<Vec<Modifier>, String> {
   if let Some(v) = v {
     if let j::Array(elems) = v {
@@ -743,7 +1331,45 @@ pub fn format_layout_as_json(layout: &Layout) -> Value {
   let mappings: Vec<Value> = layout.mappings.iter().map(format_mapping).collect();
   
   let mut keys = Map::new();
-  keys.insert("mappings".to_owned(), j::Array(mappings));
+  if !layout.named_layouts.is_empty() {
+    keys.insert("layouts".to_owned(), j::Array(layout.named_layouts.iter().map(|named| json!({
+      "name": named.name,
+      "mappings": j::Array(named.mappings.iter().map(format_mapping).collect())
+    })).collect()));
+  }
+  if !mappings.is_empty() || layout.named_layouts.is_empty() {
+    keys.insert("mappings".to_owned(), j::Array(mappings));
+  }
+  if !layout.debounce.is_empty() {
+    keys.insert("debounce".to_owned(), j::Array(layout.debounce.iter().map(format_debounce_rule).collect()));
+  }
+  if layout.switch_all_keyboards {
+    keys.insert("switch_all_keyboards".to_owned(), json!(true));
+  }
+  if let Some(chord) = &layout.emergency_chord {
+    keys.insert("emergency_chord".to_owned(), j::Array(chord.iter().map(format_key_code).collect()));
+  }
+  
+  j::Object(keys)
+}
+
+#[cfg(test)]
+fn format_debounce_rule(rule: &Debounce) -> Value {
+  let mut keys = Map::new();
+  
+  if let Some(device) = &rule.device {
+    keys.insert("device".to_owned(), json!(device));
+  }
+  keys.insert("delay_ms".to_owned(), json!(rule.delay_ms));
+  if !rule.keys.is_empty() {
+    let mut key_delays = Map::new();
+    for (k, delay_ms) in &rule.keys {
+      if let j::String(name) = format_key_code(k) {
+        key_delays.insert(name, json!(delay_ms));
+      }
+    }
+    keys.insert("keys".to_owned(), j::Object(key_delays));
+  }
   
   j::Object(keys)
 }
@@ -754,6 +1380,15 @@ fn format_mapping(mapping: &Mapping) -> Value {
     Mapping::Alias(alias) => format_alias_mapping(alias),
     Mapping::Row(row) => format_row_mapping(row),
     Mapping::RepeatOnlySingle(single) => format_repeat_only_single_mapping(single),
+    Mapping::Latch(latch) => format_latch_mapping(latch),
+    Mapping::Combo(combo) => format_combo_mapping(combo),
+    Mapping::Sequence(sequence) => format_sequence_mapping(sequence),
+    Mapping::Macro(macro_mapping) => format_macro_mapping(macro_mapping),
+    Mapping::Action(action) => format_action_mapping(action),
+    Mapping::Mouse(mouse) => format_mouse_mapping(mouse),
+    Mapping::Priority(priority) => format_priority_mapping(priority),
+    Mapping::Conditional(conditional) => format_conditional_mapping(conditional),
+    Mapping::ClearModifiers(clear) => format_clear_modifiers_mapping(clear),
   }
 }
 
@@ -761,13 +1396,19 @@ fn format_single_mapping(mapping: &SingleMapping) -> Value {
   let mut keys = Map::new();
   
   keys.insert("from".to_owned(), format_single_from(&mapping.from));
-  keys.insert("to".to_owned(), format_single_to(&mapping.to));
+  keys.insert("to".to_owned(), format_tap_hold(&mapping.tap, format_single_to(&mapping.to)));
   if let Some(repeat) = format_single_repeat(&mapping.repeat) {
     keys.insert("repeat".to_owned(), repeat);
   }
   if let Some(absorbing) = format_absorbing(&mapping.absorbing) {
     keys.insert("absorbing".to_owned(), absorbing);
   }
+  if mapping.one_shot {
+    keys.insert("one_shot".to_owned(), json!(true));
+  }
+  if let Some(to_if_alone) = &mapping.to_if_alone {
+    keys.insert("to_if_alone".to_owned(), format_single_to(to_if_alone));
+  }
   
   j::Object(keys)
 }
@@ -783,24 +1424,161 @@ fn format_repeat_only_single_mapping(mapping: &RepeatOnlySingleMapping) -> Value
   j::Object(keys)
 }
 
-fn format_alias_mapping(mapping: &AliasMapping) -> Value {
+fn format_latch_mapping(mapping: &LatchMapping) -> Value {
   let mut keys = Map::new();
   
-  keys.insert("from".to_owned(), format_alias_from(&mapping.from));
-  keys.insert("to".to_owned(), format_alias_to(&mapping.to));
+  let action = match mapping.action {
+    LatchAction::Toggle => "toggle",
+    LatchAction::Lock => "lock",
+    LatchAction::Clear => "clear",
+  };
+  
+  keys.insert("from".to_owned(), format_single_from(&mapping.from));
+  keys.insert("to".to_owned(), json!({ action: mapping.alias }));
   
   j::Object(keys)
 }
 
-fn format_row_mapping(mapping: &RowMapping) -> Value {
+fn format_combo_mapping(mapping: &ComboMapping) -> Value {
   let mut keys = Map::new();
   
-  keys.insert("from".to_owned(), format_row_from(&mapping.from));
-  keys.insert("to".to_owned(), format_row_to(&mapping.to));
-  if let Some(repeat) = format_row_repeat(&mapping.repeat) {
-    keys.insert("repeat".to_owned(), repeat);
+  keys.insert("combo".to_owned(), j::Array(mapping.keys.iter().map(format_key_code).collect()));
+  keys.insert("to".to_owned(), format_single_to(&mapping.to));
+  if mapping.window_ms != DEFAULT_COMBO_WINDOW_MS {
+    keys.insert("window_ms".to_owned(), json!(mapping.window_ms));
   }
-  if let Some(absorbing) = format_absorbing(&mapping.absorbing) {
+  
+  j::Object(keys)
+}
+
+fn format_sequence_mapping(mapping: &SequenceMapping) -> Value {
+  let mut keys = Map::new();
+  
+  keys.insert("sequence".to_owned(), j::Array(mapping.keys.iter().map(format_key_code).collect()));
+  keys.insert("to".to_owned(), format_single_to(&mapping.to));
+  if mapping.timeout_ms != DEFAULT_SEQUENCE_TIMEOUT_MS {
+    keys.insert("timeout_ms".to_owned(), json!(mapping.timeout_ms));
+  }
+  
+  j::Object(keys)
+}
+
+fn format_macro_mapping(mapping: &MacroMapping) -> Value {
+  let mut keys = Map::new();
+  
+  let steps: Vec<Value> = mapping.steps.iter().map(|step| match step {
+    MacroStep::Tap(keys) => format_single_to(keys),
+    MacroStep::Delay(delay_ms) => json!({ "delay_ms": delay_ms }),
+    MacroStep::Text(text) => json!({ "text": text }),
+    MacroStep::Unicode { text, method } => {
+      if *method == DEFAULT_UNICODE_METHOD {
+        json!({ "unicode": text })
+      }
+      else {
+        json!({ "unicode": text, "method": unicode_method_name(*method) })
+      }
+    }
+  }).collect();
+  
+  keys.insert("from".to_owned(), format_single_from(&mapping.from));
+  match &mapping.steps[..] {
+    [MacroStep::Text(_)] | [MacroStep::Unicode { .. }] => keys.insert("to".to_owned(), steps[0].clone()),
+    _ => keys.insert("to".to_owned(), json!({ "macro": steps }))
+  };
+  
+  j::Object(keys)
+}
+
+fn format_action_mapping(mapping: &ActionMapping) -> Value {
+  let mut keys = Map::new();
+  
+  let to = match &mapping.action {
+    Action::CapsWord => json!({ "action": "caps_word" }),
+    Action::TogglePause => json!({ "action": "toggle_pause" }),
+    Action::SwitchLayout(layout) => json!({ "action": "switch_layout", "layout": layout }),
+    Action::NextLayout => json!({ "action": "next_layout" }),
+  };
+  
+  keys.insert("from".to_owned(), format_single_from(&mapping.from));
+  keys.insert("to".to_owned(), to);
+  
+  j::Object(keys)
+}
+
+fn format_mouse_mapping(mapping: &MouseMapping) -> Value {
+  let mut keys = Map::new();
+  
+  keys.insert("from".to_owned(), format_single_from(&mapping.from));
+  keys.insert("to".to_owned(), match mapping.mouse {
+    Mouse::Move { x, y } => json!({ "mouse_move": [x, y] }),
+    Mouse::Scroll { x, y } => json!({ "mouse_scroll": [x, y] })
+  });
+  
+  j::Object(keys)
+}
+
+fn format_priority_mapping(mapping: &PriorityMapping) -> Value {
+  match format_mapping(&mapping.mapping) {
+    j::Object(mut keys) => {
+      keys.insert("priority".to_owned(), json!(mapping.priority));
+      j::Object(keys)
+    },
+    other => other
+  }
+}
+
+fn format_conditional_mapping(mapping: &ConditionalMapping) -> Value {
+  match format_mapping(&mapping.mapping) {
+    j::Object(mut keys) => {
+      if !mapping.unless.is_empty() {
+        keys.insert("unless".to_owned(), j::Array(mapping.unless.iter().map(format_modifier).collect()));
+      }
+      if mapping.exclusive {
+        keys.insert("exclusive".to_owned(), json!(true));
+      }
+      if !mapping.if_led.is_empty() {
+        keys.insert("if_led".to_owned(), j::Array(mapping.if_led.iter().map(format_led).collect()));
+      }
+      j::Object(keys)
+    },
+    other => other
+  }
+}
+
+fn format_clear_modifiers_mapping(mapping: &ClearModifiersMapping) -> Value {
+  match format_mapping(&mapping.mapping) {
+    j::Object(mut keys) => {
+      keys.insert("clear_modifiers".to_owned(), json!(true));
+      j::Object(keys)
+    },
+    other => other
+  }
+}
+
+fn format_alias_mapping(mapping: &AliasMapping) -> Value {
+  let mut keys = Map::new();
+  
+  keys.insert("from".to_owned(), format_alias_from(&mapping.from));
+  keys.insert("to".to_owned(), format_tap_hold(&mapping.tap, format_alias_to(&mapping.to)));
+  if mapping.one_shot {
+    keys.insert("one_shot".to_owned(), json!(true));
+  }
+  if let Some(to_if_alone) = &mapping.to_if_alone {
+    keys.insert("to_if_alone".to_owned(), format_single_to(to_if_alone));
+  }
+  
+  j::Object(keys)
+}
+
+fn format_row_mapping(mapping: &RowMapping) -> Value {
+  let mut keys = Map::new();
+  
+  keys.insert("from".to_owned(), format_row_from(&mapping.from));
+  keys.insert("to".to_owned(), format_row_to(&mapping.to));
+  if let Some(repeat) = format_row_repeat(&mapping.repeat) {
+    keys.insert("repeat".to_owned(), repeat);
+  }
+  if let Some(absorbing) = format_absorbing(&mapping.absorbing) {
     keys.insert("absorbing".to_owned(), absorbing);
   }
   
@@ -948,6 +1726,29 @@ fn format_row_to(to: &RowToKeys) -> Value {
   }
 }
 
+fn format_tap_hold(tap: &Option<Tap>, hold: Value) -> Value {
+  match tap {
+    None => hold,
+    Some(tap) => {
+      let mut keys = Map::new();
+      
+      if tap.extra_taps.is_empty() {
+        keys.insert("tap".to_owned(), format_single_to(&tap.keys));
+      }
+      else {
+        let taps = std::iter::once(&tap.keys).chain(tap.extra_taps.iter()).map(format_single_to).collect();
+        keys.insert("taps".to_owned(), j::Array(taps));
+      }
+      keys.insert("hold".to_owned(), hold);
+      if tap.tapping_term_ms != DEFAULT_TAPPING_TERM_MS {
+        keys.insert("tapping_term_ms".to_owned(), json!(tap.tapping_term_ms));
+      }
+      
+      j::Object(keys)
+    }
+  }
+}
+
 fn format_letters(s: &str) -> Value {
   let mut keys = Map::new();
   
@@ -960,7 +1761,8 @@ fn format_single_repeat(repeat: &SingleRepeat) -> Option<Value> {
   match repeat {
     SingleRepeat::Normal => None,
     SingleRepeat::Disabled => Some(j::String("Disabled".to_owned())),
-    SingleRepeat::Special { keys, delay_ms, interval_ms } => Some(format_single_repeat_special(keys, *delay_ms, *interval_ms))
+    SingleRepeat::Special { keys, delay_ms, interval_ms } => Some(format_single_repeat_special(keys, *delay_ms, *interval_ms)),
+    SingleRepeat::AutoShift { delay_ms } => Some(format_auto_shift(*delay_ms))
   }
 }
 
@@ -968,10 +1770,15 @@ fn format_row_repeat(repeat: &RowRepeat) -> Option<Value> {
   match repeat {
     RowRepeat::Normal => None,
     RowRepeat::Disabled => Some(j::String("Disabled".to_owned())),
-    RowRepeat::Special { keys, delay_ms, interval_ms } => Some(format_row_repeat_special(keys, *delay_ms, *interval_ms))
+    RowRepeat::Special { keys, delay_ms, interval_ms } => Some(format_row_repeat_special(keys, *delay_ms, *interval_ms)),
+    RowRepeat::AutoShift { delay_ms } => Some(format_auto_shift(*delay_ms))
   }
 }
 
+fn format_auto_shift(delay_ms: i32) -> Value {
+  json!({ "AutoShift": { "delay_ms": delay_ms } })
+}
+
 fn format_single_repeat_special(keys: &SingleToKeys, delay_ms: i32, interval_ms: i32) -> Value {
   let mut elems1 = Map::new();
   let mut elems2 = Map::new();
@@ -1021,7 +1828,7 @@ fn format_absorbing(absorbing: &Vec<Modifier>) -> Option<Value> {
 #[cfg(test)]
 mod tests {
   use std::str::FromStr;
-  use crate::fancy_keys::{Layout, Mapping, SingleMapping, RowMapping, SingleFromKeys, RowFromKeys, Modifier, SingleToKeys, RowToKeys, SingleTerminalToKey, SingleRepeat, RowRepeat, AliasMapping, AliasFromKeys, AliasToKeys};
+  use crate::fancy_keys::{Layout, Mapping, SingleMapping, RowMapping, SingleFromKeys, RowFromKeys, Modifier, SingleToKeys, RowToKeys, SingleTerminalToKey, SingleRepeat, RowRepeat, AliasMapping, AliasFromKeys, AliasToKeys, Tap, LatchMapping, LatchAction, ComboMapping, SequenceMapping, MacroMapping, MacroStep, UnicodeMethod, ActionMapping, Action, RepeatOnlySingleMapping, MouseMapping, Mouse, Debounce, PriorityMapping, ConditionalMapping, ClearModifiersMapping, Led, NamedLayout};
   use super::{parse_layout_from_json, format_layout_as_json};
   use crate::key_codes::KeyCode::*;
 
@@ -1037,9 +1844,10 @@ mod tests {
     let parsed = parse_layout_from_json(&json).unwrap();
     assert_eq!(parsed, Layout {
       mappings: vec![
-        Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![CAPSLOCK] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() } }),
-        Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![RIGHTALT] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() } }),
-      ]
+        Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![CAPSLOCK] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() }, tap: None, one_shot: false, to_if_alone: None }),
+        Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![RIGHTALT] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() }, tap: None, one_shot: false, to_if_alone: None }),
+      ],
+      ..Default::default()
     });
   }
 
@@ -1059,13 +1867,14 @@ mod tests {
     use crate::fancy_keys::Row::*;
     assert_eq!(parsed, Layout {
       mappings: vec![
-        Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![CAPSLOCK] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() } }),
-        Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![RIGHTALT] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() } }),
+        Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![CAPSLOCK] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() }, tap: None, one_shot: false, to_if_alone: None }),
+        Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![RIGHTALT] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() }, tap: None, one_shot: false, to_if_alone: None }),
       
         Mapping::Row(RowMapping { from: RowFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], row: USQuertyQ }, to: RowToKeys { initial: vec![], terminal: " {}% \\*][|".to_owned() }, repeat: RowRepeat::Normal, absorbing: vec![] }),
         Mapping::Row(RowMapping { from: RowFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], row: USQuertyA }, to: RowToKeys { initial: vec![], terminal: "   = &)(/_$".to_owned() }, repeat: RowRepeat::Normal, absorbing: vec![] }),
         Mapping::Row(RowMapping { from: RowFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], row: USQuertyZ }, to: RowToKeys { initial: vec![], terminal: "\"    !+#".to_owned() }, repeat: RowRepeat::Normal, absorbing: vec![] }),
-      ]
+      ],
+      ..Default::default()
     });
   }
 
@@ -1080,8 +1889,9 @@ mod tests {
     let parsed = parse_layout_from_json(&json).unwrap();
     assert_eq!(parsed, Layout {
       mappings: vec![
-        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![], key: COMMA }, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(W) }, repeat: SingleRepeat::Special { keys: SingleToKeys { initial: vec![Modifier::Key(LEFTCTRL)], terminal: SingleTerminalToKey::Physical(F24) }, delay_ms: 180, interval_ms: 30 }, absorbing: vec![] })
-      ]
+        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![], key: COMMA }, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(W) }, repeat: SingleRepeat::Special { keys: SingleToKeys { initial: vec![Modifier::Key(LEFTCTRL)], terminal: SingleTerminalToKey::Physical(F24) }, delay_ms: 180, interval_ms: 30 }, absorbing: vec![], tap: None, one_shot: false, to_if_alone: None })
+      ],
+      ..Default::default()
     });
   }
 
@@ -1140,8 +1950,9 @@ mod tests {
     let parsed = parse_layout_from_json(&json).unwrap();
     assert_eq!(parsed, Layout {
       mappings: vec![
-        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![], key: K4}, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(K3) }, repeat: SingleRepeat::Normal, absorbing: vec![] })
-      ]
+        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![], key: K4}, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(K3) }, repeat: SingleRepeat::Normal, absorbing: vec![], tap: None, one_shot: false, to_if_alone: None })
+      ],
+      ..Default::default()
     });
   }
 
@@ -1164,5 +1975,565 @@ mod tests {
     }
     assert_eq!(restringed1, restringed2);
   }
-}
 
+  #[test]
+  fn test_tap_hold_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": "CAPSLOCK", "to": { "tap": "ESC", "hold": "@movement" } },
+    { "from": "A", "to": { "tap": "A", "hold": "LEFTCTRL", "tapping_term_ms": 150 } }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed, Layout {
+      mappings: vec![
+        Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![CAPSLOCK] }, to: AliasToKeys { initial: vec![], terminal: "@movement".to_owned() }, tap: Some(Tap { keys: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(ESC) }, tapping_term_ms: 200, extra_taps: vec![] }), one_shot: false, to_if_alone: None }),
+        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![], key: A }, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(LEFTCTRL) }, repeat: SingleRepeat::Normal, absorbing: vec![], tap: Some(Tap { keys: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(A) }, tapping_term_ms: 150, extra_taps: vec![] }), one_shot: false, to_if_alone: None }),
+      ],
+      ..Default::default()
+    });
+    
+    for bad in ["-1", "2147483648"] {
+      let text = format!(r#"{{ "mappings": [ {{ "from": "A", "to": {{ "tap": "A", "hold": "LEFTCTRL", "tapping_term_ms": {} }} }} ] }}"#, bad);
+      let json = serde_json::Value::from_str(&text).unwrap();
+      assert!(parse_layout_from_json(&json).is_err());
+    }
+  }
+
+  #[test]
+  fn test_tap_hold_formatting() {
+    let text = r#"{
+  "mappings": [
+    { "from": "CAPSLOCK", "to": { "tap": "ESC", "hold": "@movement" } },
+    { "from": "A", "to": { "tap": "A", "hold": "LEFTCTRL", "tapping_term_ms": 150 } }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let restringed1 = json.to_string();
+    let layout = parse_layout_from_json(&json).unwrap();
+    let formatted = format_layout_as_json(&layout);
+    let restringed2 = formatted.to_string();
+
+    if restringed1 != restringed2 {
+      println!("{}", restringed1);
+      println!("{}", restringed2);
+    }
+    assert_eq!(restringed1, restringed2);
+  }
+
+  #[test]
+  fn test_one_shot_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": "CAPSLOCK", "to": "@symbol", "one_shot": true },
+    { "from": "RIGHTALT", "to": "LEFTSHIFT", "one_shot": true }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed, Layout {
+      mappings: vec![
+        Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![CAPSLOCK] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() }, tap: None, one_shot: true, to_if_alone: None }),
+        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![], key: RIGHTALT }, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(LEFTSHIFT) }, repeat: SingleRepeat::Normal, absorbing: vec![], tap: None, one_shot: true, to_if_alone: None }),
+      ],
+      ..Default::default()
+    });
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    let text = r#"{
+  "mappings": [
+    { "from": ["LEFTCTRL", "CAPSLOCK"], "to": "LEFTSHIFT", "one_shot": true }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+
+  #[test]
+  fn test_latch_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": "CAPSLOCK", "to": "@numpad" },
+    { "from": "SCROLLLOCK", "to": { "toggle": "@numpad" } },
+    { "from": ["@numpad", "ESC"], "to": { "clear": "@numpad" } }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[1], Mapping::Latch(LatchMapping { from: SingleFromKeys { modifiers: vec![], key: SCROLLLOCK }, action: LatchAction::Toggle, alias: "@numpad".to_owned() }));
+    assert_eq!(parsed.mappings[2], Mapping::Latch(LatchMapping { from: SingleFromKeys { modifiers: vec![Modifier::Alias("@numpad".to_owned())], key: ESC }, action: LatchAction::Clear, alias: "@numpad".to_owned() }));
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    let text = r#"{
+  "mappings": [
+    { "from": "SCROLLLOCK", "to": { "lock": "@numpad" } }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+
+  #[test]
+  fn test_combo_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "combo": ["J", "K"], "to": "ESC" },
+    { "combo": ["D", "F"], "to": ["LEFTCTRL", "C"], "window_ms": 50 }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed, Layout {
+      mappings: vec![
+        Mapping::Combo(ComboMapping { keys: vec![J, K], to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(ESC) }, window_ms: 30 }),
+        Mapping::Combo(ComboMapping { keys: vec![D, F], to: SingleToKeys { initial: vec![Modifier::Key(LEFTCTRL)], terminal: SingleTerminalToKey::Physical(C) }, window_ms: 50 }),
+      ],
+      ..Default::default()
+    });
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    for bad in [r#"{ "combo": ["J"], "to": "ESC" }"#, r#"{ "combo": ["J", "J"], "to": "ESC" }"#, r#"{ "combo": ["J", "K"], "from": "L", "to": "ESC" }"#, r#"{ "combo": ["J", "K"], "to": "ESC", "window_ms": -1 }"#, r#"{ "combo": ["J", "K"], "to": "ESC", "window_ms": 2147483648 }"#] {
+      let text = format!(r#"{{ "mappings": [ {} ] }}"#, bad);
+      let json = serde_json::Value::from_str(&text).unwrap();
+      assert!(parse_layout_from_json(&json).is_err());
+    }
+  }
+
+  #[test]
+  fn test_sequence_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "sequence": ["RIGHTALT", "G", "G"], "to": ["LEFTCTRL", "HOME"] },
+    { "sequence": ["RIGHTALT", "E"], "to": "END", "timeout_ms": 500 }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed, Layout {
+      mappings: vec![
+        Mapping::Sequence(SequenceMapping { keys: vec![RIGHTALT, G, G], to: SingleToKeys { initial: vec![Modifier::Key(LEFTCTRL)], terminal: SingleTerminalToKey::Physical(HOME) }, timeout_ms: 1000 }),
+        Mapping::Sequence(SequenceMapping { keys: vec![RIGHTALT, E], to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(END) }, timeout_ms: 500 }),
+      ],
+      ..Default::default()
+    });
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    for bad in [r#"{ "sequence": ["RIGHTALT"], "to": "END" }"#, r#"{ "sequence": ["RIGHTALT", "E"], "to": "END", "timeout_ms": -1 }"#, r#"{ "sequence": ["RIGHTALT", "E"], "to": "END", "timeout_ms": 2147483648 }"#] {
+      let text = format!(r#"{{ "mappings": [ {} ] }}"#, bad);
+      let json = serde_json::Value::from_str(&text).unwrap();
+      assert!(parse_layout_from_json(&json).is_err());
+    }
+  }
+
+  #[test]
+  fn test_macro_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": ["CAPSLOCK", "D"], "to": { "macro": [["LEFTCTRL", "A"], { "delay_ms": 20 }, "DELETE"] } }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed, Layout {
+      mappings: vec![
+        Mapping::Macro(MacroMapping { from: SingleFromKeys { modifiers: vec![Modifier::Key(CAPSLOCK)], key: D }, steps: vec![
+          MacroStep::Tap(SingleToKeys { initial: vec![Modifier::Key(LEFTCTRL)], terminal: SingleTerminalToKey::Physical(A) }),
+          MacroStep::Delay(20),
+          MacroStep::Tap(SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(DELETE) }),
+        ] }),
+      ],
+      ..Default::default()
+    });
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    for bad in [r#"{ "from": "D", "to": { "macro": [] } }"#, r#"{ "from": "D", "to": { "macro": [{ "delay": 20 }] } }"#, r#"{ "from": "D", "to": { "macro": ["A"] }, "repeat": "Disabled" }"#, r#"{ "from": "D", "to": { "macro": [{ "delay_ms": -1 }] } }"#, r#"{ "from": "D", "to": { "macro": [{ "delay_ms": 2147483648 }] } }"#] {
+      let text = format!(r#"{{ "mappings": [ {} ] }}"#, bad);
+      let json = serde_json::Value::from_str(&text).unwrap();
+      assert!(parse_layout_from_json(&json).is_err());
+    }
+  }
+
+  #[test]
+  fn test_text_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": ["CAPSLOCK", "E"], "to": { "text": "user@example.com" } },
+    { "from": ["CAPSLOCK", "R"], "to": { "macro": [{ "text": "Regards," }, "ENTER"] } }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[0], Mapping::Macro(MacroMapping {
+      from: SingleFromKeys { modifiers: vec![Modifier::Key(CAPSLOCK)], key: E },
+      steps: vec![MacroStep::Text("user@example.com".to_owned())]
+    }));
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+  }
+
+  #[test]
+  fn test_unicode_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": ["CAPSLOCK", "K"], "to": { "unicode": "→" } },
+    { "from": ["CAPSLOCK", "L"], "to": { "macro": [{ "unicode": "§", "method": "ctrl_shift_u" }, "SPACE"] } }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[0], Mapping::Macro(MacroMapping {
+      from: SingleFromKeys { modifiers: vec![Modifier::Key(CAPSLOCK)], key: K },
+      steps: vec![MacroStep::Unicode { text: "→".to_owned(), method: UnicodeMethod::CtrlShiftU }]
+    }));
+    
+    let formatted = format_layout_as_json(&parsed);
+    assert_eq!(formatted["mappings"][0], json["mappings"][0]);
+    assert_eq!(formatted["mappings"][1]["to"]["macro"][0], serde_json::json!({ "unicode": "§" }));
+    
+    let text = r#"{ "mappings": [ { "from": "K", "to": { "unicode": "→", "method": "morse" } } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+
+  #[test]
+  fn test_action_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": ["RIGHTALT", "C"], "to": { "action": "caps_word" } },
+    { "from": ["RIGHTCTRL", "PAUSE"], "to": { "action": "toggle_pause" } }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[0], Mapping::Action(ActionMapping { from: SingleFromKeys { modifiers: vec![Modifier::Key(RIGHTALT)], key: C }, action: Action::CapsWord }));
+    assert_eq!(parsed.mappings[1], Mapping::Action(ActionMapping { from: SingleFromKeys { modifiers: vec![Modifier::Key(RIGHTCTRL)], key: PAUSE }, action: Action::TogglePause }));
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    let text = r#"{ "mappings": [ { "from": "C", "to": { "action": "shout" } } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+
+  #[test]
+  fn test_auto_shift_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": "A", "repeat": { "AutoShift": { "delay_ms": 150 } } },
+    { "from": {"row": "Q"}, "to": {"letters": "qwerty"}, "repeat": { "AutoShift": { "delay_ms": 180 } } }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[0], Mapping::RepeatOnlySingle(RepeatOnlySingleMapping { from: SingleFromKeys { modifiers: vec![], key: A }, repeat: SingleRepeat::AutoShift { delay_ms: 150 } }));
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    for bad in [r#"{ "AutoShift": { "delay_ms": 150, "keys": "B" } }"#, r#"{ "AutoShift": { "delay_ms": -1 } }"#, r#"{ "AutoShift": { "delay_ms": 2147483648 } }"#] {
+      let text = format!(r#"{{ "mappings": [ {{ "from": "A", "repeat": {} }} ] }}"#, bad);
+      let json = serde_json::Value::from_str(&text).unwrap();
+      assert!(parse_layout_from_json(&json).is_err(), "{}", bad);
+    }
+  }
+
+  #[test]
+  fn test_mouse_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": ["CAPSLOCK", "L"], "to": { "mouse_move": [10, 0] } },
+    { "from": ["CAPSLOCK", "N"], "to": { "mouse_scroll": [0, -1] } },
+    { "from": ["CAPSLOCK", "SPACE"], "to": "BTN_LEFT" }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[0], Mapping::Mouse(MouseMapping { from: SingleFromKeys { modifiers: vec![Modifier::Key(CAPSLOCK)], key: L }, mouse: Mouse::Move { x: 10, y: 0 } }));
+    assert_eq!(parsed.mappings[1], Mapping::Mouse(MouseMapping { from: SingleFromKeys { modifiers: vec![Modifier::Key(CAPSLOCK)], key: N }, mouse: Mouse::Scroll { x: 0, y: -1 } }));
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    for bad in [r#"{ "mouse_move": [10] }"#, r#"{ "mouse_move": [10001, 0] }"#, r#"{ "mouse_scroll": [0, -9223372036854775808] }"#] {
+      let text = format!(r#"{{ "mappings": [ {{ "from": "L", "to": {} }} ] }}"#, bad);
+      let json = serde_json::Value::from_str(&text).unwrap();
+      assert!(parse_layout_from_json(&json).is_err(), "{}", bad);
+    }
+  }
+
+  #[test]
+  fn test_debounce_parsing() {
+    let text = r#"{
+  "mappings": [],
+  "debounce": [
+    { "device": "Old Cherry*", "delay_ms": 30, "keys": { "E": 60, "SPACE": 0 } },
+    { "delay_ms": 10 }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed, Layout {
+      mappings: vec![],
+      debounce: vec![
+        Debounce { device: Some("Old Cherry*".to_owned()), delay_ms: 30, keys: vec![(E, 60), (SPACE, 0)].into_iter().collect() },
+        Debounce { device: None, delay_ms: 10, keys: Default::default() },
+      ],
+      ..Default::default()
+    });
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    let text = r#"{ "mappings": [], "debounce": [ { "device": "*" } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+    
+    let text = r#"{ "mappings": [], "debounce": [ { "delay_ms": -5 } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+    
+    let text = r#"{ "mappings": [], "debounce": [ { "delay_ms": 2147483648 } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+  
+  #[test]
+  fn test_emergency_chord_parsing() {
+    let text = r#"{"emergency_chord":["LEFTCTRL","RIGHTCTRL","ESC"],"mappings":[]}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(Some(vec![LEFTCTRL, RIGHTCTRL, ESC]), parsed.emergency_chord);
+    assert_eq!(vec![LEFTCTRL, RIGHTCTRL, ESC], crate::fancy_layout_interpreting::convert(&parsed).unwrap().emergency_chord);
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    // Without one, the default applies
+    let json = serde_json::Value::from_str(r#"{ "mappings": [] }"#).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(crate::keys::default_emergency_chord(), crate::fancy_layout_interpreting::convert(&parsed).unwrap().emergency_chord);
+    
+    for bad in [r#"[]"#, r#"["ESC"]"#, r#""ESC""#, r#"["@symbol", "ESC"]"#] {
+      let text = format!(r#"{{ "mappings": [], "emergency_chord": {} }}"#, bad);
+      let json = serde_json::Value::from_str(&text).unwrap();
+      assert!(parse_layout_from_json(&json).is_err(), "{}", bad);
+    }
+  }
+
+  #[test]
+  fn test_priority_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": ["LEFTSHIFT", "SPACE"], "to": "BACKSPACE", "priority": 2 }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings, vec![
+      Mapping::Priority(PriorityMapping { priority: 2, mapping: Box::new(Mapping::Single(SingleMapping {
+        from: SingleFromKeys { modifiers: vec![Modifier::Key(LEFTSHIFT)], key: SPACE },
+        to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(BACKSPACE) },
+        repeat: SingleRepeat::Normal, absorbing: vec![], tap: None, one_shot: false, to_if_alone: None
+      })) }),
+    ]);
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    let text = r#"{ "mappings": [ { "from": "CAPSLOCK", "to": "@nav", "priority": 1 } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+    
+    let text = r#"{ "mappings": [ { "from": "A", "to": "B", "priority": "high" } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+
+  #[test]
+  fn test_conditional_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": "LEFTCTRL", "to": "@ctrl" },
+    { "from": ["LEFTSHIFT", "SPACE"], "to": "BACKSPACE", "unless": ["@ctrl"], "exclusive": true, "priority": 1 }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[1],
+      Mapping::Priority(PriorityMapping { priority: 1, mapping: Box::new(Mapping::Conditional(ConditionalMapping {
+        unless: vec![Modifier::Alias("@ctrl".to_owned())],
+        exclusive: true,
+        if_led: vec![],
+        mapping: Box::new(Mapping::Single(SingleMapping {
+          from: SingleFromKeys { modifiers: vec![Modifier::Key(LEFTSHIFT)], key: SPACE },
+          to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(BACKSPACE) },
+          repeat: SingleRepeat::Normal, absorbing: vec![], tap: None, one_shot: false, to_if_alone: None
+        }))
+      })) })
+    );
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    let text = r#"{ "mappings": [ { "from": "A", "to": "B", "unless": ["@ctrl"] } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+
+  #[test]
+  fn test_if_led_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": "J", "to": "KP1", "if_led": ["NUMLOCK"] }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[0],
+      Mapping::Conditional(ConditionalMapping {
+        unless: vec![],
+        exclusive: false,
+        if_led: vec![Led::Num],
+        mapping: Box::new(Mapping::Single(SingleMapping {
+          from: SingleFromKeys { modifiers: vec![], key: J },
+          to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(KP1) },
+          repeat: SingleRepeat::Normal, absorbing: vec![], tap: None, one_shot: false, to_if_alone: None
+        }))
+      })
+    );
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    let text = r#"{ "mappings": [ { "from": "J", "to": "KP1", "if_led": ["KANA"] } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+
+  #[test]
+  fn test_clear_modifiers_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": ["RIGHTALT", "K"], "to": ["LEFTSHIFT", "9"], "clear_modifiers": true, "priority": 1 }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[0],
+      Mapping::Priority(PriorityMapping { priority: 1, mapping: Box::new(Mapping::ClearModifiers(ClearModifiersMapping {
+        mapping: Box::new(Mapping::Single(SingleMapping {
+          from: SingleFromKeys { modifiers: vec![Modifier::Key(RIGHTALT)], key: K },
+          to: SingleToKeys { initial: vec![Modifier::Key(LEFTSHIFT)], terminal: SingleTerminalToKey::Physical(K9) },
+          repeat: SingleRepeat::Normal, absorbing: vec![], tap: None, one_shot: false, to_if_alone: None
+        }))
+      })) })
+    );
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    let text = r#"{ "mappings": [ { "from": "CAPSLOCK", "to": "@symbol", "clear_modifiers": true } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+
+  #[test]
+  fn test_tap_dance_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": "APOSTROPHE", "to": { "taps": ["APOSTROPHE", ["LEFTSHIFT", "APOSTROPHE"]], "hold": "@symbols", "tapping_term_ms": 250 } },
+    { "from": ["@symbols", "J"], "to": "LEFT" }
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[0], Mapping::Alias(AliasMapping {
+      from: AliasFromKeys { keys: vec![APOSTROPHE] },
+      to: AliasToKeys { initial: vec![], terminal: "@symbols".to_owned() },
+      tap: Some(Tap {
+        keys: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(APOSTROPHE) },
+        tapping_term_ms: 250,
+        extra_taps: vec![SingleToKeys { initial: vec![Modifier::Key(LEFTSHIFT)], terminal: SingleTerminalToKey::Physical(APOSTROPHE) }]
+      }),
+      one_shot: false,
+      to_if_alone: None
+    }));
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    let text = r#"{ "mappings": [ { "from": "APOSTROPHE", "to": { "taps": ["APOSTROPHE"], "hold": "LEFTCTRL" } } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+
+  #[test]
+  fn test_named_layouts_parsing() {
+    let text = r#"{
+  "mappings": [
+    { "from": "CAPSLOCK", "to": "@symbol" },
+    { "from": ["@symbol", "F1"], "to": { "action": "switch_layout", "layout": "dvorak" } },
+    { "from": ["@symbol", "F2"], "to": { "action": "next_layout" } }
+  ],
+  "layouts": [
+    { "name": "qwerty", "mappings": [] },
+    { "name": "dvorak", "mappings": [ { "from": ["@symbol", "Q"], "to": "APOSTROPHE" } ] }
+  ],
+  "switch_all_keyboards": true
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed.mappings[1], Mapping::Action(ActionMapping { from: SingleFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], key: F1 }, action: Action::SwitchLayout("dvorak".to_owned()) }));
+    assert_eq!(parsed.mappings[2], Mapping::Action(ActionMapping { from: SingleFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], key: F2 }, action: Action::NextLayout }));
+    assert_eq!(parsed.named_layouts.len(), 2);
+    assert_eq!(parsed.named_layouts[0], NamedLayout { name: "qwerty".to_owned(), mappings: vec![] });
+    assert_eq!(parsed.named_layouts[1].name, "dvorak");
+    assert!(parsed.switch_all_keyboards);
+    
+    let restringed1 = json.to_string();
+    let restringed2 = format_layout_as_json(&parsed).to_string();
+    assert_eq!(restringed1, restringed2);
+    
+    // Aliases must be defined in the shared mappings or the same layout
+    let text = r#"{ "layouts": [ { "name": "a", "mappings": [ { "from": "CAPSLOCK", "to": "@symbol" } ] }, { "name": "b", "mappings": [ { "from": ["@symbol", "Q"], "to": "A" } ] } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+    
+    let text = r#"{ "layouts": [ { "name": "a", "mappings": [] }, { "name": "a", "mappings": [] } ] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+    
+    let text = r#"{ "layouts": [] }"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    assert!(parse_layout_from_json(&json).is_err());
+  }
+}
@@ -26,11 +26,19 @@ mod monitor_tablet_mode;
 mod example_hardware;
 mod layout_parsing_formatting;
 mod char_production_map;
+mod unicode_input;
+mod debounce;
+mod signals;
+mod control;
+mod keyboard_merging;
 mod physical_keyboard_layouts;
 mod complete_tests;
 
-use clap::{Arg, App};
-use keys::Layout;
+use clap::{Arg, App, ArgMatches};
+use keys::{Layout, Debounce};
+use remapping_loop::LayoutReloader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 fn main() {
   let mut app =
@@ -71,6 +79,11 @@ fn main() {
           .help_heading(Some("DEVICE SELECTION"))
           .help("Automatically remap keyboards as they are plugged in. Useful on systems that don't use systemd.")
         )
+        .arg(Arg::new("merge")
+          .long("merge")
+          .help_heading(Some("DEVICE SELECTION"))
+          .help("Send the keys from all remapped keyboards out through a single virtual keyboard, instead of one per keyboard. Modifiers held on one keyboard then apply to keys on another. With --auto-all-keyboards, keyboards plugged in later join the same virtual keyboard.")
+        )
         .arg(Arg::new("default_layout")
           .long("default-layout")
           .takes_value(true)
@@ -105,12 +118,62 @@ fn main() {
           .help_heading(Some("TABLET MODE"))
           .help("Do not emit key events when the selected device indicates the computer is in tablet mode.")
         )
+        .arg(Arg::new("debounce_ms")
+          .long("debounce-ms")
+          .takes_value(true)
+          .value_name("MS")
+          .help_heading(Some("DEBOUNCE"))
+          .help("Ignore a key press that comes less than MS milliseconds after the same key was released, for keyboards whose keys double-fire. Applies to every keyboard being remapped and takes the place of any \"debounce\" rules in the layout.")
+        )
+        .arg(Arg::new("emergency_chord")
+          .long("emergency-chord")
+          .takes_value(true)
+          .value_name("KEYS")
+          .help_heading(Some("PROCESS"))
+          .help("Comma-separated keys that, held together on any remapped keyboard, release the keyboard and make totalmapper exit, in case a layout leaves it unusable. The default is BACKSPACE,ESC,ENTER, or the layout's \"emergency_chord\". The keys are read before any remapping.")
+        )
+        .arg(Arg::new("control_socket")
+          .long("control-socket")
+          .takes_value(true)
+          .value_name("PATH")
+          .help_heading(Some("PROCESS"))
+          .help("Listen on a Unix domain socket at PATH for commands from `totalmapper ctl`.")
+        )
         .arg(Arg::new("verbose")
           .long("verbose")
           .help_heading(Some("DEBUGGING"))
           .help("Print verbose info.")
         )
       )
+      .subcommand(App::new("ctl")
+        .about("Send a command to a running `totalmapper remap` started with --control-socket. COMMAND is one of: status, pause, resume, reload, layout. `layout` switches to the layout given with --default-layout or --layout-file.")
+        .arg(Arg::new("COMMAND")
+          .required(true)
+          .index(1)
+          .help("status, pause, resume, reload or layout")
+        )
+        .arg(Arg::new("socket")
+          .long("socket")
+          .takes_value(true)
+          .value_name("PATH")
+          .required(true)
+          .help("The path given to `totalmapper remap --control-socket`.")
+        )
+        .arg(Arg::new("default_layout")
+          .long("default-layout")
+          .takes_value(true)
+          .value_name("NAME")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("With `layout`, switch to the builtin layout named NAME.")
+        )
+        .arg(Arg::new("layout_file")
+          .long("layout-file")
+          .takes_value(true)
+          .value_name("FILE")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("With `layout`, switch to the layout in json file FILE.")
+        )
+      )
       .subcommand(App::new("list_keyboards")
         .about("List keyboard devices under /dev/input")
         .arg(Arg::new("verbose")
@@ -192,7 +255,22 @@ fn main() {
   let m = app.clone().get_matches();
   
   if let Some(m) = m.subcommand_matches("remap") {
-    let layout = load_layout(&m.value_of("default_layout"), &m.value_of("layout_file"));
+    if let Err(msg) = signals::start(m.occurrences_of("verbose") > 0) {
+      println!("Error: {}", msg);
+      std::process::exit(1);
+    }
+    
+    let layout = load_layout(&m.value_of("default_layout"), &m.value_of("layout_file"))
+      .and_then(|layout| apply_debounce_option(layout, &m.value_of("debounce_ms")))
+      .and_then(|layout| apply_emergency_chord_option(layout, &m.value_of("emergency_chord")));
+    let reloader = make_layout_reloader(m);
+    if let (Ok(_), Some(path)) = (&layout, m.value_of("control_socket")) {
+      control::set_active_layout(&describe_layout(&m.value_of("default_layout"), &m.value_of("layout_file")));
+      if let Err(msg) = control::serve(Path::new(path), make_layout_loader(m), m.occurrences_of("verbose") > 0) {
+        println!("Error: {}", msg);
+        std::process::exit(1);
+      }
+    }
     match layout {
       Err(msg) => {
         println!("{}", msg);
@@ -217,7 +295,7 @@ fn main() {
               None => vec![],
               Some(excludes) => excludes.collect()
             };
-            match remapping_loop::do_remapping_loop_all_devices(&layout, &excludes, m.occurrences_of("verbose") > 0) {
+            match remapping_loop::do_remapping_loop_all_devices(&layout, &reloader, &excludes, m.occurrences_of("merge") > 0, m.occurrences_of("verbose") > 0) {
               Ok(_) => (),
               Err(err) => {
                 println!("Error: {}", err);
@@ -231,13 +309,18 @@ fn main() {
               None => vec![],
               Some(excludes) => excludes.collect()
             };
-            match remapping_loop::do_remapping_loop_multiple_devices(
+            let devices = remapping_loop::filter_devices_verbose(
                 &devs2,
                 m.occurrences_of("only_if_keyboard") > 0,
                 &excludes,
+                m.occurrences_of("verbose") > 0);
+            match devices.and_then(|devices| remapping_loop::do_remapping_loop_multiple_devices(
+                &devices,
                 &layout,
+                &reloader,
                 &m.value_of("tablet_mode_switch_device"),
-                m.occurrences_of("verbose") > 0)
+                m.occurrences_of("merge") > 0,
+                m.occurrences_of("verbose") > 0))
             {
               Ok(_) => (),
               Err(err) => {
@@ -251,7 +334,7 @@ fn main() {
               None => vec![],
               Some(excludes) => excludes.collect()
             };
-            match remapping_loop::do_remapping_loop_auto_all_devices(&layout, &excludes, m.occurrences_of("verbose") > 0) {
+            match remapping_loop::do_remapping_loop_auto_all_devices(&layout, &reloader, &excludes, m.occurrences_of("merge") > 0, m.occurrences_of("verbose") > 0) {
               Ok(_) => (),
               Err(err) => {
                 println!("Error: {}", err);
@@ -262,6 +345,29 @@ fn main() {
         }
       }
     }
+    control::remove_socket();
+  }
+  else if let Some(m) = m.subcommand_matches("ctl") {
+    match ctl_command_line(m) {
+      Err(msg) => {
+        println!("{}", msg);
+        std::process::exit(1);
+      },
+      Ok(line) => {
+        match control::send_command(Path::new(m.value_of("socket").unwrap()), &line) {
+          Err(msg) => {
+            println!("Error: {}", msg);
+            std::process::exit(1);
+          },
+          Ok(reply) => {
+            print!("{}", reply);
+            if reply.starts_with("Error") {
+              std::process::exit(1);
+            }
+          }
+        }
+      }
+    }
   }
   else if let Some(m) = m.subcommand_matches("list_keyboards") {
     keyboard_listing::list_keyboards_to_stdout(m.occurrences_of("verbose") > 0).unwrap();
@@ -314,18 +420,18 @@ fn main() {
     }
   }
   else if let Some(m) = m.subcommand_matches("add_systemd_service") {
-    match load_layout(&m.value_of("default_layout"), &m.value_of("layout_file")) {
+    match load_layout_and_json(&m.value_of("default_layout"), &m.value_of("layout_file")) {
       Err(s) => {
         println!("{}", s);
         std::process::exit(1);
       },
-      Ok(layout) => {
+      Ok((layout_json, _)) => {
         let excludes: Vec<&str> = match m.values_of("exclude") {
           None => vec![],
           Some(excludes) => excludes.collect()
         };
         
-        match udev_utils::add_systemd_service(&layout, excludes.into_iter()) {
+        match udev_utils::add_systemd_service(&layout_json, excludes.into_iter()) {
           Err(msg) => {
             println!("{}", msg);
             std::process::exit(1);
@@ -351,6 +457,11 @@ fn main() {
 }
 
 fn load_layout(default_layout: &Option<&str>, layout_file: &Option<&str>) -> Result<Layout, String> {
+  load_layout_and_json(default_layout, layout_file).map(|(_, layout)| layout)
+}
+
+// The JSON too, for `add_systemd_service` to copy as it is
+fn load_layout_and_json(default_layout: &Option<&str>, layout_file: &Option<&str>) -> Result<(String, Layout), String> {
   match (default_layout, layout_file) {
     (None, None) => {
       Err("Error: no layout specified. Use --default-layout or --layout-file.".to_string())
@@ -361,21 +472,118 @@ fn load_layout(default_layout: &Option<&str>, layout_file: &Option<&str>) -> Res
     (Some(name), None) => {
       match (*default_fancy_layouts::DEFAULT_LAYOUTS).get(&name.to_string()) {
         None => Err(format!("Error: no builtin layout named {}", name)),
-        Some(layout) => Ok(
+        Some(layout) => Ok((
+          layout.to_string(),
           fancy_layout_interpreting::convert(
             &layout_parsing_formatting::parse_layout_from_json(
               &serde_json::from_str(layout).unwrap()
             ).unwrap()
           ).unwrap()
-        )
+        ))
       }
     },
     (None, Some(path)) => {
-      match layout_loading::load_layout_from_file(path) {
-        Err(err) => Err(err),
-        Ok(layout) => Ok(layout)
+      layout_loading::load_layout_and_json_from_file(path)
+    }
+  }
+}
+
+// With --layout-file, the file is loaded again whenever it changes
+fn make_layout_reloader(m: &ArgMatches) -> Option<LayoutReloader> {
+  Some(layout_reloader_for(m.value_of("layout_file")?, &m.value_of("debounce_ms"), &m.value_of("emergency_chord")))
+}
+
+fn layout_reloader_for(path: &str, debounce_ms: &Option<&str>, emergency_chord: &Option<&str>) -> LayoutReloader {
+  let path = path.to_owned();
+  let debounce_ms = debounce_ms.map(|s| s.to_owned());
+  let emergency_chord = emergency_chord.map(|s| s.to_owned());
+  
+  LayoutReloader {
+    path: PathBuf::from(&path),
+    load: Arc::new(move || {
+      layout_loading::load_layout_from_file(&path)
+        .and_then(|layout| apply_debounce_option(layout, &debounce_ms.as_deref()))
+        .and_then(|layout| apply_emergency_chord_option(layout, &emergency_chord.as_deref()))
+    })
+  }
+}
+
+// For `totalmapper ctl layout`, with the same options as at startup
+fn make_layout_loader(m: &ArgMatches) -> control::LayoutLoader {
+  let debounce_ms = m.value_of("debounce_ms").map(|s| s.to_owned());
+  let emergency_chord = m.value_of("emergency_chord").map(|s| s.to_owned());
+  
+  Arc::new(move |choice| {
+    let loaded = match choice {
+      control::LayoutChoice::Default(name) => {
+        load_layout(&Some(name), &None)
+          .and_then(|layout| apply_debounce_option(layout, &debounce_ms.as_deref()))
+          .and_then(|layout| apply_emergency_chord_option(layout, &emergency_chord.as_deref()))
+          .map(|layout| (layout, None))
+      },
+      control::LayoutChoice::File(path) => {
+        // Reloads and file changes follow the new file
+        let reloader = layout_reloader_for(path, &debounce_ms.as_deref(), &emergency_chord.as_deref());
+        (reloader.load)().map(|layout| (layout, Some(reloader)))
       }
+    };
+    loaded.map_err(|e| e.trim_start_matches("Error: ").to_owned())
+  })
+}
+
+fn describe_layout(default_layout: &Option<&str>, layout_file: &Option<&str>) -> String {
+  match (default_layout, layout_file) {
+    (Some(name), _) => format!("default {}", name),
+    (None, Some(path)) => format!("file {}", path),
+    (None, None) => "none".to_string()
+  }
+}
+
+// The line sent over the control socket
+fn ctl_command_line(m: &ArgMatches) -> Result<String, String> {
+  match m.value_of("COMMAND").unwrap() {
+    "layout" => match (m.value_of("default_layout"), m.value_of("layout_file")) {
+      (Some(name), None) => Ok(format!("layout default {}", name)),
+      (None, Some(path)) => {
+        // The remap process has its own working directory
+        let path = std::fs::canonicalize(path).map_err(|e| format!("Error: {}: {}", path, e))?;
+        Ok(format!("layout file {}", path.display()))
+      },
+      _ => Err("Error: `layout` needs either --default-layout or --layout-file.".to_string())
+    },
+    command @ ("status" | "pause" | "resume" | "reload") => Ok(command.to_string()),
+    command => Err(format!("Error: unknown command {}. Use status, pause, resume, reload or layout.", command))
+  }
+}
+
+fn apply_debounce_option(mut layout: Layout, debounce_ms: &Option<&str>) -> Result<Layout, String> {
+  match debounce_ms {
+    None => Ok(layout),
+    Some(text) => match text.parse::<i32>() {
+      Ok(delay_ms) if delay_ms >= 0 => {
+        layout.debounce = vec![Debounce { device: None, delay_ms, keys: Default::default() }];
+        Ok(layout)
+      },
+      _ => Err(format!("Error: --debounce-ms must be a number of milliseconds from 0 to {}, found {}", i32::MAX, text))
     }
   }
 }
 
+fn apply_emergency_chord_option(mut layout: Layout, emergency_chord: &Option<&str>) -> Result<Layout, String> {
+  match emergency_chord {
+    None => Ok(layout),
+    Some(text) => {
+      let mut keys = Vec::new();
+      for name in text.split(',') {
+        keys.push(layout_parsing_formatting::parse_key_code(name.trim()).map_err(|e| format!("Error: --emergency-chord: {}", e))?);
+      }
+      if keys.len() < 2 {
+        Err(format!("Error: --emergency-chord needs at least two keys, found {}", text))
+      }
+      else {
+        layout.emergency_chord = keys;
+        Ok(layout)
+      }
+    }
+  }
+}
@@ -1,9 +1,10 @@
 
 // vim: shiftwidth=2
 
-use crate::keys::Layout;
+use crate::keys::{Layout, Debounce};
 use nix::Error;
 use nix::errno::Errno::ENODEV;
+use nix::unistd::close;
 use wildmatch::WildMatch;
 use crate::key_transforms;
 use crate::keyboard_listing::{list_keyboards, ExtractedKeyboard, list_input_devices, ExtractedInputDevice};
@@ -23,15 +24,37 @@ use std::time;
 use crate::keys::KeyCode;
 use time::{Duration, Instant};
 use crate::keys::Event;
+use crate::keys::Led;
 use crate::keys::Event::{Pressed, Released};
-use crate::key_transforms::ResultingRepeat;
+use crate::key_transforms::{ResultingRepeat, ResultingTimer};
+use crate::debounce::Debouncer;
 use crate::tablet_mode_switch_reader::TableModeEvent;
 use inotify::{
   Inotify,
+  WatchDescriptor,
   WatchMask
 };
+use std::os::unix::io::AsRawFd;
+use crate::control::{Inbox, Command, DeviceStatus, broadcast};
+use crate::signals;
+use crate::keyboard_merging::MergedHeldKeys;
+use std::collections::VecDeque;
 
-pub fn do_remapping_loop_all_devices(layout: &Layout, excludes: &[&str], verbose: bool) -> Result<(), String> {
+// Loads the layout file again when it changes, with the same command
+// line options applied
+#[derive(Clone)]
+pub struct LayoutReloader {
+  pub path: PathBuf,
+  pub load: Arc<dyn Fn() -> Result<Layout, String> + Send + Sync>
+}
+
+impl std::fmt::Debug for LayoutReloader {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    f.debug_struct("LayoutReloader").field("path", &self.path).finish()
+  }
+}
+
+pub fn do_remapping_loop_all_devices(layout: &Layout, reloader: &Option<LayoutReloader>, excludes: &[&str], merge: bool, verbose: bool) -> Result<(), String> {
   match list_keyboards(false) {
     Err(e) => Err(format!("Failed to get the list of keyboards: {}", e)),
     Ok(devs) => {
@@ -49,7 +72,7 @@ pub fn do_remapping_loop_all_devices(layout: &Layout, excludes: &[&str], verbose
         .filter(|e| !e.excluded)
         .map(|e| e.extracted_keyboard).collect();
       
-      do_remapping_loop_these_devices(&devs.iter().map(|d| d.dev_path.clone()).collect(), layout, &None, verbose)
+      do_remapping_loop_these_devices(&devs.iter().map(|d| d.dev_path.clone()).collect(), layout, reloader, &None, merge, verbose)
     }
   }
 }
@@ -60,7 +83,20 @@ struct WorkingChild {
   done: Arc<Mutex<bool>>
 }
 
-pub fn do_remapping_loop_auto_all_devices(layout: &Layout, excludes: &[&str], verbose: bool) -> Result<(), String> {
+pub fn do_remapping_loop_auto_all_devices(layout: &Layout, reloader: &Option<LayoutReloader>, excludes: &[&str], merge: bool, verbose: bool) -> Result<(), String> {
+  if merge {
+    // Keyboards plugged in later are picked up by the driver itself
+    let hotplug = Hotplug::new(excludes, verbose)?;
+    let devs = list_keyboards(false).map_err(|e| format!("Failed to get the list of keyboards: {}", e))?;
+    let paths: Vec<PathBuf> = flag_excluded(devs, excludes).into_iter()
+      .filter(|e| !e.excluded)
+      .map(|e| e.extracted_keyboard.dev_path).collect();
+    
+    if verbose { eprintln!("Merging {} keyboards.", paths.len()); }
+    let mut driver = open_devices(&paths, Some(hotplug), &None, reloader, uses_mouse(layout))?;
+    return do_remapping_loop_one_device(&mut driver, layout.clone(), verbose);
+  }
+  
   let mut inotify = Inotify::init().expect("Error initializing");
   inotify.add_watch("/dev/input", WatchMask::CREATE | WatchMask::ATTRIB)
     .expect("Failed to add watch");
@@ -118,7 +154,7 @@ pub fn do_remapping_loop_auto_all_devices(layout: &Layout, excludes: &[&str], ve
           let already_have_it = children.iter().any(|c| c.dev_path == dev.dev_path);
           if verbose { eprintln!(" * {:?}: {}", dev.dev_path, already_have_it); }
           if !already_have_it {
-            match open_device(dev.dev_path.as_path(), &None) {
+            match open_device(dev.dev_path.as_path(), &None, reloader, uses_mouse(layout)) {
               Err(msg) => {
                 eprintln!("Failed to open keyboard device: {}", msg)
               },
@@ -150,18 +186,19 @@ pub fn do_remapping_loop_auto_all_devices(layout: &Layout, excludes: &[&str], ve
   }
 }
 
-pub fn do_remapping_loop_multiple_devices(devices: &Vec<&str>, skip_non_keyboard: bool, excludes: &[&str], layout: &Layout, tablet_mode_switch_device: &Option<&str>, verbose: bool) -> Result<(), String> {
-  let devices = filter_devices_verbose(devices, skip_non_keyboard, excludes, verbose)?;
-
+// `devices` as given by `filter_devices_verbose()`
+pub fn do_remapping_loop_multiple_devices(devices: &[&str], layout: &Layout, reloader: &Option<LayoutReloader>, tablet_mode_switch_device: &Option<&str>, merge: bool, verbose: bool) -> Result<(), String> {
   do_remapping_loop_these_devices(
-    &devices.into_iter().map(|p| Path::new(p).to_path_buf()).collect(),
+    &devices.iter().map(|p| Path::new(p).to_path_buf()).collect(),
     layout,
+    reloader,
     &tablet_mode_switch_device.map(|p| Path::new(p).to_path_buf()),
+    merge,
     verbose
   )
 }
 
-fn filter_devices_verbose<'s>(devices: &Vec<&'s str>, skip_non_keyboard: bool, excludes: &[&str], verbose: bool) -> Result<Vec<&'s str>, String> {
+pub fn filter_devices_verbose<'s>(devices: &Vec<&'s str>, skip_non_keyboard: bool, excludes: &[&str], verbose: bool) -> Result<Vec<&'s str>, String> {
   use std::fs::canonicalize;
   let mut res = Vec::new();
   
@@ -271,13 +308,23 @@ fn flag_excluded_input_devices(devices: Vec<ExtractedInputDevice>, excludes: &[&
   }).collect()
 }
 
-fn open_device(path: &Path, tablet_mode_switch_device: &Option<PathBuf>) -> Result<RealDriver, String> {
-  let r = match DevInputReader::open(path, Exclusion::WaitReleaseAndExclude, true) {
-    Err(e) => Err(format!("Failed to open {:?} for reading: {}", path, e)),
-    Ok(r) => Ok(r)
-  }?;
+fn open_device(path: &Path, tablet_mode_switch_device: &Option<PathBuf>, reloader: &Option<LayoutReloader>, pointer: bool) -> Result<RealDriver, String> {
+  open_devices(&[path.to_path_buf()], None, tablet_mode_switch_device, reloader, pointer)
+}
+
+// Relative axes make the virtual keyboard look like a mouse too, so it
+// only gets them if the layout it starts with has mouse mappings.
+fn uses_mouse(layout: &Layout) -> bool {
+  layout.mappings.iter()
+    .chain(layout.named_layouts.iter().flat_map(|named| named.mappings.iter()))
+    .any(|m| m.mouse.is_some())
+}
+
+// Several keyboards behind one virtual keyboard
+fn open_devices(paths: &[PathBuf], hotplug: Option<Hotplug>, tablet_mode_switch_device: &Option<PathBuf>, reloader: &Option<LayoutReloader>, pointer: bool) -> Result<RealDriver, String> {
+  let keyboards = Keyboards::open(paths, hotplug)?;
   
-  let w = match DevInputWriter::open() {
+  let w = match DevInputWriter::open(pointer) {
     Err(e) => Err(format!("Failed to open /dev/uinput for writing: {}", e)),
     Ok(w) => Ok(w)
   }?;
@@ -290,19 +337,28 @@ fn open_device(path: &Path, tablet_mode_switch_device: &Option<PathBuf>) -> Resu
     }
   }?;
   
-  let rw = RW { r, w, t };
+  let rw = RW { keyboards, w, t };
+  
+  let layout_watch = LayoutWatch::new(reloader.clone())?;
+  
+  let inbox = Inbox::open().map_err(|e| format!("Failed to open a pipe for commands: {}", e))?;
   
-  Ok(RealDriver { rw })
+  Ok(RealDriver { rw, layout_watch, inbox })
 }
 
-pub fn do_remapping_loop_these_devices(devices: &Vec<PathBuf>, layout: &Layout, tablet_mode_switch_device: &Option<PathBuf>, verbose: bool) -> Result<(), String> {
+pub fn do_remapping_loop_these_devices(devices: &Vec<PathBuf>, layout: &Layout, reloader: &Option<LayoutReloader>, tablet_mode_switch_device: &Option<PathBuf>, merge: bool, verbose: bool) -> Result<(), String> {
   if verbose { eprintln!("Remapping {} devices.", devices.len()); }
   
+  if merge {
+    let mut driver = open_devices(devices, None, tablet_mode_switch_device, reloader, uses_mouse(layout))?;
+    return do_remapping_loop_one_device(&mut driver, layout.clone(), verbose);
+  }
+  
   let mut drivers: Vec<RealDriver> = Vec::new();
   
   for p in devices {
     if verbose { eprintln!(" * {}", p.to_string_lossy()); }
-    drivers.push(open_device(p.as_path(), tablet_mode_switch_device)?);
+    drivers.push(open_device(p.as_path(), tablet_mode_switch_device, reloader, uses_mouse(layout))?);
   }
   
   let mut threads: Vec<JoinHandle<Result<(), String>>> = Vec::new();
@@ -327,10 +383,242 @@ pub fn do_remapping_loop_these_devices(devices: &Vec<PathBuf>, layout: &Layout,
 }
 
 struct RW {
-  r: DevInputReader,
+  keyboards: Keyboards,
   w: DevInputWriter,
   t: Option<TabletModeSwitchReader>
 }
+
+// The keyboards feeding one virtual keyboard. There is just one of them
+// unless merging.
+struct Keyboards {
+  readers: Vec<KeyboardReader>,
+  next_id: usize,
+  held: MergedHeldKeys,
+  // Releases owed for keyboards that went away
+  pending: VecDeque<Event>,
+  hotplug: Option<Hotplug>,
+  // The layout's rules, for keyboards plugged in later
+  debounce: Vec<Debounce>
+}
+
+struct KeyboardReader {
+  id: usize,
+  path: PathBuf,
+  r: DevInputReader,
+  // Before merging, so that rules match each keyboard's own name
+  debouncer: Debouncer
+}
+
+// When merging with --auto-all-keyboards, keyboards plugged in later
+// join the same virtual keyboard.
+struct Hotplug {
+  inotify: Inotify,
+  excludes: Vec<String>,
+  verbose: bool
+}
+
+impl Hotplug {
+  fn new(excludes: &[&str], verbose: bool) -> Result<Hotplug, String> {
+    let inotify = Inotify::init().map_err(|e| format!("Failed to watch /dev/input: {}", e))?;
+    inotify.watches().add("/dev/input", WatchMask::CREATE | WatchMask::ATTRIB)
+      .map_err(|e| format!("Failed to watch /dev/input: {}", e))?;
+    
+    Ok(Hotplug {
+      inotify,
+      excludes: excludes.iter().map(|e| e.to_string()).collect(),
+      verbose
+    })
+  }
+}
+
+impl Keyboards {
+  fn open(paths: &[PathBuf], hotplug: Option<Hotplug>) -> Result<Keyboards, String> {
+    let mut keyboards = Keyboards {
+      readers: Vec::new(),
+      next_id: 0,
+      held: MergedHeldKeys::new(),
+      pending: VecDeque::new(),
+      hotplug,
+      debounce: Vec::new()
+    };
+    
+    for path in paths {
+      match DevInputReader::open(path, Exclusion::WaitReleaseAndExclude, true) {
+        Err(e) => return Err(format!("Failed to open {:?} for reading: {}", path, e)),
+        Ok(r) => keyboards.add(path, r)
+      }
+    }
+    
+    Ok(keyboards)
+  }
+  
+  fn add(&mut self, path: &Path, r: DevInputReader) {
+    let debouncer = debouncer_for(&self.debounce, &r);
+    self.readers.push(KeyboardReader { id: self.next_id, path: path.to_path_buf(), r, debouncer });
+    self.next_id += 1;
+  }
+  
+  // Returns whether any keyboard is debounced
+  fn set_debounce(&mut self, rules: &[Debounce]) -> bool {
+    self.debounce = rules.to_vec();
+    for reader in &mut self.readers {
+      reader.debouncer = debouncer_for(rules, &reader.r);
+    }
+    self.readers.iter().any(|reader| !reader.debouncer.is_disabled())
+  }
+  
+  fn next(&mut self) -> Result<Next<Event>, String> {
+    let mut i = 0;
+    while self.pending.is_empty() && i < self.readers.len() {
+      match self.readers[i].r.next() {
+        Err(Error::Sys(EAGAIN)) => {
+          i += 1;
+        },
+        Err(Error::Sys(ENODEV)) => {
+          let gone = self.readers.remove(i);
+          eprintln!("Keyboard {:?} went away.", gone.path);
+          let _ = close(gone.r.fd);
+          self.pending.extend(self.held.remove_source(gone.id));
+        },
+        Err(e) => return Err(format!("read() from keyboard failed with {}", e)),
+        Ok(ev) => {
+          let reader = &mut self.readers[i];
+          if reader.debouncer.accept(&ev, Instant::now()) && self.held.accept(reader.id, &ev) {
+            return Ok(Next::One(ev));
+          }
+        }
+      }
+    }
+    
+    match self.pending.pop_front() {
+      Some(ev) => Ok(Next::One(ev)),
+      None if self.readers.is_empty() && self.hotplug.is_none() => Ok(Next::End),
+      None => Ok(Next::Busy)
+    }
+  }
+  
+  // Opens any keyboards plugged in since the last call
+  fn add_plugged_in(&mut self, registry: &mio::Registry) -> Result<(), String> {
+    let hotplug = match &mut self.hotplug {
+      None => return Ok(()),
+      Some(hotplug) => hotplug
+    };
+    
+    let mut buffer = [0; 1024];
+    loop {
+      match hotplug.inotify.read_events(&mut buffer) {
+        Ok(_) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+        Err(e) => return Err(format!("Failed to watch /dev/input: {}", e))
+      }
+    }
+    
+    let verbose = hotplug.verbose;
+    let excludes: Vec<&str> = hotplug.excludes.iter().map(|e| e.as_str()).collect();
+    let devs = list_keyboards(false).map_err(|e| format!("Failed to get the list of keyboards: {}", e))?;
+    
+    for dev in flag_excluded(devs, &excludes) {
+      let path = dev.extracted_keyboard.dev_path;
+      if dev.excluded || self.readers.iter().any(|reader| reader.path == path) {
+        continue;
+      }
+      
+      match DevInputReader::open(&path, Exclusion::WaitReleaseAndExclude, true) {
+        Err(e) => {
+          eprintln!("Failed to open {:?} for reading: {}", path, e);
+        },
+        Ok(r) => {
+          if verbose { eprintln!("Merging {:?}", path); }
+          registry.register(&mut SourceFd(&r.fd), KEYBOARD, Interest::READABLE).unwrap();
+          self.add(&path, r);
+        }
+      }
+    }
+    
+    Ok(())
+  }
+  
+  fn name(&self) -> Result<String, String> {
+    let mut names = Vec::new();
+    for reader in &self.readers {
+      names.push(reader.r.name().map_err(|e| format!("Failed to get the keyboard name: {}", e))?);
+    }
+    Ok(names.join(", "))
+  }
+  
+  fn ungrab(&self) -> Result<(), String> {
+    for reader in &self.readers {
+      reader.r.ungrab().map_err(|e| format!("Failed to ungrab the keyboard: {}", e))?;
+    }
+    Ok(())
+  }
+}
+
+fn debouncer_for(rules: &[Debounce], r: &DevInputReader) -> Debouncer {
+  match r.name() {
+    Ok(name) => Debouncer::for_device(rules, &name),
+    Err(_) => Debouncer::disabled()
+  }
+}
+
+// Editors often save by replacing the file, so we watch the directory
+// it is in. The inotify instance stays put so that `ctl layout` can
+// point it at another file, or at none.
+struct LayoutWatch {
+  inotify: Inotify,
+  reloader: Option<LayoutReloader>,
+  watch: Option<WatchDescriptor>
+}
+
+impl LayoutWatch {
+  fn new(reloader: Option<LayoutReloader>) -> Result<LayoutWatch, String> {
+    let inotify = Inotify::init().map_err(|e| format!("Failed to start inotify: {}", e))?;
+    let mut watch = LayoutWatch { inotify, reloader: None, watch: None };
+    watch.set_reloader(reloader)?;
+    Ok(watch)
+  }
+  
+  fn set_reloader(&mut self, reloader: Option<LayoutReloader>) -> Result<(), String> {
+    if let Some(wd) = self.watch.take() {
+      let _ = self.inotify.watches().remove(wd);
+    }
+    
+    if let Some(reloader) = &reloader {
+      let dir = match reloader.path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from(".")
+      };
+      
+      let wd = self.inotify.watches().add(&dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+        .map_err(|e| format!("Failed to watch {:?}: {}", dir, e))?;
+      self.watch = Some(wd);
+    }
+    
+    self.reloader = reloader;
+    Ok(())
+  }
+  
+  // Whether the layout file was written since the last call
+  fn changed(&mut self) -> Result<bool, String> {
+    let file_name = self.reloader.as_ref().and_then(|reloader| reloader.path.file_name());
+    let mut buffer = [0; 1024];
+    let mut changed = false;
+    
+    loop {
+      match self.inotify.read_events(&mut buffer) {
+        Ok(events) => {
+          for event in events {
+            if event.name.is_some() && event.name == file_name {
+              changed = true;
+            }
+          }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break Ok(changed),
+        Err(e) => break Err(format!("Failed to watch the layout file: {}", e))
+      }
+    }
+  }
+}
   
 #[derive(Debug)]
 enum WorkingRepeat {
@@ -342,10 +630,67 @@ enum WorkingRepeat {
   },
 }
 
+#[derive(Debug)]
+enum Wakeup {
+  Repeat,
+  Timer,
+  Mouse
+}
+
+fn next_wakeup(working_repeat: &WorkingRepeat, working_timer: &Option<Instant>, working_mouse: &Option<Instant>) -> Option<(Wakeup, Instant)> {
+  let mut candidates: Vec<(Wakeup, Instant)> = Vec::new();
+  
+  // On a tie the timer goes first
+  if let Some(deadline) = working_timer {
+    candidates.push((Wakeup::Timer, *deadline));
+  }
+  if let WorkingRepeat::Repeating { keys: _, next_wakeup, interval_ms: _ } = working_repeat {
+    candidates.push((Wakeup::Repeat, *next_wakeup));
+  }
+  if let Some(next_tick) = working_mouse {
+    candidates.push((Wakeup::Mouse, *next_tick));
+  }
+  
+  candidates.into_iter().min_by_key(|(_, at)| *at)
+}
+
+fn update_working_repeat(working_repeat: WorkingRepeat, repeat: ResultingRepeat) -> WorkingRepeat {
+  match repeat {
+    ResultingRepeat::Repeating { keys, delay_ms, interval_ms } => WorkingRepeat::Repeating {
+      keys,
+      next_wakeup: Instant::now() + Duration::from_millis(delay_ms as u64),
+      interval_ms
+    },
+    ResultingRepeat::Disabled => WorkingRepeat::Idle,
+    ResultingRepeat::NoChange => working_repeat
+  }
+}
+
+fn update_working_mouse(working_mouse: Option<Instant>, interval_ms: Option<i32>) -> Option<Instant> {
+  match (working_mouse, interval_ms) {
+    (_, None) => None,
+    (Some(next_tick), Some(_)) => Some(next_tick),
+    (None, Some(interval_ms)) => Some(Instant::now() + Duration::from_millis(interval_ms as u64))
+  }
+}
+
+fn update_working_timer(working_timer: Option<Instant>, timer: ResultingTimer) -> Option<Instant> {
+  match timer {
+    ResultingTimer::After { delay_ms } => Some(Instant::now() + Duration::from_millis(delay_ms as u64)),
+    ResultingTimer::Disabled => None,
+    ResultingTimer::NoChange => working_timer
+  }
+}
+
 #[derive(Debug)]
 enum Device {
   Keyboard,
-  Tablet
+  Tablet,
+  // LED changes written to our virtual keyboard
+  Leds,
+  LayoutFile,
+  // Signals and the control socket
+  Commands
 }
 
 #[derive(Debug)]
@@ -359,13 +704,33 @@ trait Driver {
   type PollRegistry;
   fn register_poll(&mut self) -> Result<Self::PollRegistry, String>;
   fn poll(&mut self, registry: &mut Self::PollRegistry, timeout: Option<Duration>) -> Result<PollResult, String>;
+  // Already debounced, per keyboard when merging
   fn next_keyboard(&mut self) -> Result<Next<Event>, String>;
+  // Returns whether any keyboard is debounced
+  fn set_debounce(&mut self, rules: &[Debounce]) -> bool;
   fn next_tablet(&mut self) -> Result<Next<TableModeEvent>, String>;
+  fn next_led(&mut self) -> Result<Next<(Led, bool)>, String>;
+  // The layout file, loaded again after it changes
+  fn next_layout(&mut self) -> Result<Next<Result<Layout, String>>, String>;
+  // The layout file, loaded again now, or `None` if there is no file
+  fn reload_layout(&mut self) -> Option<Result<Layout, String>>;
+  // Which file `reload_layout()` and `next_layout()` follow from now on
+  fn set_reloader(&mut self, reloader: Option<LayoutReloader>) -> Result<(), String>;
+  fn next_command(&mut self) -> Result<Next<Command>, String>;
+  // For `switch_all_keyboards`; this keyboard gets it as a command too
+  fn select_layout_everywhere(&mut self, name: &str);
   fn send(&mut self, evs: &Vec<Event>) -> Result<(), String>;
+  fn device_name(&self) -> Result<String, String>;
+  fn ungrab(&mut self) -> Result<(), String>;
+  // Stops the other keyboards too, and then the process, once this
+  // loop has returned
+  fn shut_down(&mut self);
 }
 
 struct RealDriver {
-  rw: RW
+  rw: RW,
+  layout_watch: LayoutWatch,
+  inbox: Inbox
 }
 
 struct RealPollRegistry {
@@ -375,6 +740,10 @@ struct RealPollRegistry {
 
 const KEYBOARD: Token = Token(0);
 const TABLET_SWITCH: Token = Token(1);
+const VIRTUAL_KEYBOARD: Token = Token(2);
+const LAYOUT_FILE: Token = Token(3);
+const COMMANDS: Token = Token(4);
+const HOTPLUG: Token = Token(5);
 
 #[derive(Debug)]
 enum Next<T> {
@@ -388,7 +757,18 @@ impl Driver for RealDriver {
   
   fn register_poll(&mut self) -> Result<RealPollRegistry, String> {
     let poll = Poll::new().unwrap();
-    poll.registry().register(&mut SourceFd(&self.rw.r.fd), KEYBOARD, Interest::READABLE).unwrap();
+    // Merged keyboards share a token; `next_keyboard()` reads them all
+    for reader in &self.rw.keyboards.readers {
+      poll.registry().register(&mut SourceFd(&reader.r.fd), KEYBOARD, Interest::READABLE).unwrap();
+    }
+    if let Some(hotplug) = &self.rw.keyboards.hotplug {
+      poll.registry().register(&mut SourceFd(&hotplug.inotify.as_raw_fd()), HOTPLUG, Interest::READABLE).unwrap();
+    }
+    poll.registry().register(&mut SourceFd(&self.rw.w.fd), VIRTUAL_KEYBOARD, Interest::READABLE).unwrap();
+    
+    poll.registry().register(&mut SourceFd(&self.layout_watch.inotify.as_raw_fd()), LAYOUT_FILE, Interest::READABLE).unwrap();
+    
+    poll.registry().register(&mut SourceFd(&self.inbox.fd), COMMANDS, Interest::READABLE).unwrap();
     
     match &self.rw.t {
       None => (),
@@ -406,6 +786,7 @@ impl Driver for RealDriver {
     match registry.poll.poll(&mut registry.events, timeout) {
       Ok(_) => {
         let mut res: Vec<Device> = Vec::new();
+        let mut plugged_in = false;
         
         for event in registry.events.iter() {
           match event.token() {
@@ -415,12 +796,25 @@ impl Driver for RealDriver {
             TABLET_SWITCH => {
               res.push(Device::Tablet)
             },
+            VIRTUAL_KEYBOARD => {
+              res.push(Device::Leds)
+            },
+            LAYOUT_FILE => {
+              res.push(Device::LayoutFile)
+            },
+            COMMANDS => {
+              res.push(Device::Commands)
+            },
+            HOTPLUG => {
+              self.rw.keyboards.add_plugged_in(registry.poll.registry())?;
+              plugged_in = true;
+            },
             Token(_) => {
             }
           }
         }
         
-        if res.is_empty() {
+        if res.is_empty() && !plugged_in {
           Ok(PollResult::TimedOut)
         }
         else {
@@ -444,12 +838,11 @@ impl Driver for RealDriver {
   }
   
   fn next_keyboard(&mut self) -> Result<Next<Event>, String> {
-    match self.rw.r.next() {
-      Err(Error::Sys(EAGAIN)) => Ok(Next::Busy),
-      Err(Error::Sys(ENODEV)) => Ok(Next::End),
-      Err(e) => Err(format!("read() from keyboard failed with {}", e)),
-      Ok(ev) => Ok(Next::One(ev))
-    }
+    self.rw.keyboards.next()
+  }
+  
+  fn set_debounce(&mut self, rules: &[Debounce]) -> bool {
+    self.rw.keyboards.set_debounce(rules)
   }
   
   fn next_tablet(&mut self) -> Result<Next<TableModeEvent>, String> {
@@ -466,6 +859,46 @@ impl Driver for RealDriver {
     }
   }
   
+  fn next_led(&mut self) -> Result<Next<(Led, bool)>, String> {
+    match self.rw.w.next_led() {
+      Err(Error::Sys(EAGAIN)) => Ok(Next::Busy),
+      Err(e) => Err(format!("read() from synthetic keyboard failed with {}", e)),
+      Ok(ev) => Ok(Next::One(ev))
+    }
+  }
+  
+  fn next_layout(&mut self) -> Result<Next<Result<Layout, String>>, String> {
+    if self.layout_watch.changed()? {
+      match self.reload_layout() {
+        None => Ok(Next::Busy),
+        Some(layout) => Ok(Next::One(layout))
+      }
+    }
+    else {
+      Ok(Next::Busy)
+    }
+  }
+  
+  fn reload_layout(&mut self) -> Option<Result<Layout, String>> {
+    self.layout_watch.reloader.as_ref().map(|reloader| (reloader.load)())
+  }
+  
+  fn set_reloader(&mut self, reloader: Option<LayoutReloader>) -> Result<(), String> {
+    self.layout_watch.set_reloader(reloader)
+  }
+  
+  fn next_command(&mut self) -> Result<Next<Command>, String> {
+    match self.inbox.next() {
+      Err(Error::Sys(EAGAIN)) => Ok(Next::Busy),
+      Err(e) => Err(format!("read() from command pipe failed with {}", e)),
+      Ok(command) => Ok(Next::One(command))
+    }
+  }
+  
+  fn select_layout_everywhere(&mut self, name: &str) {
+    broadcast(|| Command::SelectLayout(name.to_owned()));
+  }
+  
   fn send(&mut self, evs: &Vec<Event>) -> Result<(), String> {
     match self.rw.w.send(evs) {
       Err(e) => {
@@ -474,41 +907,101 @@ impl Driver for RealDriver {
       Ok(_) => Ok(())
     }
   }
+  
+  fn device_name(&self) -> Result<String, String> {
+    self.rw.keyboards.name()
+  }
+  
+  fn ungrab(&mut self) -> Result<(), String> {
+    self.rw.keyboards.ungrab()
+  }
+  
+  fn shut_down(&mut self) {
+    spawn(signals::shut_down);
+  }
 }
 
-fn do_remapping_loop_one_device(driver: &mut impl Driver, layout: Layout, verbose: bool) -> Result<(), String> {
+fn do_remapping_loop_one_device(driver: &mut impl Driver, mut layout: Layout, verbose: bool) -> Result<(), String> {
   let mut mapper = key_transforms::Mapper::for_layout(&layout);
+  let debouncing = driver.set_debounce(&layout.debounce);
   let mut working_repeat: WorkingRepeat = WorkingRepeat::Idle;
+  let mut working_timer: Option<Instant> = None;
+  let mut working_mouse: Option<Instant> = None;
   
   let mut poll = driver.register_poll()?;
   
   let mut in_tablet_mode: bool = false;
   let mut restart_count: i32 = 0;
+  let mut physically_held: Vec<KeyCode> = Vec::new();
   
-  if verbose { eprintln!("Starting remapping loop."); }
+  if verbose {
+    eprintln!("Starting remapping loop.");
+    if debouncing { eprintln!("Debouncing is on for this keyboard."); }
+  }
   
   loop {
     loop {
-      let timeout = match working_repeat {
-        WorkingRepeat::Idle => None,
-        WorkingRepeat::Repeating { keys: _, next_wakeup, interval_ms: _ } => {
+      let wakeup = next_wakeup(&working_repeat, &working_timer, &working_mouse);
+      
+      let timeout = match &wakeup {
+        None => None,
+        Some((_, next_wakeup)) => {
           let now = Instant::now();
-          if now >= next_wakeup {
+          if now >= *next_wakeup {
             Some(Duration::from_millis(1))
           }
           else {
-            Some(next_wakeup - now)
+            Some(*next_wakeup - now)
           }
         }
       };
       
       match driver.poll(&mut poll, timeout)? {
         PollResult::TimedOut => {
-          match working_repeat {
-            WorkingRepeat::Idle => {
+          match (wakeup, working_repeat) {
+            (None, _) => {
               // Well that's weird. I guess just keep going?
+              working_repeat = WorkingRepeat::Idle;
+            },
+            (Some((Wakeup::Timer, _)), repeat) => {
+              working_repeat = repeat;
+              working_timer = None;
+              if !in_tablet_mode {
+                let step_out = mapper.timeout();
+                
+                if !step_out.events.is_empty() {
+                  driver.send(&step_out.events)?;
+                }
+                
+                working_repeat = update_working_repeat(working_repeat, step_out.repeat);
+                working_timer = update_working_timer(working_timer, step_out.timer);
+                working_mouse = update_working_mouse(working_mouse, mapper.mouse_interval_ms());
+                
+                if toggle_pause_if_requested(&mut mapper, driver, verbose)? {
+                  working_repeat = WorkingRepeat::Idle;
+                  working_timer = None;
+                  working_mouse = None;
+                }
+              }
+            },
+            (Some((Wakeup::Mouse, next_tick)), repeat) => {
+              working_repeat = repeat;
+              working_mouse = None;
+              if !in_tablet_mode {
+                let mouse_events = mapper.mouse_tick();
+                
+                if !mouse_events.is_empty() {
+                  driver.send(&mouse_events)?;
+                }
+                
+                working_mouse = mapper.mouse_interval_ms()
+                  .map(|interval_ms| next_tick + Duration::from_millis(interval_ms as u64));
+              }
             },
-            WorkingRepeat::Repeating { keys, next_wakeup, interval_ms } => {
+            (Some((Wakeup::Repeat, _)), WorkingRepeat::Idle) => {
+              working_repeat = WorkingRepeat::Idle;
+            },
+            (Some((Wakeup::Repeat, _)), WorkingRepeat::Repeating { keys, next_wakeup, interval_ms }) => {
               if !in_tablet_mode {
                 let mut repeat_send = Vec::new();
                 for key in &keys {
@@ -553,6 +1046,17 @@ fn do_remapping_loop_one_device(driver: &mut impl Driver, layout: Layout, verbos
                       return Ok(());
                     }
                     Next::One(ev_in) => {
+                      if completes_emergency_chord(&layout.emergency_chord, &mut physically_held, &ev_in) {
+                        eprintln!("Emergency chord pressed. Ungrabbing the keyboard and exiting.");
+                        let release_events = mapper.release_all();
+                        if !release_events.is_empty() {
+                          driver.send(&release_events)?;
+                        }
+                        driver.ungrab()?;
+                        driver.shut_down();
+                        return Ok(());
+                      }
+                      
                       if !in_tablet_mode {
                         let step_out = mapper.step(ev_in);
                         let evs_out = step_out.events;
@@ -561,15 +1065,20 @@ fn do_remapping_loop_one_device(driver: &mut impl Driver, layout: Layout, verbos
                           driver.send(&evs_out)?;
                         }
                         
-                        working_repeat = match step_out.repeat {
-                          ResultingRepeat::Repeating { keys, delay_ms, interval_ms } => WorkingRepeat::Repeating {
-                            keys,
-                            next_wakeup: Instant::now() + Duration::from_millis(delay_ms as u64),
-                            interval_ms
-                          },
-                          ResultingRepeat::Disabled => WorkingRepeat::Idle,
-                          ResultingRepeat::NoChange => working_repeat
-                        };
+                        working_repeat = update_working_repeat(working_repeat, step_out.repeat);
+                        working_timer = update_working_timer(working_timer, step_out.timer);
+                        working_mouse = update_working_mouse(working_mouse, mapper.mouse_interval_ms());
+                        
+                        if toggle_pause_if_requested(&mut mapper, driver, verbose)? {
+                          working_repeat = WorkingRepeat::Idle;
+                          working_timer = None;
+                          working_mouse = None;
+                        }
+                        if switch_named_layout_if_requested(&mut mapper, driver, verbose)? {
+                          working_repeat = WorkingRepeat::Idle;
+                          working_timer = None;
+                          working_mouse = None;
+                        }
                       }
                     }
                   }
@@ -589,6 +1098,8 @@ fn do_remapping_loop_one_device(driver: &mut impl Driver, layout: Layout, verbos
                         On => {
                           in_tablet_mode = true;
                           working_repeat = WorkingRepeat::Idle;
+                          working_timer = None;
+                          working_mouse = None;
                           let release_events = mapper.release_all();
                           if !release_events.is_empty() {
                             driver.send(&release_events)?;
@@ -597,6 +1108,8 @@ fn do_remapping_loop_one_device(driver: &mut impl Driver, layout: Layout, verbos
                         Off => {
                           in_tablet_mode = false;
                           working_repeat = WorkingRepeat::Idle;
+                          working_timer = None;
+                          working_mouse = None;
                           let release_events = mapper.release_all();
                           if !release_events.is_empty() {
                             driver.send(&release_events)?;
@@ -606,6 +1119,106 @@ fn do_remapping_loop_one_device(driver: &mut impl Driver, layout: Layout, verbos
                     }
                   }
                 }
+              },
+              Device::Leds => {
+                loop {
+                  match driver.next_led()? {
+                    Next::Busy | Next::End => {
+                      break;
+                    },
+                    Next::One((led, on)) => {
+                      if verbose { eprintln!("LED {:?} is {}", led, if on { "on" } else { "off" }); }
+                      mapper.set_led(led, on);
+                    }
+                  }
+                }
+              },
+              Device::LayoutFile => {
+                loop {
+                  match driver.next_layout()? {
+                    Next::Busy | Next::End => {
+                      break;
+                    },
+                    Next::One(Err(msg)) => {
+                      eprintln!("Keeping the old layout: {}", msg);
+                    },
+                    Next::One(Ok(new_layout)) => {
+                      working_repeat = WorkingRepeat::Idle;
+                      working_timer = None;
+                      working_mouse = None;
+                      switch_layout(driver, &mut mapper, &new_layout)?;
+                      layout = new_layout;
+                      if verbose { eprintln!("Reloaded the layout."); }
+                    }
+                  }
+                }
+              },
+              Device::Commands => {
+                loop {
+                  match driver.next_command()? {
+                    Next::Busy | Next::End => {
+                      break;
+                    },
+                    Next::One(Command::Terminate) => {
+                      if verbose { eprintln!("Letting go of the keyboard and stopping."); }
+                      let release_events = mapper.release_all();
+                      if !release_events.is_empty() {
+                        driver.send(&release_events)?;
+                      }
+                      driver.ungrab()?;
+                      return Ok(());
+                    },
+                    Next::One(Command::Reload) => {
+                      match driver.reload_layout() {
+                        None => {
+                          eprintln!("Not reloading: the layout is not from a file.");
+                        },
+                        Some(Err(msg)) => {
+                          eprintln!("Keeping the old layout: {}", msg);
+                        },
+                        Some(Ok(new_layout)) => {
+                          working_repeat = WorkingRepeat::Idle;
+                          working_timer = None;
+                          working_mouse = None;
+                          switch_layout(driver, &mut mapper, &new_layout)?;
+                          layout = new_layout;
+                          if verbose { eprintln!("Reloaded the layout."); }
+                        }
+                      }
+                    },
+                    Next::One(Command::SetLayout(new_layout, reloader)) => {
+                      working_repeat = WorkingRepeat::Idle;
+                      working_timer = None;
+                      working_mouse = None;
+                      switch_layout(driver, &mut mapper, &new_layout)?;
+                      layout = new_layout;
+                      driver.set_reloader(reloader)?;
+                      if verbose { eprintln!("Switched layouts."); }
+                    },
+                    Next::One(Command::SetPaused(paused)) => {
+                      if set_paused(&mut mapper, driver, paused, verbose)? {
+                        working_repeat = WorkingRepeat::Idle;
+                        working_timer = None;
+                        working_mouse = None;
+                      }
+                    },
+                    Next::One(Command::SelectLayout(name)) => {
+                      if select_named_layout(&mut mapper, driver, &name, verbose)? {
+                        working_repeat = WorkingRepeat::Idle;
+                        working_timer = None;
+                        working_mouse = None;
+                      }
+                    },
+                    Next::One(Command::Status(reply)) => {
+                      let _ = reply.send(DeviceStatus {
+                        name: driver.device_name()?,
+                        paused: mapper.is_paused(),
+                        layout: mapper.active_layout_name().map(|name| name.to_owned()),
+                        held_keys: physically_held.clone()
+                      });
+                    }
+                  }
+                }
               }
             }
           }
@@ -615,13 +1228,110 @@ fn do_remapping_loop_one_device(driver: &mut impl Driver, layout: Layout, verbos
   }
 }
 
+// Lets go of everything and starts using `new_layout`
+fn switch_layout(driver: &mut impl Driver, mapper: &mut key_transforms::Mapper, new_layout: &Layout) -> Result<(), String> {
+  let release_events = mapper.release_all();
+  if !release_events.is_empty() {
+    driver.send(&release_events)?;
+  }
+  mapper.replace_layout(new_layout);
+  driver.set_debounce(&new_layout.debounce);
+  Ok(())
+}
+
+// Keeps track of the keys held on the keyboards, after debouncing and
+// merging but before mapping, and says whether this press completes the
+// emergency chord.
+fn completes_emergency_chord(chord: &[KeyCode], held: &mut Vec<KeyCode>, ev: &Event) -> bool {
+  match ev {
+    Pressed(k) => {
+      if !held.contains(k) {
+        held.push(*k);
+      }
+      chord.contains(k) && chord.iter().all(|k2| held.contains(k2))
+    },
+    Released(k) => {
+      held.retain(|k2| k2 != k);
+      false
+    },
+    _ => false
+  }
+}
+
+// Like switching tablet mode, pausing or resuming lets go of everything
+// first. Returns whether it happened.
+fn toggle_pause_if_requested(mapper: &mut key_transforms::Mapper, driver: &mut impl Driver, verbose: bool) -> Result<bool, String> {
+  if !mapper.take_pause_toggle() {
+    return Ok(false);
+  }
+  
+  let paused = !mapper.is_paused();
+  set_paused(mapper, driver, paused, verbose)
+}
+
+// Returns whether this changed anything
+fn set_paused(mapper: &mut key_transforms::Mapper, driver: &mut impl Driver, paused: bool, verbose: bool) -> Result<bool, String> {
+  if mapper.is_paused() == paused {
+    return Ok(false);
+  }
+  
+  let release_events = mapper.release_all();
+  if !release_events.is_empty() {
+    driver.send(&release_events)?;
+  }
+  
+  mapper.set_paused(paused);
+  if verbose { eprintln!("{}", if paused { "Remapping paused." } else { "Remapping resumed." }); }
+  
+  Ok(true)
+}
+
+// With `switch_all_keyboards` the switch goes to every keyboard,
+// including this one, as a command. Returns whether this keyboard
+// switched.
+fn switch_named_layout_if_requested(mapper: &mut key_transforms::Mapper, driver: &mut impl Driver, verbose: bool) -> Result<bool, String> {
+  match mapper.take_layout_switch() {
+    None => Ok(false),
+    Some(name) => {
+      if mapper.switches_all_keyboards() {
+        driver.select_layout_everywhere(&name);
+        Ok(false)
+      }
+      else {
+        select_named_layout(mapper, driver, &name, verbose)
+      }
+    }
+  }
+}
+
+// Returns whether this changed anything
+fn select_named_layout(mapper: &mut key_transforms::Mapper, driver: &mut impl Driver, name: &str, verbose: bool) -> Result<bool, String> {
+  if mapper.active_layout_name() == Some(name) {
+    return Ok(false);
+  }
+  
+  let release_events = mapper.release_all();
+  if !release_events.is_empty() {
+    driver.send(&release_events)?;
+  }
+  
+  if mapper.select_layout(name) {
+    if verbose { eprintln!("Switched to layout {}.", name); }
+  }
+  else {
+    eprintln!("There is no layout named {}.", name);
+  }
+  
+  Ok(true)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
   use std::collections::VecDeque;
   use KeyCode::*;
   use std::default::Default;
-  use crate::keys::{Layout, Mapping, KeyCode, Pressed, Released, Event, Repeat};
+  use crate::keys::{Layout, NamedLayout, Mapping, KeyCode, Pressed, Released, Event, Repeat, Tap, Sequence, Mouse, Axis, Debounce, Action};
   
   #[derive(Debug)]
   enum TestOp {
@@ -636,19 +1346,43 @@ mod tests {
     NextTablet {
       result: Next<TableModeEvent>
     },
-    Send {
-      evs: Vec<Event>
-    }
+    NextLed {
+      result: Next<(Led, bool)>
+    },
+    NextLayout {
+      result: Next<Result<Layout, String>>
+    },
+    ReloadLayout,
+    SetReloader {
+      path: Option<PathBuf>
+    },
+    NextCommand {
+      result: Next<Command>
+    },
+    SelectLayoutEverywhere {
+      name: String
+    },
+    Send {
+      evs: Vec<Event>
+    },
+    Ungrab,
+    ShutDown
   }
   
   struct TestPollRegistry {
   }
   
   struct TestDriver {
-    ops: VecDeque<TestOp>
+    ops: VecDeque<TestOp>,
+    reloader: Option<LayoutReloader>,
+    debouncer: Debouncer
   }
   
   impl TestDriver {
+    fn new(ops: VecDeque<TestOp>) -> TestDriver {
+      TestDriver { ops, reloader: None, debouncer: Debouncer::disabled() }
+    }
+    
     fn finish(&self) {
       assert!(self.ops.is_empty());
     }
@@ -703,6 +1437,15 @@ mod tests {
         None => {
           panic!("next_keyboard() on empty op list")
         },
+        Some(TestOp::NextKeyboard { result: Next::One(ev) }) => {
+          // Like a real keyboard, skipping what the debouncer drops
+          if self.debouncer.accept(&ev, Instant::now()) {
+            Ok(Next::One(ev))
+          }
+          else {
+            self.next_keyboard()
+          }
+        },
         Some(TestOp::NextKeyboard { result }) => {
           Ok(result)
         },
@@ -712,6 +1455,11 @@ mod tests {
       }
     }
     
+    fn set_debounce(&mut self, rules: &[Debounce]) -> bool {
+      self.debouncer = Debouncer::for_device(rules, "Test Keyboard");
+      !self.debouncer.is_disabled()
+    }
+    
     fn next_tablet(&mut self) -> Result<Next<TableModeEvent>, String> {
       match self.ops.pop_front() {
         None => {
@@ -726,6 +1474,51 @@ mod tests {
       }
     }
     
+    fn next_led(&mut self) -> Result<Next<(Led, bool)>, String> {
+      match self.ops.pop_front() {
+        Some(TestOp::NextLed { result }) => Ok(result),
+        other => panic!("next_led() called but should have called {:?}", other)
+      }
+    }
+    
+    fn next_layout(&mut self) -> Result<Next<Result<Layout, String>>, String> {
+      match self.ops.pop_front() {
+        Some(TestOp::NextLayout { result }) => Ok(result),
+        other => panic!("next_layout() called but should have called {:?}", other)
+      }
+    }
+    
+    fn reload_layout(&mut self) -> Option<Result<Layout, String>> {
+      match self.ops.pop_front() {
+        Some(TestOp::ReloadLayout) => self.reloader.as_ref().map(|reloader| (reloader.load)()),
+        other => panic!("reload_layout() called but should have called {:?}", other)
+      }
+    }
+    
+    fn set_reloader(&mut self, reloader: Option<LayoutReloader>) -> Result<(), String> {
+      let path = reloader.as_ref().map(|reloader| reloader.path.clone());
+      match self.ops.pop_front() {
+        Some(TestOp::SetReloader { path: expected }) => assert_eq!(expected, path),
+        other => panic!("set_reloader({:?}) called but should have called {:?}", path, other)
+      }
+      self.reloader = reloader;
+      Ok(())
+    }
+    
+    fn next_command(&mut self) -> Result<Next<Command>, String> {
+      match self.ops.pop_front() {
+        Some(TestOp::NextCommand { result }) => Ok(result),
+        other => panic!("next_command() called but should have called {:?}", other)
+      }
+    }
+    
+    fn select_layout_everywhere(&mut self, name: &str) {
+      match self.ops.pop_front() {
+        Some(TestOp::SelectLayoutEverywhere { name: expected }) => assert_eq!(expected, name),
+        other => panic!("select_layout_everywhere({}) called but should have called {:?}", name, other)
+      }
+    }
+    
     fn send(&mut self, evs: &Vec<Event>) -> Result<(), String> {
       match self.ops.pop_front() {
         None => {
@@ -740,6 +1533,24 @@ mod tests {
         }
       }
     }
+    
+    fn device_name(&self) -> Result<String, String> {
+      Ok("Test Keyboard".to_owned())
+    }
+    
+    fn ungrab(&mut self) -> Result<(), String> {
+      match self.ops.pop_front() {
+        Some(TestOp::Ungrab) => Ok(()),
+        other => panic!("ungrab() called but should have called {:?}", other)
+      }
+    }
+    
+    fn shut_down(&mut self) {
+      match self.ops.pop_front() {
+        Some(TestOp::ShutDown) => (),
+        other => panic!("shut_down() called but should have called {:?}", other)
+      }
+    }
   }
   
   #[test]
@@ -747,7 +1558,8 @@ mod tests {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![A], to: vec![B], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut ops: VecDeque<TestOp> = VecDeque::new();
@@ -761,7 +1573,7 @@ mod tests {
     ops.push_back(TestOp::Send { evs: vec![Released(B)] });
     ops.push_back(TestOp::NextKeyboard { result: Next::End });
     
-    let mut driver = TestDriver { ops };
+    let mut driver = TestDriver::new(ops);
     do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
     driver.finish();
   }
@@ -771,7 +1583,8 @@ mod tests {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![A], to: vec![B], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut ops: VecDeque<TestOp> = VecDeque::new();
@@ -786,7 +1599,7 @@ mod tests {
     ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(A)) });
     ops.push_back(TestOp::NextKeyboard { result: Next::End });
     
-    let mut driver = TestDriver { ops };
+    let mut driver = TestDriver::new(ops);
     do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
     driver.finish();
   }
@@ -797,7 +1610,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Special { keys: vec![C], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut ops: VecDeque<TestOp> = VecDeque::new();
@@ -837,7 +1651,7 @@ mod tests {
     ops.push_back(TestOp::Send { evs: vec![Released(LEFTSHIFT)] });
     ops.push_back(TestOp::NextKeyboard { result: Next::End });
     
-    let mut driver = TestDriver { ops };
+    let mut driver = TestDriver::new(ops);
     do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
     driver.finish();
   }
@@ -848,7 +1662,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Special { keys: vec![C], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut ops: VecDeque<TestOp> = VecDeque::new();
@@ -884,7 +1699,7 @@ mod tests {
     ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
     ops.push_back(TestOp::NextKeyboard { result: Next::End });
     
-    let mut driver = TestDriver { ops };
+    let mut driver = TestDriver::new(ops);
     do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
     driver.finish();
   }
@@ -895,7 +1710,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Special { keys: vec![LEFTCTRL, C], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut ops: VecDeque<TestOp> = VecDeque::new();
@@ -931,9 +1747,508 @@ mod tests {
     ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
     ops.push_back(TestOp::NextKeyboard { result: Next::End });
     
-    let mut driver = TestDriver { ops };
+    let mut driver = TestDriver::new(ops);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+  }
+  
+  #[test]
+  fn test_remapping_loop_tap_hold() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], tap: Some(Tap { keys: vec![ESC], tapping_term_ms: 200, extra_taps: vec![] }), ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(CAPSLOCK)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: Some(Duration::from_millis(200)), result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(CAPSLOCK)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(ESC), Released(ESC)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(CAPSLOCK)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: Some(Duration::from_millis(200)), result: PollResult::TimedOut });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(J)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(LEFT)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(CAPSLOCK)) });
+    ops.push_back(TestOp::Send { evs: vec![Released(LEFT)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::End });
+    
+    let mut driver = TestDriver::new(ops);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+  }
+  
+  #[test]
+  fn test_remapping_loop_sequence_timeout() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![RIGHTALT, G], to: vec![HOME], sequence: Some(Sequence { timeout_ms: 1000 }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(RIGHTALT)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(RIGHTALT)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(RIGHTALT)) });
+    ops.push_back(TestOp::Send { evs: vec![Released(RIGHTALT)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: Some(Duration::from_millis(1000)), result: PollResult::TimedOut });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(G)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(G)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::End });
+    
+    let mut driver = TestDriver::new(ops);
     do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
     driver.finish();
   }
-}
 
+  #[test]
+  fn test_remapping_loop_mouse() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![L], to: vec![], mouse: Some(Mouse::Move { x: 10, y: 0 }), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(L)) });
+    ops.push_back(TestOp::Send { evs: vec![Event::Moved { axis: Axis::X, delta: 10 }] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: Some(Duration::from_millis(20)), result: PollResult::TimedOut });
+    ops.push_back(TestOp::Send { evs: vec![Event::Moved { axis: Axis::X, delta: 10 }] });
+    
+    ops.push_back(TestOp::Poll { timeout: Some(Duration::from_millis(40)), result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(L)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::End });
+    
+    let mut driver = TestDriver::new(ops);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+  }
+  
+  #[test]
+  fn test_remapping_loop_debounce() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      debounce: vec![
+        Debounce { device: Some("Other*".to_owned()), delay_ms: 0, keys: Default::default() },
+        Debounce { device: Some("Test*".to_owned()), delay_ms: 10000, keys: Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(B)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Released(B)] });
+    // Chatter: dropped along with its release
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(A)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(C)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(C)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::End });
+    
+    let mut driver = TestDriver::new(ops);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+  }
+  
+  #[test]
+  fn test_remapping_loop_pause() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![RIGHTCTRL, PAUSE], to: vec![], action: Some(Action::TogglePause), ..Default::default() },
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(RIGHTCTRL)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(RIGHTCTRL)] });
+    // Pausing lets go of everything
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(PAUSE)) });
+    ops.push_back(TestOp::Send { evs: vec![Released(RIGHTCTRL)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(PAUSE)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(RIGHTCTRL)) });
+    // Then keys go out unchanged
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(A)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Released(A)] });
+    // Until the same hotkey resumes remapping
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(RIGHTCTRL)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(RIGHTCTRL)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(PAUSE)) });
+    ops.push_back(TestOp::Send { evs: vec![Released(RIGHTCTRL)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(PAUSE)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(RIGHTCTRL)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(B)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::End });
+    
+    let mut driver = TestDriver::new(ops);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+  }
+  
+  #[test]
+  fn test_remapping_loop_emergency_chord() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![ESC], to: vec![], ..Default::default() },
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      emergency_chord: vec![BACKSPACE, ESC, ENTER],
+      ..Default::default()
+    };
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(B)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(BACKSPACE)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(BACKSPACE)] });
+    // Checked on the physical keys, even though ESC does nothing here
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(ESC)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(ENTER)) });
+    ops.push_back(TestOp::Send { evs: vec![Released(B), Released(BACKSPACE)] });
+    ops.push_back(TestOp::Ungrab);
+    ops.push_back(TestOp::ShutDown);
+    
+    let mut driver = TestDriver::new(ops);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+  }
+  
+  #[test]
+  fn test_remapping_loop_if_led() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![J], to: vec![KP1], if_led: vec![Led::Num], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(J)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(J)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(J)) });
+    ops.push_back(TestOp::Send { evs: vec![Released(J)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Leds]) });
+    ops.push_back(TestOp::NextLed { result: Next::One((Led::Num, true)) });
+    ops.push_back(TestOp::NextLed { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(J)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(KP1)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::End });
+    
+    let mut driver = TestDriver::new(ops);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+  }
+  
+  #[test]
+  fn test_remapping_loop_reload() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let new_layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![C], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(B)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    // A broken layout is ignored
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::LayoutFile]) });
+    ops.push_back(TestOp::NextLayout { result: Next::One(Err("Unknown key code: BEE".to_owned())) });
+    ops.push_back(TestOp::NextLayout { result: Next::Busy });
+    
+    // A good one lets go of everything first
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::LayoutFile]) });
+    ops.push_back(TestOp::NextLayout { result: Next::One(Ok(new_layout)) });
+    ops.push_back(TestOp::Send { evs: vec![Released(B)] });
+    ops.push_back(TestOp::NextLayout { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(A)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(C)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::End });
+    
+    let mut driver = TestDriver::new(ops);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+  }
+  
+  #[test]
+  fn test_remapping_loop_signals() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let new_layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![C], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(LEFTSHIFT)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(LEFTSHIFT)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    // SIGHUP
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Commands]) });
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::Reload) });
+    ops.push_back(TestOp::ReloadLayout);
+    ops.push_back(TestOp::Send { evs: vec![Released(LEFTSHIFT)] });
+    ops.push_back(TestOp::NextCommand { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(C)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    // SIGTERM
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Commands]) });
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::Terminate) });
+    ops.push_back(TestOp::Send { evs: vec![Released(C)] });
+    ops.push_back(TestOp::Ungrab);
+    
+    let reloader = LayoutReloader {
+      path: PathBuf::from("layout.json"),
+      load: Arc::new(move || Ok(new_layout.clone()))
+    };
+    let mut driver = TestDriver::new(ops);
+    driver.reloader = Some(reloader);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+  }
+  
+  #[test]
+  fn test_remapping_loop_control_commands() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let new_layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![C], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let (sender, receiver) = std::sync::mpsc::channel();
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(B)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Commands]) });
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::Status(sender)) });
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::SetPaused(true)) });
+    ops.push_back(TestOp::Send { evs: vec![Released(B)] });
+    // Already paused
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::SetPaused(true)) });
+    ops.push_back(TestOp::NextCommand { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    // B was already let go of when pausing
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Released(A)) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Commands]) });
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::SetPaused(false)) });
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::SetLayout(new_layout, None)) });
+    ops.push_back(TestOp::SetReloader { path: None });
+    ops.push_back(TestOp::NextCommand { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(C)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::End });
+    
+    let mut driver = TestDriver::new(ops);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+    
+    let status = receiver.recv().unwrap();
+    assert_eq!("Test Keyboard", status.name);
+    assert!(!status.paused);
+    assert_eq!(vec![A], status.held_keys);
+  }
+  
+  #[test]
+  fn test_remapping_loop_reload_after_set_layout() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let new_layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![C], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let old_file = LayoutReloader {
+      path: PathBuf::from("old.json"),
+      load: Arc::new(move || Ok(layout.clone()))
+    };
+    let new_file = LayoutReloader {
+      path: PathBuf::from("new.json"),
+      load: Arc::new(move || Ok(new_layout.clone()))
+    };
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Commands]) });
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::SetLayout((new_file.load)().unwrap(), Some(new_file))) });
+    ops.push_back(TestOp::SetReloader { path: Some(PathBuf::from("new.json")) });
+    // Reloads the new file, not the one from the command line
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::Reload) });
+    ops.push_back(TestOp::ReloadLayout);
+    ops.push_back(TestOp::NextCommand { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(C)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::End });
+    
+    let mut driver = TestDriver::new(ops);
+    driver.reloader = Some(old_file.clone());
+    do_remapping_loop_one_device(&mut driver, (old_file.load)().unwrap(), true).unwrap();
+    driver.finish();
+  }
+  
+  #[test]
+  fn test_remapping_loop_switch_all_keyboards() {
+    let next_layout = Mapping { from: vec![F12], to: vec![], action: Some(Action::NextLayout), ..Default::default() };
+    let layout = Layout {
+      mappings: vec![next_layout.clone()],
+      named_layouts: vec![
+        NamedLayout { name: "plain".to_owned(), mappings: vec![next_layout.clone()] },
+        NamedLayout { name: "swapped".to_owned(), mappings: vec![
+          next_layout.clone(),
+          Mapping { from: vec![A], to: vec![B], ..Default::default() },
+        ] },
+      ],
+      switch_all_keyboards: true,
+      ..Default::default()
+    };
+    let (sender, receiver) = std::sync::mpsc::channel();
+    
+    let mut ops: VecDeque<TestOp> = VecDeque::new();
+    ops.push_back(TestOp::RegisterPoll);
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(LEFTSHIFT)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(LEFTSHIFT)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(F12)) });
+    ops.push_back(TestOp::SelectLayoutEverywhere { name: "swapped".to_owned() });
+    ops.push_back(TestOp::NextKeyboard { result: Next::Busy });
+    
+    // The switch comes back to this keyboard too
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Commands]) });
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::SelectLayout("swapped".to_owned())) });
+    ops.push_back(TestOp::Send { evs: vec![Released(LEFTSHIFT)] });
+    ops.push_back(TestOp::NextCommand { result: Next::One(Command::Status(sender)) });
+    ops.push_back(TestOp::NextCommand { result: Next::Busy });
+    
+    ops.push_back(TestOp::Poll { timeout: None, result: PollResult::DeviceEvent(vec![Device::Keyboard]) });
+    ops.push_back(TestOp::NextKeyboard { result: Next::One(Pressed(A)) });
+    ops.push_back(TestOp::Send { evs: vec![Pressed(B)] });
+    ops.push_back(TestOp::NextKeyboard { result: Next::End });
+    
+    let mut driver = TestDriver::new(ops);
+    do_remapping_loop_one_device(&mut driver, layout, true).unwrap();
+    driver.finish();
+    
+    assert_eq!(Some("swapped".to_owned()), receiver.recv().unwrap().layout);
+  }
+  
+  #[test]
+  fn test_uses_mouse() {
+    let mut layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    assert!(!uses_mouse(&layout));
+    
+    layout.named_layouts.push(NamedLayout {
+      name: "mouse".to_owned(),
+      mappings: vec![
+        Mapping { from: vec![J], to: vec![], mouse: Some(Mouse::Move { x: -10, y: 0 }), ..Default::default() },
+      ]
+    });
+    assert!(uses_mouse(&layout));
+  }
+}
@@ -0,0 +1,56 @@
+// vim: shiftwidth=2
+
+use crate::control::{broadcast, open_inboxes, remove_socket, Command};
+use nix::sys::signal::{SigSet, Signal};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+// Signals are handled on their own thread and passed on to every
+// keyboard's remapping loop as a `Command`, since a signal only wakes up
+// one thread.
+
+// How long the remapping loops get to let go of their keys before we
+// exit anyway
+const SHUTDOWN_GRACE_MS: u64 = 1000;
+
+// Must be called before any other threads are started, so that they
+// all leave these signals to us.
+pub fn start(verbose: bool) -> Result<(), String> {
+  let mut signals = SigSet::empty();
+  signals.add(Signal::SIGTERM);
+  signals.add(Signal::SIGINT);
+  signals.add(Signal::SIGHUP);
+  signals.thread_block().map_err(|e| format!("Failed to block signals: {}", e))?;
+
+  spawn(move || {
+    loop {
+      let signal = match signals.wait() {
+        Ok(signal) => signal,
+        Err(_) => continue
+      };
+      if verbose { eprintln!("Got {:?}", signal); }
+
+      if signal == Signal::SIGHUP {
+        broadcast(|| Command::Reload);
+      }
+      else {
+        shut_down();
+      }
+    }
+  });
+
+  Ok(())
+}
+
+// Asks every remapping loop to let go of its keys and stop, then exits
+// once they have. Blocks, so a remapping loop must call it on another
+// thread.
+pub fn shut_down() {
+  broadcast(|| Command::Terminate);
+  let deadline = Instant::now() + Duration::from_millis(SHUTDOWN_GRACE_MS);
+  while open_inboxes() > 0 && Instant::now() < deadline {
+    sleep(Duration::from_millis(10));
+  }
+  remove_socket();
+  std::process::exit(0);
+}
@@ -7,7 +7,6 @@ use std::io::Write;
 use std::os::unix::prelude::MetadataExt;
 use std::path::Path;
 use std::process::Command;
-use crate::keys::Layout;
 use crate::keyboard_listing::list_keyboards;
 
 fn convert_io_error<T>(whats_happening: &str, res: Result<T, std::io::Error>) -> Result<T, String> {
@@ -17,16 +16,9 @@ fn convert_io_error<T>(whats_happening: &str, res: Result<T, std::io::Error>) ->
   }
 }
 
-fn convert_json_error<T>(whats_happening: &str, res: Result<T, serde_json::Error>) -> Result<T, String> {
-  match res {
-    Ok(t) => Ok(t),
-    Err(e) => Err(format!("Error {}: {}", whats_happening, e))
-  }
-}
-
-pub fn add_systemd_service<'s, I: Iterator<Item=&'s str>>(layout: &Layout, excludes: I) -> Result<(), String> {
+pub fn add_systemd_service<'s, I: Iterator<Item=&'s str>>(layout_json: &str, excludes: I) -> Result<(), String> {
   check_usr_bin_totalmapper_exists();
-  write_layout_to_global_config(layout)?;
+  write_layout_to_global_config(layout_json)?;
   create_input_group_if_necessary()?;
   create_user_if_necessary()?;
   set_permissions_if_necessary()?;
@@ -76,22 +68,19 @@ fn check_usr_bin_totalmapper_exists() {
   }
 }
 
-fn write_layout_to_global_config(layout: &Layout) -> Result<(), String> {
-  let file_out = convert_io_error(
+// The layout is saved as the user wrote it rather than re-serialized from
+// `keys::Layout`, since not every mapping option survives that round trip.
+fn write_layout_to_global_config(layout_json: &str) -> Result<(), String> {
+  let mut file_out = convert_io_error(
     "saving layout to /etc/totalmapper.json",
     OpenOptions::new()
       .truncate(true).read(false).create(true).write(true)
       .open("/etc/totalmapper.json")
   )?;
   
-  let buffered_out = std::io::BufWriter::new(file_out);
-  
-  convert_json_error(
+  convert_io_error(
     "saving layout to /etc/totalmapper.json",
-    serde_json::to_writer_pretty(
-      buffered_out,
-      layout
-    )
+    file_out.write_all(layout_json.as_bytes())
   )?;
   
   Ok(())
@@ -0,0 +1,69 @@
+// vim: shiftwidth=2
+
+use crate::keys::{KeyCode, MacroStep};
+use KeyCode::*;
+
+// Ways of typing a character that has no key of its own. Each one
+// turns the character into a macro.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnicodeMethod {
+  // GTK and IBus: Ctrl+Shift+U, the hex code point, then Space
+  CtrlShiftU,
+}
+
+pub const DEFAULT_UNICODE_METHOD: UnicodeMethod = UnicodeMethod::CtrlShiftU;
+
+pub fn parse_unicode_method(name: &str) -> Result<UnicodeMethod, String> {
+  match name {
+    "ctrl_shift_u" => Ok(UnicodeMethod::CtrlShiftU),
+    _ => Err(format!("Unknown unicode method: {}", name))
+  }
+}
+
+pub fn unicode_method_name(method: UnicodeMethod) -> &'static str {
+  match method {
+    UnicodeMethod::CtrlShiftU => "ctrl_shift_u",
+  }
+}
+
+pub fn unicode_macro_steps(method: UnicodeMethod, ch: char) -> Vec<MacroStep> {
+  match method {
+    UnicodeMethod::CtrlShiftU => ctrl_shift_u_steps(ch),
+  }
+}
+
+fn ctrl_shift_u_steps(ch: char) -> Vec<MacroStep> {
+  let mut steps = vec![MacroStep::Tap(vec![LEFTCTRL, LEFTSHIFT, U])];
+
+  for digit in format!("{:x}", ch as u32).chars() {
+    steps.push(MacroStep::Tap(vec![hex_digit_key(digit)]));
+  }
+
+  steps.push(MacroStep::Tap(vec![SPACE]));
+  steps
+}
+
+fn hex_digit_key(digit: char) -> KeyCode {
+  match digit {
+    '0' => K0, '1' => K1, '2' => K2, '3' => K3, '4' => K4,
+    '5' => K5, '6' => K6, '7' => K7, '8' => K8, '9' => K9,
+    'a' => A, 'b' => B, 'c' => C, 'd' => D, 'e' => E, 'f' => F,
+    _ => panic!("Not a hex digit: {}", digit)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use MacroStep::Tap;
+
+  #[test]
+  fn test_ctrl_shift_u() {
+    assert_eq!(unicode_macro_steps(UnicodeMethod::CtrlShiftU, '→'), vec![
+      Tap(vec![LEFTCTRL, LEFTSHIFT, U]), Tap(vec![K2]), Tap(vec![K1]), Tap(vec![K9]), Tap(vec![K2]), Tap(vec![SPACE])
+    ]);
+    assert_eq!(unicode_macro_steps(UnicodeMethod::CtrlShiftU, '§'), vec![
+      Tap(vec![LEFTCTRL, LEFTSHIFT, U]), Tap(vec![A]), Tap(vec![K7]), Tap(vec![SPACE])
+    ]);
+  }
+}